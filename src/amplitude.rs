@@ -0,0 +1,483 @@
+use std::fmt::Debug;
+use num_complex::Complex;
+use num_traits::{Float, NumCast};
+use serde::{Serialize, Serializer, Deserialize, Deserializer, de::Error};
+
+/// Amplitude of a Hamiltonian term, either a constant (real or complex) or a real function of time.
+/// Tables of piecewise-linear amplitudes are validated by the deserializer.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+    PartialOrd
+)]
+#[serde(untagged, remote = "Self")]
+pub enum Amplitude<R> {
+    /// A constant amplitude
+    Constant(R),
+    /// An expression of time `t`, e.g. "0.5 * sin(2 * pi * t) + 1"
+    Expression(Expression),
+    /// Piecewise-linear interpolation of a table, the amplitude is constant outside of the table
+    Piecewise {
+        times: Vec<R>,
+        values: Vec<R>,
+    },
+    /// offset + amplitude * sin(frequency * t + phase)
+    Sinusoid {
+        amplitude: R,
+        frequency: R,
+        phase: Option<R>,
+        offset: Option<R>,
+    },
+    /// Linear ramp from `from` at the time `start` to `to` at the time `end`,
+    /// the amplitude is constant outside of the ramp
+    Ramp {
+        from: R,
+        to: R,
+        start: R,
+        end: R,
+    },
+    /// A complex constant given by its real and imaginary parts
+    Complex {
+        re: R,
+        im: R,
+    },
+    /// A complex constant abs * exp(i * phase), e.g. a Peierls phase of a hopping
+    Polar {
+        abs: R,
+        phase: R,
+    },
+}
+
+impl<'de, R: Float + Deserialize<'de>> Deserialize<'de> for Amplitude<R> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>
+    {
+        let amplitude = Amplitude::deserialize(deserializer)?;
+        amplitude.check().map_err(D::Error::custom)?;
+        Ok(amplitude)
+    }
+}
+
+impl<R: Serialize> Serialize for Amplitude<R> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    {
+        Amplitude::serialize(self, serializer)
+    }
+}
+
+impl<R: Float> Amplitude<R> {
+
+    /// Checks that a piecewise-linear amplitude is given by a non-empty table
+    /// of values at increasing times
+    fn check(&self) -> Result<(), String>
+    {
+        let Amplitude::Piecewise { times, values } = self else {
+            return Ok(());
+        };
+        if times.len() != values.len() {
+            return Err(format!(
+                "Piecewise amplitude has different numbers of times and values, {} and {}",
+                times.len(), values.len(),
+            ));
+        }
+        if times.is_empty() {
+            return Err("Piecewise amplitude table is empty".to_owned());
+        }
+        if times.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err("Times of a piecewise amplitude must increase".to_owned());
+        }
+        Ok(())
+    }
+
+    pub(super) fn is_time_dependent(&self) -> bool
+    {
+        !matches!(self, Amplitude::Constant(_) | Amplitude::Complex { .. } | Amplitude::Polar { .. })
+    }
+
+    /// Complex conjugate of the amplitude
+    pub(super) fn conj(&self) -> Self
+    {
+        match self {
+            Amplitude::Complex { re, im } => Amplitude::Complex { re: *re, im: -*im },
+            Amplitude::Polar { abs, phase } => Amplitude::Polar { abs: *abs, phase: -*phase },
+            real => real.clone(),
+        }
+    }
+
+    /// The amplitude with the opposite sign
+    pub(super) fn neg(&self) -> Self
+    {
+        match self {
+            Amplitude::Constant(value) => Amplitude::Constant(-*value),
+            Amplitude::Expression(expression) => Amplitude::Expression(
+                Expression::try_from(format!("-({})", expression.source)).expect("Negation of a valid expression"),
+            ),
+            Amplitude::Piecewise { times, values } => Amplitude::Piecewise {
+                times: times.clone(),
+                values: values.iter().map(|value| -*value).collect(),
+            },
+            Amplitude::Sinusoid { amplitude, frequency, phase, offset } => Amplitude::Sinusoid {
+                amplitude: -*amplitude,
+                frequency: *frequency,
+                phase: *phase,
+                offset: offset.map(|offset| -offset),
+            },
+            Amplitude::Ramp { from, to, start, end } => Amplitude::Ramp { from: -*from, to: -*to, start: *start, end: *end },
+            Amplitude::Complex { re, im } => Amplitude::Complex { re: -*re, im: -*im },
+            Amplitude::Polar { abs, phase } => Amplitude::Polar { abs: -*abs, phase: *phase },
+        }
+    }
+
+    pub(super) fn value(&self, time: R) -> Complex<R>
+    {
+        let value = match self {
+            Amplitude::Constant(value) => *value,
+            Amplitude::Expression(expression) => {
+                <R as NumCast>::from(expression.eval(time.to_f64().unwrap())).unwrap()
+            },
+            Amplitude::Piecewise { times, values } => {
+                // tables are validated by the deserializer
+                debug_assert_eq!(times.len(), values.len(), "Piecewise amplitude has different number of times and values");
+                let next = times.iter().position(|t| *t > time);
+                match next {
+                    None => *values.last().expect("Piecewise amplitude table is empty"),
+                    Some(0) => values[0],
+                    Some(i) => {
+                        let weight = (time - times[i - 1]) / (times[i] - times[i - 1]);
+                        values[i - 1] + weight * (values[i] - values[i - 1])
+                    },
+                }
+            },
+            Amplitude::Sinusoid { amplitude, frequency, phase, offset } => {
+                offset.unwrap_or(R::zero())
+                    + *amplitude * (*frequency * time + phase.unwrap_or(R::zero())).sin()
+            },
+            Amplitude::Ramp { from, to, start, end } => {
+                if time <= *start {
+                    *from
+                } else if time >= *end {
+                    *to
+                } else {
+                    *from + (*to - *from) * (time - *start) / (*end - *start)
+                }
+            },
+            Amplitude::Complex { re, im } => return Complex::new(*re, *im),
+            Amplitude::Polar { abs, phase } => return Complex::from_polar(*abs, *phase),
+        };
+        Complex::new(value, R::zero())
+    }
+}
+
+// ---------------------------------------------------------------------------------------
+
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    PartialOrd
+)]
+enum Expr {
+    Number(f64),
+    Time,
+    Neg(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+    Pow(Box<Expr>, Box<Expr>),
+    Func(Func, Box<Expr>),
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    PartialOrd
+)]
+enum Func {
+    Sin,
+    Cos,
+    Tan,
+    Exp,
+    Log,
+    Sqrt,
+    Abs,
+    Sinh,
+    Cosh,
+    Tanh,
+    Step,
+}
+
+impl Func {
+    fn eval(&self, x: f64) -> f64
+    {
+        match self {
+            Func::Sin => x.sin(),
+            Func::Cos => x.cos(),
+            Func::Tan => x.tan(),
+            Func::Exp => x.exp(),
+            Func::Log => x.ln(),
+            Func::Sqrt => x.sqrt(),
+            Func::Abs => x.abs(),
+            Func::Sinh => x.sinh(),
+            Func::Cosh => x.cosh(),
+            Func::Tanh => x.tanh(),
+            Func::Step => if x >= 0. { 1. } else { 0. },
+        }
+    }
+}
+
+impl Expr {
+    fn eval(&self, time: f64) -> f64
+    {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Time => time,
+            Expr::Neg(arg) => -arg.eval(time),
+            Expr::Add(lhs, rhs) => lhs.eval(time) + rhs.eval(time),
+            Expr::Sub(lhs, rhs) => lhs.eval(time) - rhs.eval(time),
+            Expr::Mul(lhs, rhs) => lhs.eval(time) * rhs.eval(time),
+            Expr::Div(lhs, rhs) => lhs.eval(time) / rhs.eval(time),
+            Expr::Pow(lhs, rhs) => lhs.eval(time).powf(rhs.eval(time)),
+            Expr::Func(func, arg) => func.eval(arg.eval(time)),
+        }
+    }
+}
+
+/// A recursive descent parser of arithmetic expressions of the time `t` with
+/// operators + - * / ^, parentheses, the constant `pi` and functions
+/// sin, cos, tan, exp, log, sqrt, abs, sinh, cosh, tanh and step (Heaviside).
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+
+    fn skip_whitespaces(&mut self)
+    {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn peek(&mut self) -> Option<char>
+    {
+        self.skip_whitespaces();
+        self.chars.peek().copied()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String>
+    {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.chars.next();
+                Ok(())
+            },
+            other => Err(format!("expected {:?}, found {:?}", expected, other)),
+        }
+    }
+
+    fn parse_sum(&mut self) -> Result<Expr, String>
+    {
+        let mut lhs = self.parse_product()?;
+        loop {
+            match self.peek() {
+                Some('+') => {
+                    self.chars.next();
+                    lhs = Expr::Add(Box::new(lhs), Box::new(self.parse_product()?));
+                },
+                Some('-') => {
+                    self.chars.next();
+                    lhs = Expr::Sub(Box::new(lhs), Box::new(self.parse_product()?));
+                },
+                _ => return Ok(lhs),
+            }
+        }
+    }
+
+    fn parse_product(&mut self) -> Result<Expr, String>
+    {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            match self.peek() {
+                Some('*') => {
+                    self.chars.next();
+                    lhs = Expr::Mul(Box::new(lhs), Box::new(self.parse_unary()?));
+                },
+                Some('/') => {
+                    self.chars.next();
+                    lhs = Expr::Div(Box::new(lhs), Box::new(self.parse_unary()?));
+                },
+                _ => return Ok(lhs),
+            }
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String>
+    {
+        if self.peek() == Some('-') {
+            self.chars.next();
+            return Ok(Expr::Neg(Box::new(self.parse_unary()?)));
+        }
+        let base = self.parse_primary()?;
+        if self.peek() == Some('^') {
+            self.chars.next();
+            return Ok(Expr::Pow(Box::new(base), Box::new(self.parse_unary()?)));
+        }
+        Ok(base)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String>
+    {
+        match self.peek() {
+            Some('(') => {
+                self.chars.next();
+                let expr = self.parse_sum()?;
+                self.expect(')')?;
+                Ok(expr)
+            },
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let mut number = String::new();
+                while let Some(c) = self.chars.next_if(|c| c.is_ascii_digit() || *c == '.' || *c == 'e' || *c == 'E') {
+                    number.push(c);
+                    if c == 'e' || c == 'E' {
+                        if let Some(sign) = self.chars.next_if(|c| *c == '-' || *c == '+') {
+                            number.push(sign);
+                        }
+                    }
+                }
+                number.parse::<f64>()
+                    .map(Expr::Number)
+                    .map_err(|_| format!("invalid number {:?}", number))
+            },
+            Some(c) if c.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(c) = self.chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                let func = match name.as_str() {
+                    "t" => return Ok(Expr::Time),
+                    "pi" => return Ok(Expr::Number(std::f64::consts::PI)),
+                    "sin" => Func::Sin,
+                    "cos" => Func::Cos,
+                    "tan" => Func::Tan,
+                    "exp" => Func::Exp,
+                    "log" => Func::Log,
+                    "sqrt" => Func::Sqrt,
+                    "abs" => Func::Abs,
+                    "sinh" => Func::Sinh,
+                    "cosh" => Func::Cosh,
+                    "tanh" => Func::Tanh,
+                    "step" => Func::Step,
+                    other => return Err(format!("unknown identifier {:?}", other)),
+                };
+                self.expect('(')?;
+                let arg = self.parse_sum()?;
+                self.expect(')')?;
+                Ok(Expr::Func(func, Box::new(arg)))
+            },
+            other => Err(format!("unexpected {:?}", other)),
+        }
+    }
+}
+
+/// A parsed expression of time, (de)serialized as a string.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+    PartialOrd
+)]
+#[serde(try_from = "String", into = "String")]
+pub struct Expression {
+    source: String,
+    expr: Expr,
+}
+
+impl Expression {
+    fn eval(&self, time: f64) -> f64
+    {
+        self.expr.eval(time)
+    }
+}
+
+impl TryFrom<String> for Expression {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error>
+    {
+        let mut parser = Parser { chars: source.chars().peekable() };
+        let expr = parser.parse_sum()
+            .map_err(|err| format!("Unable to parse expression {:?}: {}", source, err))?;
+        if let Some(c) = parser.peek() {
+            return Err(format!("Unable to parse expression {:?}: unexpected {:?}", source, c));
+        }
+        Ok(Expression { source, expr })
+    }
+}
+
+impl From<Expression> for String {
+    fn from(expression: Expression) -> Self
+    {
+        expression.source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expression()
+    {
+        let expression = Expression::try_from("0.5 * sin(2 * pi * t) + 2^-t - (1e-1 + t) / 4".to_owned()).unwrap();
+        for t in [0., 0.3, 1.7] {
+            let true_value = 0.5 * (2. * std::f64::consts::PI * t).sin() + 2f64.powf(-t) - (1e-1 + t) / 4.;
+            assert!((expression.eval(t) - true_value).abs() < 1e-12);
+        }
+        assert!(Expression::try_from("sin(t".to_owned()).is_err());
+        assert!(Expression::try_from("foo(t)".to_owned()).is_err());
+        assert!(Expression::try_from("t t".to_owned()).is_err());
+    }
+
+    #[test]
+    fn test_amplitude()
+    {
+        let piecewise = Amplitude::Piecewise { times: vec![0., 1., 3.], values: vec![1., 3., -1.] };
+        assert!((piecewise.value(-1.) - 1.).norm() < 1e-12);
+        assert!((piecewise.value(0.5) - 2.).norm() < 1e-12);
+        assert!((piecewise.value(2.) - 1.).norm() < 1e-12);
+        assert!((piecewise.value(5.) + 1.).norm() < 1e-12);
+        let ramp = Amplitude::Ramp { from: 1., to: 0., start: 1., end: 3. };
+        assert!((ramp.value(0.) - 1.).norm() < 1e-12);
+        assert!((ramp.value(2.) - 0.5).norm() < 1e-12);
+        assert!(ramp.value(4.).norm() < 1e-12);
+        let amplitude: Amplitude<f64> = serde_yaml::from_str("\"2 * t\"").unwrap();
+        assert!((amplitude.value(0.25) - 0.5).norm() < 1e-12);
+        let amplitude: Amplitude<f64> = serde_yaml::from_str("{amplitude: 2, frequency: 3}").unwrap();
+        assert!((amplitude.value(0.1) - 2. * 0.3f64.sin()).norm() < 1e-12);
+        assert!(!serde_yaml::from_str::<Amplitude<f64>>("-1").unwrap().is_time_dependent());
+        let amplitude: Amplitude<f64> = serde_yaml::from_str("{re: 1, im: -2}").unwrap();
+        assert!((amplitude.value(0.) - Complex::new(1., -2.)).norm() < 1e-12);
+        assert!((amplitude.conj().value(0.) - Complex::new(1., 2.)).norm() < 1e-12);
+        assert!(!amplitude.is_time_dependent());
+        let amplitude: Amplitude<f64> = serde_yaml::from_str("{abs: 2, phase: 0.5}").unwrap();
+        assert!((amplitude.value(0.) - Complex::new(2. * 0.5f64.cos(), 2. * 0.5f64.sin())).norm() < 1e-12);
+        assert!((amplitude.conj().value(0.) - amplitude.value(0.).conj()).norm() < 1e-12);
+        assert!((amplitude.neg().value(0.) + amplitude.value(0.)).norm() < 1e-12);
+        let amplitude: Amplitude<f64> = serde_yaml::from_str("\"1 - t\"").unwrap();
+        assert!((amplitude.neg().value(0.25) + 0.75).norm() < 1e-12);
+        assert!((ramp.neg().value(2.) + 0.5).norm() < 1e-12);
+        let amplitude: Amplitude<f64> = serde_yaml::from_str("{times: [0, 1], values: [1, 2]}").unwrap();
+        assert_eq!(amplitude, Amplitude::Piecewise { times: vec![0., 1.], values: vec![1., 2.] });
+        assert_eq!(serde_yaml::to_string(&amplitude).unwrap(), "times:\n- 0.0\n- 1.0\nvalues:\n- 1.0\n- 2.0\n");
+        let error = serde_yaml::from_str::<Amplitude<f64>>("{times: [0, 1], values: [1]}").unwrap_err();
+        assert!(error.to_string().contains("different numbers of times and values"));
+        assert!(serde_yaml::from_str::<Amplitude<f64>>("{times: [], values: []}").is_err());
+        assert!(serde_yaml::from_str::<Amplitude<f64>>("{times: [0, 2, 1], values: [1, 2, 3]}").is_err());
+        assert!(serde_yaml::from_str::<Amplitude<f64>>("{times: [0, 0], values: [1, 2]}").is_err());
+    }
+}
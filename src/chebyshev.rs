@@ -4,25 +4,6 @@ use num_traits::One;
 /*use crate::tasks::TermAndAmpl;
 use crate::subroutines::apply_term;*/
 
-static BESSEL_COEFFS: [Complex64; 16] = [
-    Complex64::new(1.2660658777520083355982446252147175376076703113549622068081353312, 0.),
-    Complex64::new(0., -0.56515910399248502720769602760986330732889962162109200948029448),
-    Complex64::new(-0.135747669767038281182852569994990922949871068112778187847546352, 0.),
-    Complex64::new(0., 0.022168424924331902476285747629899615529415349169979258090109080),
-    Complex64::new(0.0027371202210468663251380842155932297733789730929026393068918695, 0.),
-    Complex64::new(0., -0.00027146315595697187518107390515377734238356442675814363497412),
-    Complex64::new(-0.000022488661477147573327345164055456349543328825321202957150624, 0.),
-    Complex64::new(0., 1.5992182312009952529319364883011478636185229037081491666241e-6),
-    Complex64::new(9.9606240333639786298053219240279452669504669288868817881985e-8, 0.),
-    Complex64::new(0., -5.51838586275867216308498045667662090644819508624808051273e-9),
-    Complex64::new(-2.75294803983687362523571020100276353437157736403368652675e-10, 0.),
-    Complex64::new(0., 1.248978308492491261356005467109383770504035818070745922593e-11),
-    Complex64::new(5.1957611533928502524981733621192392626985642780454970518752e-13, 0.),
-    Complex64::new(0., -1.9956316782072007564438602007663474563803913398266301430e-14),
-    Complex64::new(-7.11879005412828574413684012673587610954679449625867991552e-16, 0.),
-    Complex64::new(0., 2.370463051280748085544965280302145707288880874199766713661e-17),
-];
-
 pub trait FromComplex64 {
     fn new(val: Complex64) -> Self;
 }
@@ -39,18 +20,18 @@ impl FromComplex64 for Complex32 {
     }
 }
 
-enum Sign {
+pub(super) enum Sign {
     Pos,
     Neg,
 }
 
-struct Chebyshev<'a, T1>
+pub(super) struct Chebyshev<'a, T1>
 {
-    val: &'a mut T1,
-    sign: Sign,
+    pub(super) val: &'a mut T1,
+    pub(super) sign: Sign,
 }
 
-fn get_next_negative_chebyshev<T1, T2: ComplexFloat + FromComplex64>(
+pub(super) fn get_next_negative_chebyshev<T1, T2: ComplexFloat + FromComplex64>(
     prev_chebyshev: &mut Chebyshev<T1>,
     curr_chebyshev: &Chebyshev<T1>,
     update_fn: impl Fn(&mut T1, &T1, T2),
@@ -84,43 +65,227 @@ fn get_next_negative_chebyshev<T1, T2: ComplexFloat + FromComplex64>(
     }
 }
 
-pub fn cheb_exp<T1, T2: ComplexFloat + FromComplex64>(
+/// Bessel functions of the first kind J_0(x), ..., J_{order-1}(x) for a real non-negative
+/// argument computed by the Miller backward recurrence normalized via J_0(x) + 2 sum_k J_{2k}(x) = 1.
+pub fn bessel_j(x: f64, order: usize) -> Vec<f64>
+{
+    if x == 0. {
+        let mut values = vec![0f64; order];
+        if let Some(value) = values.first_mut() {
+            *value = 1.;
+        }
+        return values;
+    }
+    let start = order.max(x.ceil() as usize) + (10. * x.cbrt()).ceil() as usize + 20;
+    let mut values = vec![0f64; start + 2];
+    values[start] = 1e-300;
+    for k in (1..=start).rev() {
+        values[k - 1] = 2. * (k as f64) / x * values[k] - values[k + 1];
+        if values[k - 1].abs() > 1e250 {
+            for v in &mut values[(k - 1)..] {
+                *v *= 1e-250;
+            }
+        }
+    }
+    let norm = values[0] + 2. * values.iter().skip(2).step_by(2).sum::<f64>();
+    values.truncate(order);
+    values.iter_mut().for_each(|v| *v /= norm);
+    values
+}
+
+/// Modified Bessel functions of the first kind I_0(x), ..., I_{order-1}(x) for a real
+/// non-negative argument computed by the Miller backward recurrence normalized
+/// via I_0(x) + 2 sum_k I_k(x) = exp(x).
+pub fn modified_bessel_i(x: f64, order: usize) -> Vec<f64>
+{
+    if x == 0. {
+        let mut values = vec![0f64; order];
+        if let Some(value) = values.first_mut() {
+            *value = 1.;
+        }
+        return values;
+    }
+    let start = order + 20 + (2. * x).ceil() as usize;
+    let mut values = vec![0f64; start + 2];
+    values[start] = 1e-300;
+    for k in (1..=start).rev() {
+        values[k - 1] = values[k + 1] + 2. * (k as f64) / x * values[k];
+        if values[k - 1] > 1e250 {
+            for v in &mut values[(k - 1)..] {
+                *v *= 1e-250;
+            }
+        }
+    }
+    let norm = values[0] + 2. * values[1..].iter().sum::<f64>();
+    let scale = x.exp() / norm;
+    values.truncate(order);
+    values.iter_mut().for_each(|v| *v *= scale);
+    values
+}
+
+/// Computes exp += sum_k coeffs[k] T_k(A) state, where T_k is the k-th Chebyshev polynomial,
+/// A is an operator applied via update_fn(dst, src, delta): dst += delta * A * src.
+/// Note, that state and aux are used as buffers of the recurrence and are overwritten.
+fn cheb_series<T1, T2: ComplexFloat + FromComplex64>(
     exp: &mut T1,
     state: &mut T1,
     aux: &mut T1,
     update_fn: impl Fn(&mut T1, &T1, T2),
     add: impl Fn(&mut T1, &T1, T2),
-    order: usize,
+    coeffs: &[Complex64],
 )
 {
-    let imag = Complex64::new(0., 1.);
-    let mut imag_pow = imag;
-    let two = Complex64::new(2., 0.);
-    add(exp, state, T2::new(BESSEL_COEFFS[0]));
+    add(exp, state, T2::new(coeffs[0]));
     update_fn(aux, state, T2::new(Complex64::one()));
-    add(exp, aux, T2::new(two * imag_pow * BESSEL_COEFFS[1]));
+    add(exp, aux, T2::new(coeffs[1]));
     let mut prev_cheb = Chebyshev { val: state, sign: Sign::Pos };
     let mut curr_cheb = Chebyshev { val: aux, sign: Sign::Pos };
-    for bessel in &BESSEL_COEFFS[2..order]
+    for coeff in &coeffs[2..]
     {
-        imag_pow = imag_pow * imag;
         get_next_negative_chebyshev(&mut prev_cheb, &curr_cheb, &update_fn);
         std::mem::swap(&mut curr_cheb, &mut prev_cheb);
         let coeff = match curr_cheb.sign {
-            Sign::Pos => T2::new(two * imag_pow * *bessel),
-            Sign::Neg => T2::new(-two * imag_pow * *bessel),
+            Sign::Pos => T2::new(*coeff),
+            Sign::Neg => T2::new(-*coeff),
         };
         add(exp, curr_cheb.val, coeff);
     }
 }
 
+/// Computes exp += exp(A) state for an operator A whose norm does not exceed 1, the
+/// Chebyshev expansion exp(x) = I_0(1) + 2 sum_k I_k(1) T_k(x) is used.
+pub fn cheb_exp<T1, T2: ComplexFloat + FromComplex64>(
+    exp: &mut T1,
+    state: &mut T1,
+    aux: &mut T1,
+    update_fn: impl Fn(&mut T1, &T1, T2),
+    add: impl Fn(&mut T1, &T1, T2),
+    order: usize,
+)
+{
+    let coeffs = modified_bessel_i(1., order).into_iter().enumerate().map(|(k, bessel)| {
+        if k == 0 { Complex64::new(bessel, 0.) } else { Complex64::new(2. * bessel, 0.) }
+    }).collect::<Vec<_>>();
+    cheb_series(exp, state, aux, update_fn, add, &coeffs);
+}
+
+/// Computes exp += exp(i tau A) state for a Hermitian operator A whose spectrum lies in [-1, 1],
+/// the Jacobi–Anger expansion exp(i tau x) = J_0(tau) + 2 sum_k i^k J_k(tau) T_k(x) is used.
+pub fn cheb_exp_unitary<T1, T2: ComplexFloat + FromComplex64>(
+    exp: &mut T1,
+    state: &mut T1,
+    aux: &mut T1,
+    update_fn: impl Fn(&mut T1, &T1, T2),
+    add: impl Fn(&mut T1, &T1, T2),
+    tau: f64,
+    order: usize,
+)
+{
+    let imag = Complex64::new(0., 1.);
+    let mut imag_pow = Complex64::one();
+    let coeffs = bessel_j(tau, order).into_iter().enumerate().map(|(k, bessel)| {
+        let coeff = if k == 0 { imag_pow * bessel } else { 2. * imag_pow * bessel };
+        imag_pow *= imag;
+        coeff
+    }).collect::<Vec<_>>();
+    cheb_series(exp, state, aux, update_fn, add, &coeffs);
+}
+
+/// Smallest number of terms of a Chebyshev series with coefficients 2 * |bessel[k]| (k > 0) such
+/// that the truncated tail does not exceed `tolerance`, T_k is bounded by `growth^k` on the domain
+/// of the series (by 1 on [-1, 1] and by (1 + sqrt(2))^k in the unit disk).
+fn truncation_order(bessel: &[f64], growth: f64, tolerance: f64) -> usize
+{
+    let mut tail = 0.;
+    for (k, value) in bessel.iter().enumerate().rev() {
+        tail += 2. * value.abs() * growth.powi(k as i32);
+        if tail > tolerance {
+            return (k + 1).max(2);
+        }
+    }
+    2
+}
+
+/// Order of the expansion in `cheb_exp_unitary` sufficient to reach `tolerance`.
+pub fn unitary_order(tau: f64, tolerance: f64) -> usize
+{
+    let max_order = tau.ceil() as usize + (10. * tau.cbrt()).ceil() as usize + 40;
+    truncation_order(&bessel_j(tau, max_order), 1., tolerance)
+}
+
+/// Order of the expansion in `cheb_exp` sufficient to reach `tolerance`. The operator is not
+/// Hermitian in general, thus T_k is bounded by max_{|z| <= 1} |T_k(z)| = |T_k(i)| <= (1 + sqrt(2))^k
+/// (by the von Neumann inequality for an operator of norm at most 1).
+pub fn exp_order(tolerance: f64) -> usize
+{
+    truncation_order(&modified_bessel_i(1., 40), 1. + std::f64::consts::SQRT_2, tolerance)
+}
+
+/// Order of the expansion in `cheb_exp_real` sufficient to reach `tolerance`
+/// relative to the largest value exp(tau) of the exponent.
+pub fn real_order(tau: f64, tolerance: f64) -> usize
+{
+    let max_order = tau.ceil() as usize + (10. * tau.cbrt()).ceil() as usize + 40;
+    truncation_order(&modified_bessel_i(tau, max_order), 1., tolerance * tau.exp())
+}
+
+/// Computes exp += exp(-tau A) state for an operator A whose spectrum lies in [-1, 1], the
+/// Chebyshev expansion exp(-tau x) = I_0(tau) + 2 sum_k (-1)^k I_k(tau) T_k(x) is used.
+pub fn cheb_exp_real<T1, T2: ComplexFloat + FromComplex64>(
+    exp: &mut T1,
+    state: &mut T1,
+    aux: &mut T1,
+    update_fn: impl Fn(&mut T1, &T1, T2),
+    add: impl Fn(&mut T1, &T1, T2),
+    tau: f64,
+    order: usize,
+)
+{
+    let coeffs = modified_bessel_i(tau, order).into_iter().enumerate().map(|(k, bessel)| {
+        let sign = if k % 2 == 0 { 1. } else { -1. };
+        if k == 0 { Complex64::new(bessel, 0.) } else { Complex64::new(2. * sign * bessel, 0.) }
+    }).collect::<Vec<_>>();
+    cheb_series(exp, state, aux, update_fn, add, &coeffs);
+}
+
 #[cfg(test)]
 mod tests {
     use std::fmt::Debug;
     use num_complex::{Complex32, Complex64, ComplexFloat};
     use super::FromComplex64;
 
-    use super::cheb_exp;
+    use super::{
+        cheb_exp,
+        cheb_exp_real,
+        cheb_exp_unitary,
+        modified_bessel_i,
+        bessel_j,
+        unitary_order,
+        exp_order,
+        real_order,
+    };
+
+    // I_k(1) (-i)^k
+    #[allow(clippy::excessive_precision)]
+    static BESSEL_COEFFS: [Complex64; 16] = [
+        Complex64::new(1.2660658777520083355982446252147175376076703113549622068081353312, 0.),
+        Complex64::new(0., -0.56515910399248502720769602760986330732889962162109200948029448),
+        Complex64::new(-0.135747669767038281182852569994990922949871068112778187847546352, 0.),
+        Complex64::new(0., 0.022168424924331902476285747629899615529415349169979258090109080),
+        Complex64::new(0.0027371202210468663251380842155932297733789730929026393068918695, 0.),
+        Complex64::new(0., -0.00027146315595697187518107390515377734238356442675814363497412),
+        Complex64::new(-0.000022488661477147573327345164055456349543328825321202957150624, 0.),
+        Complex64::new(0., 1.5992182312009952529319364883011478636185229037081491666241e-6),
+        Complex64::new(9.9606240333639786298053219240279452669504669288868817881985e-8, 0.),
+        Complex64::new(0., -5.51838586275867216308498045667662090644819508624808051273e-9),
+        Complex64::new(-2.75294803983687362523571020100276353437157736403368652675e-10, 0.),
+        Complex64::new(0., 1.248978308492491261356005467109383770504035818070745922593e-11),
+        Complex64::new(5.1957611533928502524981733621192392626985642780454970518752e-13, 0.),
+        Complex64::new(0., -1.9956316782072007564438602007663474563803913398266301430e-14),
+        Complex64::new(-7.11879005412828574413684012673587610954679449625867991552e-16, 0.),
+        Complex64::new(0., 2.370463051280748085544965280302145707288880874199766713661e-17),
+    ];
+
 
     fn _test_cheb_exp<T: ComplexFloat + FromComplex64 + Debug>(order: usize, acc: T::Real)
     {
@@ -142,4 +307,139 @@ mod tests {
         _test_cheb_exp::<Complex64>(14, 1e-10);
         _test_cheb_exp::<Complex32>(7, 1e-4);
     }
-}
\ No newline at end of file
+
+    fn _test_cheb_exp_real<T: ComplexFloat + FromComplex64 + Debug>(order: usize, acc: T::Real)
+    {
+        let x = T::new(Complex64::new(-0.6, 0.));
+        let mut exp = T::zero();
+        let mut aux = T::zero();
+        let mut state = T::one();
+        cheb_exp_real::<T, T>(&mut exp, &mut state, &mut aux,
+            |dst, src, coeff| *dst = *dst + coeff * *src * x,
+            |dst, src, coeff| *dst = *dst + coeff * *src,
+            1.,
+            order,
+        );
+        assert!((exp - (-x).exp()).abs() < acc);
+        // a large argument, the error is relative to exp(tau)
+        let tolerance = 1e-12;
+        let mut exp = T::zero();
+        let mut aux = T::zero();
+        let mut state = T::one();
+        cheb_exp_real::<T, T>(&mut exp, &mut state, &mut aux,
+            |dst, src, coeff| *dst = *dst + coeff * *src * x,
+            |dst, src, coeff| *dst = *dst + coeff * *src,
+            20.,
+            real_order(20., tolerance),
+        );
+        assert!(((exp - (-x * T::from(20.).unwrap()).exp()) / T::from(20f64.exp()).unwrap()).abs() < acc);
+    }
+
+    #[test]
+    fn test_cheb_exp_real()
+    {
+        _test_cheb_exp_real::<Complex64>(14, 1e-10);
+        _test_cheb_exp_real::<Complex32>(7, 1e-4);
+    }
+
+    #[test]
+    fn test_modified_bessel_i()
+    {
+        let bessel = modified_bessel_i(1., 16);
+        for (lhs, rhs) in bessel.into_iter().zip(BESSEL_COEFFS) {
+            assert!((lhs - rhs.abs()).abs() < 1e-14 * rhs.abs().max(1e-3));
+        }
+    }
+
+    #[test]
+    fn test_bessel_j()
+    {
+        // reference values of J_k(x)
+        let bessel = bessel_j(1., 4);
+        let exact = [0.7651976865579666, 0.44005058574493355, 0.11490348493190049, 0.019563353982668407];
+        for (lhs, rhs) in bessel.iter().zip(exact) {
+            assert!((lhs - rhs).abs() < 1e-14);
+        }
+        let bessel = bessel_j(30., 41);
+        assert!((bessel[0] + 0.0863679835810403).abs() < 1e-13);
+        assert!((bessel[40] - 3.612023608896723e-4).abs() < 1e-15);
+    }
+
+    fn _test_cheb_exp_unitary<T: ComplexFloat + FromComplex64 + Debug>(tau: f64, tolerance: f64, acc: T::Real)
+    {
+        let order = unitary_order(tau, tolerance);
+        for x in [-1., -0.3, 0.5, 1.] {
+            let mut exp = T::zero();
+            let mut aux = T::zero();
+            let mut state = T::one();
+            cheb_exp_unitary::<T, T>(&mut exp, &mut state, &mut aux,
+                |dst, src, coeff| *dst = *dst + coeff * *src * T::new(Complex64::new(x, 0.)),
+                |dst, src, coeff| *dst = *dst + coeff * *src,
+                tau,
+                order,
+            );
+            assert!((exp - T::new(Complex64::new(0., tau * x).exp())).abs() < acc);
+        }
+    }
+
+    #[test]
+    fn test_cheb_exp_unitary()
+    {
+        _test_cheb_exp_unitary::<Complex64>(0.7, 1e-12, 1e-11);
+        _test_cheb_exp_unitary::<Complex64>(50., 1e-10, 1e-9);
+        _test_cheb_exp_unitary::<Complex32>(3., 1e-5, 1e-4);
+        assert!(unitary_order(50., 1e-10) > 50);
+    }
+
+    #[test]
+    fn test_exp_order()
+    {
+        // the worst case of the unit disk is z = +-i, where |T_k| grows as (1 + sqrt(2))^k
+        let points = [
+            Complex64::new(0., 1.),
+            Complex64::new(0., -1.),
+            Complex64::new(0.6, 0.8),
+            Complex64::new(-1., 0.),
+            Complex64::new(-0.3, 0.4),
+        ];
+        for tolerance in [1e-4, 1e-6, 1e-10, 1e-13] {
+            let order = exp_order(tolerance);
+            for x in points {
+                let mut exp = Complex64::new(0., 0.);
+                let mut aux = Complex64::new(0., 0.);
+                let mut state = Complex64::new(1., 0.);
+                cheb_exp::<Complex64, Complex64>(&mut exp, &mut state, &mut aux,
+                    |dst, src, coeff| *dst += coeff * *src * x,
+                    |dst, src, coeff| *dst += coeff * *src,
+                    order,
+                );
+                assert!((exp - x.exp()).abs() < tolerance);
+            }
+        }
+        // a dissipative non-normal generator of norm below 1, exp(A) of the triangular
+        // A = [[a, b], [0, d]] is [[e^a, b (e^a - e^d) / (a - d)], [0, e^d]]
+        let (a, b, d) = (Complex64::new(-0.4, 0.3), Complex64::new(0.5, 0.), Complex64::new(-0.2, -0.5));
+        let expected = [(a.exp(), Complex64::new(0., 0.)), (b * (a.exp() - d.exp()) / (a - d), d.exp())];
+        for tolerance in [1e-6, 1e-10] {
+            let order = exp_order(tolerance);
+            for column in 0..2 {
+                let mut exp = vec![Complex64::new(0., 0.); 2];
+                let mut aux = vec![Complex64::new(0., 0.); 2];
+                let mut state = vec![Complex64::new(0., 0.); 2];
+                state[column] = Complex64::new(1., 0.);
+                cheb_exp::<Vec<Complex64>, Complex64>(&mut exp, &mut state, &mut aux,
+                    |dst, src, coeff| {
+                        dst[0] += coeff * (a * src[0] + b * src[1]);
+                        dst[1] += coeff * d * src[1];
+                    },
+                    |dst, src, coeff| dst.iter_mut().zip(src).for_each(|(dst, src)| *dst += coeff * src),
+                    order,
+                );
+                let (top, bottom) = expected[column];
+                let error = ((exp[0] - top).norm_sqr() + (exp[1] - bottom).norm_sqr()).sqrt();
+                assert!(error < tolerance);
+            }
+        }
+        assert!(exp_order(1e-14) > 14);
+    }
+}
@@ -0,0 +1,135 @@
+use std::fs::{read, rename, write};
+use serde::{Serialize, Deserialize, Deserializer, de::Error};
+
+/// Periodic saving of a run to the checkpoint file `path` every `every` time steps
+/// (and after the last one), see `Checkpoint`. In a sweep, `path` is suffixed by the index
+/// of a point, e.g. `checkpoint_3.pkl` for `checkpoint.pkl`.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+    PartialOrd
+)]
+pub struct CheckpointPolicy {
+    pub(super) path: String,
+    pub(super) every: usize,
+}
+
+impl CheckpointPolicy {
+
+    /// Checks that checkpoints are made every positive number of time steps
+    fn check(&self) -> Result<(), String>
+    {
+        if self.every == 0 {
+            return Err(format!("Checkpoints to {} must be made every positive number of time steps", self.path));
+        }
+        Ok(())
+    }
+}
+
+/// Deserializes an optional checkpoint policy and rejects invalid ones
+pub(super) fn deserialize_checkpoint_policy<'de, D>(deserializer: D) -> Result<Option<CheckpointPolicy>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let policy = Option::<CheckpointPolicy>::deserialize(deserializer)?;
+    if let Some(policy) = &policy {
+        policy.check().map_err(D::Error::custom)?;
+    }
+    Ok(policy)
+}
+
+/// A checkpoint of a run in the pickle format: the state vector of the whole space after `step`
+/// time steps, observables collected so far and the hash of the task config the run was made for.
+/// A run may be resumed from a checkpoint, and its state may be used as `init_state` of any task.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+pub struct Checkpoint<T, O> {
+    pub(super) config_hash: String,
+    pub(super) step: usize,
+    pub(super) state: Vec<T>,
+    pub(super) observables: O,
+}
+
+/// The state vector of a checkpoint, other fields are skipped
+#[derive(Deserialize)]
+struct CheckpointState<T> {
+    state: Vec<T>,
+}
+
+impl<T: Serialize, O: Serialize> Checkpoint<T, O> {
+
+    /// Writes the checkpoint to a temporary file which then replaces the previous
+    /// checkpoint, thus the file is never left half-written
+    pub(super) fn save(&self, path: &str) -> Result<(), String>
+    {
+        let pickled = serde_pickle::to_vec(self, Default::default()).map_err(|err| err.to_string())?;
+        let tmp_path = format!("{}.tmp", path);
+        write(&tmp_path, pickled).map_err(|err| format!("Unable to write the checkpoint {}: {}", tmp_path, err))?;
+        rename(&tmp_path, path).map_err(|err| format!("Unable to write the checkpoint {}: {}", path, err))
+    }
+}
+
+impl<T, O> Checkpoint<T, O>
+where
+    T: for<'a > Deserialize<'a>,
+    O: for<'a > Deserialize<'a>,
+{
+    pub(super) fn load(path: &str) -> Result<Self, String>
+    {
+        let pickled = read(path).map_err(|err| format!("Unable to read the checkpoint {}: {}", path, err))?;
+        serde_pickle::from_slice(&pickled, Default::default())
+            .map_err(|err| format!("Unable to recognize the checkpoint {}: {}", path, err))
+    }
+}
+
+/// Loads only the state vector of a checkpoint
+pub(super) fn load_state<T>(path: &str) -> Result<Vec<T>, String>
+where
+    T: for<'a > Deserialize<'a>,
+{
+    let pickled = read(path).map_err(|err| format!("Unable to read the checkpoint {}: {}", path, err))?;
+    let checkpoint: CheckpointState<T> = serde_pickle::from_slice(&pickled, Default::default())
+        .map_err(|err| format!("Unable to recognize the checkpoint {}: {}", path, err))?;
+    Ok(checkpoint.state)
+}
+
+/// FNV-1a hash of a config in hex, unlike the hashers of std it is stable across builds
+pub(super) fn config_hash(config: &impl Serialize) -> String
+{
+    let config = serde_yaml::to_string(config).expect("Unable to serialize a config");
+    let hash = config.bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_complex::Complex64;
+
+    #[test]
+    fn test_checkpoint()
+    {
+        let path = std::env::temp_dir().join(format!("checkpoint_test_{}.pkl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let checkpoint = Checkpoint {
+            config_hash: config_hash(&"config"),
+            step: 3,
+            state: vec![Complex64::new(0.6, 0.), Complex64::new(0., 0.8)],
+            observables: vec![vec![1., 2.], vec![3.]],
+        };
+        checkpoint.save(path).unwrap();
+        assert_eq!(Checkpoint::load(path), Ok(checkpoint.clone()));
+        assert_eq!(load_state::<Complex64>(path), Ok(checkpoint.state));
+        assert_ne!(config_hash(&"config"), config_hash(&"other config"));
+        std::fs::remove_file(path).unwrap();
+        assert!(load_state::<Complex64>(path).is_err());
+    }
+}
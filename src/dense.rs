@@ -0,0 +1,266 @@
+use num_complex::Complex;
+use num_traits::Float;
+use serde::{Serialize, Deserialize};
+use serde_yaml::{Value as YamlValue, Mapping};
+
+/// An element of a dense operator, either a real number or a complex number
+/// given by its real and imaginary parts.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    PartialOrd
+)]
+#[serde(untagged)]
+pub enum Element<R> {
+    Real(R),
+    Complex {
+        re: R,
+        im: R,
+    },
+}
+
+impl<R: Float> Element<R> {
+
+    pub(super) fn value(&self) -> Complex<R>
+    {
+        match self {
+            Element::Real(value) => Complex::new(*value, R::zero()),
+            Element::Complex { re, im } => Complex::new(*re, *im),
+        }
+    }
+}
+
+/// A dense operator acting on one or several modes given by its matrix in the basis
+/// of occupation numbers of these modes (the first mode is the least significant digit
+/// of an index, as in reduced density matrices). Operators are defined by names in
+/// the `dense_operators` section of a config and terms refer to them by `dense: name`.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+    PartialOrd
+)]
+pub struct DenseOperator<R> {
+    #[serde(default)]
+    pub(super) name: String,
+    pub(super) matrix: Vec<Vec<Element<R>>>,
+}
+
+impl<R: Float> DenseOperator<R> {
+
+    fn from_values(name: String, values: Vec<Vec<Complex<R>>>) -> Self
+    {
+        let matrix = values.into_iter().map(|row| {
+            row.into_iter().map(|value| Element::Complex { re: value.re, im: value.im }).collect()
+        }).collect();
+        DenseOperator { name, matrix }
+    }
+
+    fn values(&self) -> Vec<Vec<Complex<R>>>
+    {
+        self.matrix.iter().map(|row| row.iter().map(|elem| elem.value()).collect()).collect()
+    }
+
+    /// Dimension of the space of target modes
+    pub(super) fn size(&self) -> usize
+    {
+        self.matrix.len()
+    }
+
+    /// Checks that the matrix is square and not empty
+    pub(super) fn check(&self) -> Result<(), String>
+    {
+        if self.matrix.is_empty() || self.matrix.iter().any(|row| row.len() != self.size()) {
+            return Err(format!("Matrix of the dense operator \"{}\" must be square and not empty", self.name));
+        }
+        Ok(())
+    }
+
+    pub(super) fn transposed(&self) -> Self
+    {
+        let values = self.values();
+        let transposed = (0..self.size()).map(|j| values.iter().map(|row| row[j]).collect()).collect();
+        DenseOperator::from_values(self.name.clone(), transposed)
+    }
+
+    /// Complex conjugate of the operator
+    pub(super) fn conj(&self) -> Self
+    {
+        let conjugate = self.values().into_iter().map(|row| row.into_iter().map(|x| x.conj()).collect()).collect();
+        DenseOperator::from_values(self.name.clone(), conjugate)
+    }
+
+    /// op^dagger op
+    pub(super) fn hermitian_square(&self) -> Self
+    {
+        let values = self.values();
+        let size = self.size();
+        let square = (0..size).map(|j| {
+            (0..size).map(|k| {
+                values.iter().fold(Complex::new(R::zero(), R::zero()), |acc, row| acc + row[j].conj() * row[k])
+            }).collect()
+        }).collect();
+        DenseOperator::from_values(self.name.clone(), square)
+    }
+
+    /// Whether matrices of two operators coincide up to the rounding errors
+    pub(super) fn is_close(&self, other: &Self) -> bool
+    {
+        let tolerance = R::epsilon().sqrt();
+        self.size() == other.size() && self.values().into_iter().flatten().zip(other.values().into_iter().flatten())
+            .all(|(lhs, rhs)| (lhs - rhs).norm() <= tolerance * lhs.norm().max(R::one()))
+    }
+
+    /// Non-zero elements (k, op[j][k]) of each row j of the matrix
+    pub(super) fn rows(&self) -> Vec<Vec<(usize, Complex<R>)>>
+    {
+        self.values().into_iter().map(|row| {
+            row.into_iter().enumerate().filter(|(_, value)| *value != Complex::new(R::zero(), R::zero())).collect()
+        }).collect()
+    }
+
+    /// Upper bound of the operator norm, sqrt(||op||_1 ||op||_inf)
+    pub(super) fn norm_bound(&self) -> f64
+    {
+        let values = self.values();
+        let max_row_sum = values.iter()
+            .map(|row| row.iter().map(|x| x.norm().to_f64().unwrap()).sum::<f64>())
+            .fold(0., f64::max);
+        let max_column_sum = (0..self.size())
+            .map(|k| values.iter().map(|row| row[k].norm().to_f64().unwrap()).sum::<f64>())
+            .fold(0., f64::max);
+        (max_row_sum * max_column_sum).sqrt()
+    }
+
+    /// Whether all non-zero elements connect states of target modes with
+    /// equal total numbers of particles, `target_dims` are local dimensions of the target modes
+    pub(super) fn conserves_particle_number(&self, target_dims: &[usize]) -> bool
+    {
+        let particles_number = |mut index: usize| {
+            target_dims.iter().map(|dim| {
+                let occupation = index % dim;
+                index /= dim;
+                occupation
+            }).sum::<usize>()
+        };
+        self.rows().into_iter().enumerate().all(|(j, row)| {
+            row.into_iter().all(|(k, _)| particles_number(j) == particles_number(k))
+        })
+    }
+}
+
+/// Substitutes matrices of named dense operators defined in the `dense_operators` section
+/// of a task config into all the terms referring to them by `dense: name`,
+/// the section itself is removed from the config.
+pub(super) fn expand_dense_operators(config: &mut YamlValue) -> Result<(), String>
+{
+    let task = match config {
+        YamlValue::Tagged(tagged) => &mut tagged.value,
+        other => other,
+    };
+    let Some(task) = task.as_mapping_mut() else {
+        return Ok(());
+    };
+    let operators = match task.remove("dense_operators") {
+        Some(YamlValue::Mapping(operators)) => operators,
+        Some(_) => return Err("`dense_operators` must be a map from names to matrices".to_owned()),
+        None => Mapping::new(),
+    };
+    for (_, value) in task.iter_mut() {
+        substitute(value, &operators)?;
+    }
+    Ok(())
+}
+
+fn substitute(value: &mut YamlValue, operators: &Mapping) -> Result<(), String>
+{
+    match value {
+        YamlValue::Mapping(mapping) => {
+            if let Some(YamlValue::String(name)) = mapping.get("dense") {
+                let matrix = operators.get(name.as_str())
+                    .ok_or_else(|| format!("Dense operator \"{}\" is not defined in `dense_operators`", name))?;
+                let mut dense = Mapping::new();
+                dense.insert("name".into(), name.as_str().into());
+                dense.insert("matrix".into(), matrix.clone());
+                mapping.insert("dense".into(), YamlValue::Mapping(dense));
+            }
+            for (_, value) in mapping.iter_mut() {
+                substitute(value, operators)?;
+            }
+        },
+        YamlValue::Sequence(sequence) => {
+            for value in sequence.iter_mut() {
+                substitute(value, operators)?;
+            }
+        },
+        YamlValue::Tagged(tagged) => substitute(&mut tagged.value, operators)?,
+        _ => {},
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex64;
+    use crate::tasks::Task;
+    use super::*;
+
+    #[test]
+    fn test_dense_operator()
+    {
+        let mut config: YamlValue = serde_yaml::from_str(
+            "!Task {dense_operators: {proj: [[0, 1], [0, 0]]}, terms: [{pos: [0], dense: proj}, [{pos: [1], dense: proj}]]}"
+        ).unwrap();
+        expand_dense_operators(&mut config).unwrap();
+        let YamlValue::Tagged(task) = &config else { panic!("Unexpected config") };
+        assert!(task.value.get("dense_operators").is_none());
+        let dense: DenseOperator<f64> = serde_yaml::from_value(task.value["terms"][1][0]["dense"].clone()).unwrap();
+        assert_eq!(dense.name, "proj");
+        assert_eq!(dense.rows(), vec![vec![(1, Complex::new(1., 0.))], vec![]]);
+        assert_eq!(dense.transposed().rows(), vec![vec![], vec![(0, Complex::new(1., 0.))]]);
+        assert_eq!(dense.hermitian_square().rows(), vec![vec![], vec![(1, Complex::new(1., 0.))]]);
+        assert!(!dense.conserves_particle_number(&[2]));
+        let mut config: YamlValue = serde_yaml::from_str("{terms: [{pos: [0], dense: parity}]}").unwrap();
+        assert!(expand_dense_operators(&mut config).is_err());
+        // the swap of a particle between two qubit modes
+        let dense: DenseOperator<f64> = serde_yaml::from_str(
+            "{matrix: [[0, 0, 0, 0], [0, 0, {re: 0, im: 1}, 0], [0, {re: 0, im: -1}, 0, 0], [0, 0, 0, 0]]}"
+        ).unwrap();
+        assert!(dense.check().is_ok());
+        assert!(dense.conserves_particle_number(&[2, 2]));
+        assert!(dense.is_close(&dense.transposed().conj()));
+        assert!(!dense.is_close(&dense.transposed()));
+        assert!((dense.norm_bound() - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_dense_terms()
+    {
+        // dense operators referred to by names in Hamiltonian terms
+        let dense = |operators: &str, terms: &str| {
+            let config = format!(
+                "!GroundState {{qubits_per_mode: [1, 1], init_state: [1, 0], density_matrices: [], \
+                krylov_dim: 2, eigenvalues_number: 1, tolerance: 1e-6, max_restarts: 1, hamiltonian: {}, \
+                dense_operators: {{{}}}}}",
+                terms, operators,
+            );
+            Task::<Complex64>::from_value(serde_yaml::from_str(&config).unwrap())
+        };
+        let parity = "parity: [[1, 0], [0, -1]]";
+        let swap = "swap: [[0, 0, 0, 0], [0, 0, {re: 0, im: 1}, 0], [0, {re: 0, im: -1}, 0, 0], [0, 0, 0, 0]]";
+        let projector = "projector: [[0, 1], [0, 0]]";
+        assert!(dense(parity, "[{ampl: 1, pos: [0], dense: parity}]").is_ok());
+        assert!(dense(swap, "[{ampl: 0.5, pos: [0, 1], dense: swap}]").is_ok());
+        assert!(dense(projector, "[{ampl: {re: 1, im: 1}, pos: [1], dense: projector}]").is_err());
+        assert!(dense(projector, "[{ampl: {re: 1, im: 1}, pos: [1], dense: projector, hc: true}]").is_ok());
+        assert!(dense(projector, "[{ampl: 1, pos: [1], dense: parity}]").is_err());
+        assert!(dense(parity, "[{ampl: 1, pos: [0, 0], dense: parity}]").is_err());
+        assert!(dense(parity, "[{ampl: 1, pos: [0], ops: [N1], dense: parity}]").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use std::f64::consts::PI;
+use num_traits::ToPrimitive;
+
+use crate::chebyshev::{Chebyshev, Sign, get_next_negative_chebyshev, FromComplex64};
+use crate::subroutines_utils::Value;
+use crate::subroutines::{init_zero_sized, dot};
+
+/// Fraction of [-1, 1] kept free at the edges when a Hamiltonian is mapped
+/// onto the domain of Chebyshev polynomials.
+pub(super) const KPM_EPSILON: f64 = 0.01;
+
+fn sign_value(sign: &Sign) -> f64
+{
+    match sign {
+        Sign::Pos => 1.,
+        Sign::Neg => -1.,
+    }
+}
+
+/// Chebyshev moments mu_n = <state|T_n(A)|state>, n = 0..moments_number, of a Hermitian A
+/// whose spectrum lies in [-1, 1], A is applied via update_fn(dst, src, delta): dst += delta * A * src.
+/// The recurrence runs only up to n = moments_number / 2, the remaining moments are
+/// recovered from mu_{2n} = 2 <T_n|T_n> - mu_0 and mu_{2n+1} = 2 <T_{n+1}|T_n> - mu_1.
+pub(super) fn chebyshev_moments<T: Value + FromComplex64>(
+    state: &[T],
+    update_fn: impl Fn(&mut Vec<T>, &Vec<T>, T),
+    moments_number: usize,
+) -> Vec<f64>
+{
+    assert!(moments_number > 1, "At least two Chebyshev moments are required");
+    let mut moments = vec![0f64; moments_number];
+    let mut prev = state.to_owned();
+    let mut curr = init_zero_sized(state.len());
+    update_fn(&mut curr, &prev, T::one());
+    let mu0 = dot(state, state).re().to_f64().unwrap();
+    let mu1 = dot(state, &curr).re().to_f64().unwrap();
+    moments[0] = mu0;
+    moments[1] = mu1;
+    let mut prev_cheb = Chebyshev { val: &mut prev, sign: Sign::Pos };
+    let mut curr_cheb = Chebyshev { val: &mut curr, sign: Sign::Pos };
+    let mut n = 1;
+    loop {
+        if 2 * n < moments_number {
+            let overlap = dot(curr_cheb.val, curr_cheb.val).re().to_f64().unwrap();
+            moments[2 * n] = 2. * overlap - mu0;
+        }
+        if 2 * n + 1 >= moments_number {
+            break;
+        }
+        get_next_negative_chebyshev(&mut prev_cheb, &curr_cheb, &update_fn);
+        std::mem::swap(&mut curr_cheb, &mut prev_cheb);
+        n += 1;
+        let sign = sign_value(&curr_cheb.sign) * sign_value(&prev_cheb.sign);
+        let overlap = dot(curr_cheb.val, prev_cheb.val).re().to_f64().unwrap();
+        moments[2 * n - 1] = 2. * sign * overlap - mu1;
+    }
+    moments
+}
+
+/// Jackson kernel g_n, n = 0..moments_number, damping the Gibbs oscillations
+/// of a truncated Chebyshev series.
+pub(super) fn jackson_kernel(moments_number: usize) -> Vec<f64>
+{
+    let size = (moments_number + 1) as f64;
+    (0..moments_number).map(|n| {
+        let phase = PI * (n as f64) / size;
+        ((size - n as f64) * phase.cos() + phase.sin() / (PI / size).tan()) / size
+    }).collect()
+}
+
+/// Chebyshev nodes x_k = -cos(pi (k + 1/2) / points_number) in the ascending order.
+pub(super) fn chebyshev_nodes(points_number: usize) -> Vec<f64>
+{
+    (0..points_number)
+        .map(|k| -(PI * (k as f64 + 0.5) / points_number as f64).cos())
+        .collect()
+}
+
+/// Spectral density (g_0 mu_0 + 2 sum_n g_n mu_n T_n(x)) / (pi sqrt(1 - x^2)) restored
+/// from Chebyshev moments with the Jackson damping at points x from (-1, 1).
+pub(super) fn spectral_density(moments: &[f64], points: &[f64]) -> Vec<f64>
+{
+    let kernel = jackson_kernel(moments.len());
+    points.iter().map(|x| {
+        let angle = x.acos();
+        let series = moments.iter().zip(&kernel).enumerate().map(|(n, (mu, g))| {
+            let weight = if n == 0 { 1. } else { 2. };
+            weight * g * mu * (n as f64 * angle).cos()
+        }).sum::<f64>();
+        series / (PI * (1. - x * x).sqrt())
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex64;
+    use super::*;
+
+    #[test]
+    fn test_kpm()
+    {
+        // A = diag(x_0, ..., x_{size-1}), <state|T_n(A)|state> = sum_i |state_i|^2 T_n(x_i)
+        let size = 50;
+        let diag: Vec<f64> = (0..size).map(|i| 0.9 * (2. * i as f64 / (size - 1) as f64 - 1.)).collect();
+        let state: Vec<Complex64> = (0..size).map(|i| Complex64::new(1., 0.3 * i as f64)).collect();
+        let moments = chebyshev_moments(
+            &state,
+            |dst, src, delta| {
+                for ((d, s), x) in dst.iter_mut().zip(src).zip(&diag) {
+                    *d += delta * *x * *s;
+                }
+            },
+            31,
+        );
+        for (n, mu) in moments.iter().enumerate() {
+            let exact = state.iter().zip(&diag)
+                .map(|(s, x)| s.norm_sqr() * (n as f64 * x.acos()).cos())
+                .sum::<f64>();
+            assert!((mu - exact).abs() < 1e-10 * exact.abs().max(1.), "n = {}: {} vs {}", n, mu, exact);
+        }
+        // the Jackson-damped density stays normalized
+        let points = chebyshev_nodes(512);
+        let density = spectral_density(&moments, &points);
+        let integral = density.iter().zip(&points)
+            .map(|(rho, x)| rho * PI * (1. - x * x).sqrt() / 512.)
+            .sum::<f64>();
+        assert!((integral - moments[0]).abs() < 1e-8 * moments[0]);
+        assert!(density.iter().all(|rho| *rho > -1e-10));
+    }
+}
@@ -0,0 +1,396 @@
+use num_traits::{ToPrimitive, NumCast};
+use num_complex::ComplexFloat;
+use log::{info, warn};
+
+use crate::subroutines_utils::Value;
+use crate::subroutines::{
+    init_zero_sized,
+    add_inplace,
+    dot,
+    norm,
+    scale_inplace,
+};
+
+/// Eigenvalues (ascending) and eigenvectors (columns, eigenvectors[i][j] is the
+/// j-th component of the i-th eigenvector) of a real symmetric tridiagonal matrix
+/// with the main diagonal `diag` and the off-diagonal `off_diag`. Implicit QL
+/// algorithm with Wilkinson shifts.
+pub(super) fn tridiagonal_eigh(
+    diag: &[f64],
+    off_diag: &[f64],
+) -> (Vec<f64>, Vec<Vec<f64>>)
+{
+    let n = diag.len();
+    let mut d = diag.to_owned();
+    let mut e = off_diag.to_owned();
+    e.resize(n, 0.);
+    let mut z = vec![vec![0f64; n]; n];
+    for (i, row) in z.iter_mut().enumerate() {
+        row[i] = 1.;
+    }
+    for l in 0..n {
+        let mut iter = 0;
+        loop {
+            let mut m = l;
+            while m + 1 < n {
+                let dd = d[m].abs() + d[m + 1].abs();
+                if e[m].abs() <= f64::EPSILON * dd {
+                    break;
+                }
+                m += 1;
+            }
+            if m == l {
+                break;
+            }
+            iter += 1;
+            if iter > 64 {
+                panic!("Tridiagonal eigensolver has not converged");
+            }
+            let mut g = (d[l + 1] - d[l]) / (2. * e[l]);
+            let mut r = g.hypot(1.);
+            g = d[m] - d[l] + e[l] / (g + r.copysign(g));
+            let mut s = 1.;
+            let mut c = 1.;
+            let mut p = 0.;
+            let mut i = m;
+            let mut underflow = false;
+            while i > l {
+                i -= 1;
+                let f = s * e[i];
+                let b = c * e[i];
+                r = f.hypot(g);
+                e[i + 1] = r;
+                if r == 0. {
+                    d[i + 1] -= p;
+                    e[m] = 0.;
+                    underflow = true;
+                    break;
+                }
+                s = f / r;
+                c = g / r;
+                g = d[i + 1] - p;
+                r = (d[i] - g) * s + 2. * c * b;
+                p = s * r;
+                d[i + 1] = g + p;
+                g = c * r - b;
+                for row in z.iter_mut() {
+                    let f = row[i + 1];
+                    row[i + 1] = s * row[i] + c * f;
+                    row[i] = c * row[i] - s * f;
+                }
+            }
+            if underflow {
+                continue;
+            }
+            d[l] -= p;
+            e[l] = g;
+            e[m] = 0.;
+        }
+    }
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|i, j| d[*i].partial_cmp(&d[*j]).unwrap());
+    let eigenvalues = order.iter().map(|i| d[*i]).collect();
+    let eigenvectors = order.iter().map(|i| z.iter().map(|row| row[*i]).collect()).collect();
+    (eigenvalues, eigenvectors)
+}
+
+/// Eigenvalues (ascending) and eigenvectors (in the same layout as in `tridiagonal_eigh`)
+/// of a real symmetric matrix given by its rows. Cyclic Jacobi rotations.
+pub(super) fn symmetric_eigh(matrix: &[Vec<f64>]) -> (Vec<f64>, Vec<Vec<f64>>)
+{
+    let n = matrix.len();
+    let mut a = matrix.to_owned();
+    let mut z = vec![vec![0f64; n]; n];
+    for (i, row) in z.iter_mut().enumerate() {
+        row[i] = 1.;
+    }
+    let mut converged = false;
+    for _ in 0..64 {
+        let total: f64 = a.iter().flatten().map(|x| x * x).sum();
+        let off_diag: f64 = (0..n).flat_map(|p| ((p + 1)..n).map(move |q| (p, q))).map(|(p, q)| a[p][q] * a[p][q]).sum();
+        if off_diag <= f64::EPSILON * f64::EPSILON * total {
+            converged = true;
+            break;
+        }
+        for p in 0..n {
+            for q in (p + 1)..n {
+                if a[p][q] == 0. {
+                    continue;
+                }
+                // the rotation in the (p, q) plane zeroing a[p][q]
+                let theta = (a[q][q] - a[p][p]) / (2. * a[p][q]);
+                let t = theta.signum() / (theta.abs() + theta.hypot(1.));
+                let c = 1. / t.hypot(1.);
+                let s = t * c;
+                for row in a.iter_mut() {
+                    let (ap, aq) = (row[p], row[q]);
+                    row[p] = c * ap - s * aq;
+                    row[q] = s * ap + c * aq;
+                }
+                let (head, tail) = a.split_at_mut(q);
+                for (ap, aq) in head[p].iter_mut().zip(tail[0].iter_mut()) {
+                    (*ap, *aq) = (c * *ap - s * *aq, s * *ap + c * *aq);
+                }
+                for row in z.iter_mut() {
+                    let (zp, zq) = (row[p], row[q]);
+                    row[p] = c * zp - s * zq;
+                    row[q] = s * zp + c * zq;
+                }
+            }
+        }
+    }
+    if !converged {
+        panic!("Jacobi eigensolver has not converged");
+    }
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|i, j| a[*i][*i].partial_cmp(&a[*j][*j]).unwrap());
+    let eigenvalues = order.iter().map(|i| a[*i][*i]).collect();
+    let eigenvectors = order.iter().map(|i| z.iter().map(|row| row[*i]).collect()).collect();
+    (eigenvalues, eigenvectors)
+}
+
+/// Result of the Lanczos iteration.
+pub(super) struct LanczosResult<T: ComplexFloat> {
+    pub(super) eigenvalues: Vec<T::Real>,
+    pub(super) ground_state: Vec<T>,
+    pub(super) residual: T::Real,
+}
+
+/// Thick-restarted Lanczos iteration with full reorthogonalization. `apply_fn(dst, src)`
+/// must perform dst += H src for a Hermitian H. Each cycle keeps at most `krylov_dim`
+/// vectors in memory and the next cycle starts from the `eigenvalues_number` lowest Ritz vectors
+/// of the previous one together with the residual vector. Iteration stops when residual norms
+/// of all the `eigenvalues_number` lowest Ritz pairs drop below `tolerance`. If the Krylov space
+/// of `init_state` is invariant and smaller than `eigenvalues_number` (e.g. it spans a small
+/// symmetry sector), only the eigenvalues within it are returned.
+pub(super) fn lanczos<T: Value>(
+    init_state: &[T],
+    apply_fn: impl Fn(&mut [T], &[T]),
+    krylov_dim: usize,
+    eigenvalues_number: usize,
+    tolerance: T::Real,
+    max_restarts: usize,
+) -> LanczosResult<T>
+{
+    assert!(eigenvalues_number > 0, "Number of eigenvalues must be positive");
+    assert!(
+        krylov_dim > eigenvalues_number,
+        "Krylov subspace dimension must exceed the number of eigenvalues, got {} and {}", krylov_dim, eigenvalues_number,
+    );
+    let tolerance = tolerance.to_f64().unwrap();
+    let mut start = init_state.to_owned();
+    let start_norm = norm(&start).to_f64().unwrap();
+    scale_inplace(&mut start, T::from(1. / start_norm).unwrap());
+    // Ritz pairs kept from the previous cycle and couplings of their vectors to `start`
+    let mut kept: Vec<(f64, f64, Vec<T>)> = Vec::new();
+    let mut eigenvalues = Vec::new();
+    let mut residual = f64::INFINITY;
+    for restart in 0..=max_restarts {
+        // the projection of H onto the basis, the arrowhead of kept Ritz values followed by the tridiagonal part
+        let mut projection = vec![vec![0f64; krylov_dim]; krylov_dim];
+        let k = kept.len();
+        let mut basis = Vec::with_capacity(krylov_dim);
+        for (i, (value, coupling, vector)) in kept.drain(..).enumerate() {
+            projection[i][i] = value;
+            projection[i][k] = coupling;
+            projection[k][i] = coupling;
+            basis.push(vector);
+        }
+        basis.push(std::mem::take(&mut start));
+        let mut last_beta = 0.;
+        let mut invariant = false;
+        for j in k..krylov_dim {
+            let mut w = init_zero_sized(basis[j].len());
+            apply_fn(&mut w, &basis[j]);
+            let alpha = dot(&basis[j], &w).re().to_f64().unwrap();
+            projection[j][j] = alpha;
+            // two passes of the classical Gram–Schmidt against the whole basis
+            for _ in 0..2 {
+                for v in &basis {
+                    let overlap = dot(v, &w);
+                    add_inplace(&mut w, v, -overlap);
+                }
+            }
+            let beta = norm(&w).to_f64().unwrap();
+            if beta < f64::EPSILON.sqrt() * alpha.abs().max(1.) {
+                invariant = true;
+                break;
+            }
+            scale_inplace(&mut w, T::from(1. / beta).unwrap());
+            if j + 1 < krylov_dim {
+                projection[j][j + 1] = beta;
+                projection[j + 1][j] = beta;
+                basis.push(w);
+            } else {
+                last_beta = beta;
+                start = w;
+            }
+        }
+        let m = basis.len();
+        let projection: Vec<Vec<f64>> = projection[..m].iter().map(|row| row[..m].to_owned()).collect();
+        let (ritz_values, ritz_vectors) = symmetric_eigh(&projection);
+        let wanted = eigenvalues_number.min(m);
+        let residuals: Vec<f64> = ritz_vectors[..wanted].iter().map(|y| (last_beta * y[m - 1]).abs()).collect();
+        residual = residuals.iter().copied().fold(0., f64::max);
+        eigenvalues = ritz_values[..wanted].to_owned();
+        info!("Lanczos cycle {}: lowest Ritz values {:?}, residuals {:?}", restart, eigenvalues, residuals);
+        let ritz_vector = |y: &[f64]| {
+            let mut vector = init_zero_sized(basis[0].len());
+            for (coeff, v) in y.iter().zip(&basis) {
+                add_inplace(&mut vector, v, T::from(*coeff).unwrap());
+            }
+            vector
+        };
+        if invariant || residual < tolerance {
+            if wanted < eigenvalues_number {
+                warn!(
+                    "The Krylov space of the initial state is invariant and has dimension {}, \
+                    thus only {} of {} eigenvalues are found",
+                    m, wanted, eigenvalues_number,
+                );
+            }
+            let mut ground_state = ritz_vector(&ritz_vectors[0]);
+            let ground_norm = norm(&ground_state).to_f64().unwrap();
+            scale_inplace(&mut ground_state, T::from(1. / ground_norm).unwrap());
+            return LanczosResult {
+                eigenvalues: eigenvalues.into_iter().map(|x| <T::Real as NumCast>::from(x).unwrap()).collect(),
+                ground_state,
+                residual: <T::Real as NumCast>::from(residual).unwrap(),
+            };
+        }
+        kept = ritz_values.iter().zip(&ritz_vectors).take(eigenvalues_number)
+            .map(|(value, y)| (*value, last_beta * y[m - 1], ritz_vector(y)))
+            .collect();
+    }
+    panic!(
+        "Lanczos iteration has not converged after {} restarts, lowest Ritz values: {:?}, largest residual: {}",
+        max_restarts, eigenvalues, residual,
+    );
+}
+
+/// Estimates the lowest and the highest eigenvalues of a Hermitian H by `steps` steps of
+/// the plain Lanczos iteration (three vectors in memory, no reorthogonalization) started
+/// from `init_state`. The extreme Ritz values are widened by their residual norms.
+/// `apply_fn(dst, src)` must perform dst += H src.
+pub(super) fn lanczos_bounds<T: Value>(
+    init_state: &[T],
+    apply_fn: impl Fn(&mut [T], &[T]),
+    steps: usize,
+) -> (f64, f64)
+{
+    assert!(steps > 0, "Number of Lanczos steps must be positive");
+    let mut curr = init_state.to_owned();
+    let curr_norm = norm(&curr).to_f64().unwrap();
+    scale_inplace(&mut curr, T::from(1. / curr_norm).unwrap());
+    let mut prev = init_zero_sized(curr.len());
+    let mut alphas = Vec::with_capacity(steps);
+    let mut betas: Vec<f64> = Vec::with_capacity(steps);
+    for _ in 0..steps {
+        // prev <- H curr - beta prev - alpha curr, then the pair is swapped
+        scale_inplace(&mut prev, T::from(-betas.last().copied().unwrap_or(0.)).unwrap());
+        apply_fn(&mut prev, &curr);
+        let alpha = dot(&curr, &prev).re().to_f64().unwrap();
+        add_inplace(&mut prev, &curr, T::from(-alpha).unwrap());
+        alphas.push(alpha);
+        let beta = norm(&prev).to_f64().unwrap();
+        betas.push(beta);
+        if beta < f64::EPSILON.sqrt() * alpha.abs().max(1.) {
+            break;
+        }
+        scale_inplace(&mut prev, T::from(1. / beta).unwrap());
+        std::mem::swap(&mut prev, &mut curr);
+    }
+    let m = alphas.len();
+    let (ritz_values, ritz_vectors) = tridiagonal_eigh(&alphas, &betas[..(m - 1)]);
+    let lower_residual = (betas[m - 1] * ritz_vectors[0][m - 1]).abs();
+    let upper_residual = (betas[m - 1] * ritz_vectors[m - 1][m - 1]).abs();
+    (ritz_values[0] - lower_residual, ritz_values[m - 1] + upper_residual)
+}
+
+#[cfg(test)]
+mod tests {
+    use num_complex::{Complex64, ComplexFloat};
+    use num_traits::Zero;
+    use super::*;
+
+    #[test]
+    fn test_tridiagonal_eigh()
+    {
+        let diag = [1., -2., 0.5, 3., 0.];
+        let off_diag = [0.3, -1., 0.7, 2.];
+        let (vals, vecs) = tridiagonal_eigh(&diag, &off_diag);
+        for w in vals.windows(2) {
+            assert!(w[0] <= w[1]);
+        }
+        for (val, vec) in vals.iter().zip(&vecs) {
+            for i in 0..diag.len() {
+                let mut lhs = diag[i] * vec[i];
+                if i > 0 { lhs += off_diag[i - 1] * vec[i - 1]; }
+                if i + 1 < diag.len() { lhs += off_diag[i] * vec[i + 1]; }
+                assert!((lhs - val * vec[i]).abs() < 1e-10);
+            }
+        }
+    }
+
+    #[test]
+    fn test_symmetric_eigh()
+    {
+        let matrix = vec![
+            vec![2., 0.5, 0., 1.],
+            vec![0.5, -1., 0.3, 0.],
+            vec![0., 0.3, 0., -2.],
+            vec![1., 0., -2., 4.],
+        ];
+        let (vals, vecs) = symmetric_eigh(&matrix);
+        for w in vals.windows(2) {
+            assert!(w[0] <= w[1]);
+        }
+        for (val, vec) in vals.iter().zip(&vecs) {
+            for (row, x) in matrix.iter().zip(vec) {
+                let lhs: f64 = row.iter().zip(vec).map(|(a, b)| a * b).sum();
+                assert!((lhs - val * x).abs() < 1e-10);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lanczos()
+    {
+        // H = diag(0, 1, ..., 63) + nearest-neighbour coupling 0.5
+        let size = 64;
+        let apply_fn = |dst: &mut [Complex64], src: &[Complex64]| {
+            for i in 0..size {
+                dst[i] += Complex64::new(i as f64, 0.) * src[i];
+                if i > 0 { dst[i] += 0.5 * src[i - 1]; }
+                if i + 1 < size { dst[i] += 0.5 * src[i + 1]; }
+            }
+        };
+        let diag: Vec<_> = (0..size).map(|i| i as f64).collect();
+        let off_diag = vec![0.5; size - 1];
+        let (exact, _) = tridiagonal_eigh(&diag, &off_diag);
+        let init: Vec<_> = (0..size).map(|i| Complex64::new(1., 0.1 * i as f64)).collect();
+        let result = lanczos(&init, apply_fn, 20, 4, 1e-10, 100);
+        assert_eq!(result.eigenvalues.len(), 4);
+        for (value, exact) in result.eigenvalues.iter().zip(&exact) {
+            assert!((value - exact).abs() < 1e-8);
+        }
+        let mut h_psi = vec![Complex64::zero(); size];
+        apply_fn(&mut h_psi, &result.ground_state);
+        for (a, b) in h_psi.iter().zip(&result.ground_state) {
+            assert!((a - exact[0] * b).abs() < 1e-6);
+        }
+        // the Krylov space of a single basis vector of a diagonal H is one-dimensional
+        let mut basis_vector = vec![Complex64::zero(); size];
+        basis_vector[3] = Complex64::new(1., 0.);
+        let diagonal = |dst: &mut [Complex64], src: &[Complex64]| {
+            for i in 0..size {
+                dst[i] += Complex64::new(i as f64, 0.) * src[i];
+            }
+        };
+        let result = lanczos(&basis_vector, diagonal, 8, 2, 1e-10, 10);
+        assert_eq!(result.eigenvalues, [3.]);
+        let (lower, upper) = lanczos_bounds(&init, apply_fn, 40);
+        assert!(lower <= exact[0] + 1e-8 && lower > exact[0] - 1.);
+        assert!(upper >= exact[size - 1] - 1e-8 && upper < exact[size - 1] + 1.);
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+use log::info;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::Deserialize;
+use serde_yaml::{Value as YamlValue, Mapping};
+
+/// Geometry of a lattice, sites are numbered by positions of modes.
+#[derive(
+    Deserialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+pub enum Geometry {
+    /// An open chain of sites 0, 1, ..., L - 1
+    Chain(usize),
+    /// A periodic chain of L sites
+    Ring(usize),
+    /// Two open legs of length L connected by rungs, the sites of the i-th rung are 2i and 2i + 1
+    Ladder(usize),
+    /// An open Lx x Ly square lattice, the site (x, y) is x + Lx y
+    Square(usize, usize),
+    /// An arbitrary list of bonds
+    Edges(Vec<(usize, usize)>),
+}
+
+impl Geometry {
+
+    fn sites_number(&self) -> usize
+    {
+        match self {
+            Geometry::Chain(length) | Geometry::Ring(length) => *length,
+            Geometry::Ladder(length) => 2 * length,
+            Geometry::Square(lx, ly) => lx * ly,
+            Geometry::Edges(edges) => edges.iter().map(|(i, j)| std::cmp::max(i, j) + 1).max().unwrap_or(0),
+        }
+    }
+
+    /// Bonds of the lattice, each one is listed once
+    fn bonds(&self) -> Vec<(usize, usize)>
+    {
+        match self {
+            Geometry::Chain(length) => (1..*length).map(|i| (i - 1, i)).collect(),
+            Geometry::Ring(length) => {
+                let mut bonds: Vec<_> = (1..*length).map(|i| (i - 1, i)).collect();
+                if *length > 2 {
+                    bonds.push((length - 1, 0));
+                }
+                bonds
+            },
+            Geometry::Ladder(length) => {
+                let legs = (1..*length).flat_map(|i| [(2 * i - 2, 2 * i), (2 * i - 1, 2 * i + 1)]);
+                let rungs = (0..*length).map(|i| (2 * i, 2 * i + 1));
+                legs.chain(rungs).collect()
+            },
+            Geometry::Square(lx, ly) => {
+                let horizontal = (0..*ly).flat_map(|y| (1..*lx).map(move |x| (x - 1 + lx * y, x + lx * y)));
+                let vertical = (1..*ly).flat_map(|y| (0..*lx).map(move |x| (x + lx * (y - 1), x + lx * y)));
+                horizontal.chain(vertical).collect()
+            },
+            Geometry::Edges(edges) => edges.clone(),
+        }
+    }
+}
+
+/// Values of a coupling: the same amplitude (any amplitude of a term) for all the sites or bonds,
+/// a list of amplitudes per site or bond, or a constant `value` with a disorder uniformly
+/// distributed in [-disorder / 2, disorder / 2]. An omitted `seed` is drawn once per run
+/// and shared by all the stages of a protocol and all the points of a sweep.
+#[derive(
+    Deserialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+#[serde(untagged)]
+pub enum Coupling {
+    Disordered {
+        value: f64,
+        disorder: f64,
+        #[serde(default)]
+        seed: Option<u64>,
+    },
+    PerElement(Vec<YamlValue>),
+    Uniform(YamlValue),
+}
+
+impl Coupling {
+
+    /// Amplitudes of `number` sites or bonds
+    fn amplitudes(&self, name: &str, number: usize) -> Result<Vec<YamlValue>, String>
+    {
+        match self {
+            Coupling::Uniform(ampl) => Ok(vec![ampl.clone(); number]),
+            Coupling::PerElement(ampls) if ampls.len() == number => Ok(ampls.clone()),
+            Coupling::PerElement(ampls) => Err(format!(
+                "Coupling `{}` has {} values, while the lattice has {} of them", name, ampls.len(), number,
+            )),
+            Coupling::Disordered { value, disorder, seed } => {
+                let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+                info!("Disorder of the coupling `{}` is generated with the seed {}", name, seed);
+                let mut rng = StdRng::seed_from_u64(seed);
+                Ok((0..number).map(|_| YamlValue::from(value + disorder * (rng.gen::<f64>() - 0.5))).collect())
+            },
+        }
+    }
+}
+
+/// A lattice Hamiltonian sum_<ij> J_ij (a_i^dagger a_j + h.c.) + sum_<ij> V_ij n_i n_j
+/// + sum_i mu_i n_i + sum_i U_i n_i^2, where <ij> are bonds of the lattice.
+#[derive(
+    Deserialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+pub struct Lattice {
+    geometry: Geometry,
+    #[serde(default, rename = "J")]
+    hopping: Option<Coupling>,
+    #[serde(default, rename = "V")]
+    density_density: Option<Coupling>,
+    #[serde(default, rename = "U")]
+    on_site: Option<Coupling>,
+    #[serde(default, rename = "mu")]
+    chemical_potential: Option<Coupling>,
+}
+
+impl Lattice {
+
+    /// Hamiltonian terms of the lattice in the config format
+    fn terms(&self) -> Result<Vec<YamlValue>, String>
+    {
+        let bonds: Vec<_> = self.geometry.bonds().into_iter().map(|(i, j)| vec![i, j]).collect();
+        let sites: Vec<_> = (0..self.geometry.sites_number()).map(|i| vec![i]).collect();
+        let couplings = [
+            ("J", &self.hopping, &bonds, vec!["A+", "A-"], true),
+            ("V", &self.density_density, &bonds, vec!["N1", "N1"], false),
+            ("mu", &self.chemical_potential, &sites, vec!["N1"], false),
+            ("U", &self.on_site, &sites, vec!["N2"], false),
+        ];
+        let mut terms = Vec::new();
+        for (name, coupling, elements, ops, hc) in couplings {
+            let Some(coupling) = coupling else { continue };
+            for (pos, ampl) in elements.iter().zip(coupling.amplitudes(name, elements.len())?) {
+                let mut term = Mapping::new();
+                term.insert("ampl".into(), ampl);
+                term.insert("pos".into(), pos.clone().into());
+                term.insert("ops".into(), ops.clone().into());
+                if hc {
+                    term.insert("hc".into(), true.into());
+                }
+                terms.push(YamlValue::Mapping(term));
+            }
+        }
+        Ok(terms)
+    }
+}
+
+/// Expands the `lattice` section of a task config into Hamiltonian terms appended
+/// to the `hamiltonian` section, the `lattice` section itself is removed from the config.
+pub(super) fn expand_lattice(config: &mut YamlValue) -> Result<(), String>
+{
+    let task = match config {
+        YamlValue::Tagged(tagged) => &mut tagged.value,
+        other => other,
+    };
+    let Some(task) = task.as_mapping_mut() else {
+        return Ok(());
+    };
+    let Some(lattice) = task.remove("lattice") else {
+        return Ok(());
+    };
+    let lattice: Lattice = serde_yaml::from_value(lattice).map_err(|err| format!("Invalid `lattice` section: {}", err))?;
+    let modes_number = ["qubits_per_mode", "max_occupation"].iter()
+        .find_map(|key| task.get(*key).and_then(|dims| dims.as_sequence()).map(|dims| dims.len()));
+    if let Some(modes_number) = modes_number {
+        if lattice.geometry.sites_number() > modes_number {
+            return Err(format!(
+                "The lattice has {} sites, while there are only {} modes", lattice.geometry.sites_number(), modes_number,
+            ));
+        }
+    }
+    let terms = lattice.terms()?;
+    match task.entry("hamiltonian".into()).or_insert_with(|| YamlValue::Sequence(Vec::new())) {
+        YamlValue::Sequence(hamiltonian) => hamiltonian.extend(terms),
+        _ => return Err("`hamiltonian` must be a list of terms".to_owned()),
+    }
+    Ok(())
+}
+
+/// Calls `f` with the name and the config of every disordered coupling
+/// of every `lattice` section of a config
+fn for_each_disordered_coupling(config: &mut YamlValue, f: &mut impl FnMut(&str, &mut Mapping))
+{
+    match config {
+        YamlValue::Mapping(mapping) => {
+            for (key, value) in mapping.iter_mut() {
+                match (key.as_str(), value) {
+                    (Some("lattice"), YamlValue::Mapping(lattice)) => {
+                        for (name, coupling) in lattice.iter_mut() {
+                            let (Some(name), YamlValue::Mapping(coupling)) = (name.as_str(), coupling) else { continue };
+                            if coupling.contains_key("disorder") {
+                                f(name, coupling);
+                            }
+                        }
+                    },
+                    (_, value) => for_each_disordered_coupling(value, f),
+                }
+            }
+        },
+        YamlValue::Sequence(sequence) => sequence.iter_mut().for_each(|value| for_each_disordered_coupling(value, f)),
+        YamlValue::Tagged(tagged) => for_each_disordered_coupling(&mut tagged.value, f),
+        _ => {},
+    }
+}
+
+/// Draws seeds of disordered couplings given without a seed and writes them into the config
+/// before stages of a protocol or points of a sweep are expanded, so that all of them share
+/// the same disorder. A seed is drawn once per name of a coupling.
+pub(super) fn seed_disorder(config: &mut YamlValue)
+{
+    let mut seeds = BTreeMap::new();
+    for_each_disordered_coupling(config, &mut |name, coupling| {
+        if !coupling.contains_key("seed") {
+            let seed: u64 = *seeds.entry(name.to_owned()).or_insert_with(|| rand::thread_rng().gen());
+            coupling.insert("seed".into(), seed.into());
+        }
+    });
+}
+
+/// Seeds of disordered couplings of a config by names of couplings
+pub(super) fn disorder_seeds(config: &YamlValue) -> BTreeMap<String, u64>
+{
+    let mut seeds = BTreeMap::new();
+    for_each_disordered_coupling(&mut config.clone(), &mut |name, coupling| {
+        if let Some(seed) = coupling.get("seed").and_then(YamlValue::as_u64) {
+            seeds.insert(name.to_owned(), seed);
+        }
+    });
+    seeds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lattice()
+    {
+        assert_eq!(Geometry::Ring(4).bonds(), vec![(0, 1), (1, 2), (2, 3), (3, 0)]);
+        assert_eq!(Geometry::Ladder(2).bonds(), vec![(0, 2), (1, 3), (0, 1), (2, 3)]);
+        assert_eq!(Geometry::Square(3, 2).bonds(), vec![(0, 1), (1, 2), (3, 4), (4, 5), (0, 3), (1, 4), (2, 5)]);
+        assert_eq!(Geometry::Edges(vec![(0, 4), (2, 1)]).sites_number(), 5);
+        let mut config: YamlValue = serde_yaml::from_str(
+            "!Task {qubits_per_mode: [1, 1, 1], hamiltonian: [{ampl: 1, pos: [0], ops: [N1]}], \
+            lattice: {geometry: !Chain 3, J: -1, U: [1, 2, 3], mu: {value: 0.5, disorder: 1, seed: 3}}}"
+        ).unwrap();
+        expand_lattice(&mut config).unwrap();
+        let YamlValue::Tagged(task) = &config else { panic!("Unexpected config") };
+        assert!(task.value.get("lattice").is_none());
+        let hamiltonian = task.value["hamiltonian"].as_sequence().unwrap();
+        assert_eq!(hamiltonian.len(), 1 + 2 + 3 + 3);
+        let expected: YamlValue = serde_yaml::from_str("{ampl: -1, pos: [1, 2], ops: [A+, A-], hc: true}").unwrap();
+        assert_eq!(hamiltonian[2], expected);
+        let expected: YamlValue = serde_yaml::from_str("{ampl: 3, pos: [2], ops: [N2]}").unwrap();
+        assert_eq!(hamiltonian[8], expected);
+        for term in &hamiltonian[3..6] {
+            let ampl = term["ampl"].as_f64().unwrap();
+            assert!((0. ..=1.).contains(&ampl));
+        }
+        let mut config: YamlValue = serde_yaml::from_str(
+            "{qubits_per_mode: [1, 1], lattice: {geometry: !Chain 3, J: -1}}"
+        ).unwrap();
+        assert!(expand_lattice(&mut config).is_err());
+        let mut config: YamlValue = serde_yaml::from_str(
+            "{qubits_per_mode: [1, 1, 1], lattice: {geometry: !Ring 3, V: [1, 2]}}"
+        ).unwrap();
+        assert!(expand_lattice(&mut config).is_err());
+    }
+
+    #[test]
+    fn test_seed_disorder()
+    {
+        let mut config: YamlValue = serde_yaml::from_str(
+            "!Protocol {qubits_per_mode: [1, 1, 1], lattice: {geometry: !Chain 3, J: {value: -1, disorder: 0.5}, U: 1}, \
+            stages: [!ChebyshevDynamics {name: quench}, \
+            !ChebyshevDynamics {lattice: {geometry: !Chain 3, J: {value: -1, disorder: 0.5}, mu: {value: 0, disorder: 1, seed: 3}}}]}"
+        ).unwrap();
+        seed_disorder(&mut config);
+        let YamlValue::Tagged(protocol) = &config else { panic!("Unexpected config") };
+        let seed = protocol.value["lattice"]["J"]["seed"].as_u64().unwrap();
+        let YamlValue::Tagged(stage) = &protocol.value["stages"][1] else { panic!("Unexpected config") };
+        assert_eq!(stage.value["lattice"]["J"]["seed"].as_u64(), Some(seed));
+        assert_eq!(stage.value["lattice"]["mu"]["seed"].as_u64(), Some(3));
+        assert!(protocol.value["lattice"]["U"].get("seed").is_none());
+        assert_eq!(disorder_seeds(&config), BTreeMap::from([("J".to_owned(), seed), ("mu".to_owned(), 3)]));
+        // the disorder is the same for every expansion of the seeded lattice
+        let mut lattice = stage.clone();
+        lattice.value.as_mapping_mut().unwrap().insert("qubits_per_mode".into(), serde_yaml::from_str("[1, 1, 1]").unwrap());
+        let mut lhs = YamlValue::Tagged(lattice);
+        let mut rhs = lhs.clone();
+        expand_lattice(&mut lhs).unwrap();
+        expand_lattice(&mut rhs).unwrap();
+        assert_eq!(lhs, rhs);
+    }
+}
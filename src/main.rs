@@ -2,6 +2,18 @@ mod subroutines_utils;
 mod subroutines;
 mod tasks;
 mod chebyshev;
+mod lanczos;
+mod amplitude;
+mod kpm;
+mod sector;
+mod dense;
+mod lattice;
+mod sweep;
+mod protocol;
+mod checkpoint;
+mod stream;
+mod npz;
+mod metadata;
 
 #[cfg(test)]
 mod test_utils;
@@ -11,7 +23,9 @@ mod subroutines_tests;
 
 use std::fs::{read_to_string, write};
 use chebyshev::FromComplex64;
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use log::{info, warn};
+use rayon::prelude::*;
 use num_complex::{
     Complex32,
     Complex64,
@@ -19,10 +33,27 @@ use num_complex::{
 use subroutines_utils::TrueComplex;
 use crate::subroutines_utils::Value;
 use crate::tasks::Task;
+use crate::protocol::{is_protocol, Protocol};
+use crate::lattice::seed_disorder;
+use crate::stream::Stream;
+use crate::npz::{to_npz, NpyArray, NpyElement};
+use crate::metadata::{Metadata, ResultFile};
+use serde::Serialize;
+use crate::sweep::{expand_sweep, point_result_path, SweepRecord};
+
+/// A format of result files
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Format {
+    /// All the results pickled
+    Pickle,
+    /// A numpy archive of density matrices, (steps, dim, dim) arrays `density_matrix_i`, and `times` of steps,
+    /// arrays of stages of a protocol are prefixed by `name/` of a stage, `metadata` is a YAML string
+    Npz,
+}
 
 /// This program simulates a bosonic system exactly. It takes
 /// a task config in *.yaml format and produces results
-/// in the pickle format.
+/// in the pickle format together with the metadata of the run.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
@@ -38,19 +69,107 @@ struct Args {
     /// Precision of computation
     #[arg(short, long, default_value_t=String::from("f32"))]
     dtype: String,
+
+    /// Truncation error of Chebyshev expansions, defaults to 1e-6 for f32 and 1e-14 for f64
+    #[arg(short, long)]
+    tolerance: Option<f64>,
+
+    /// A checkpoint file to resume a run from, observables of the resumed run include the ones before the checkpoint
+    #[arg(long)]
+    resume: Option<String>,
+
+    /// A file observables are appended to after every time step, a sequence of pickles
+    #[arg(long)]
+    stream: Option<String>,
+
+    /// A format of the result file, the index of a sweep is pickled anyway
+    #[arg(long, value_enum, default_value_t=Format::Pickle)]
+    format: Format,
+}
+
+fn run<T>(config: String, output_path: &str, tolerance: f64, acc: T::Real, resume: Option<&str>, stream: Option<&str>, format: Format)
+where
+    T: Value + TrueComplex + FromComplex64 + std::iter::Sum + NpyElement,
+    T::Real: Value + NpyElement,
+{
+    let mut config = serde_yaml::from_str(&config).expect("Unable to recognize a config");
+    seed_disorder(&mut config);
+    let Some(points) = expand_sweep(&config).expect("Unable to recognize a sweep") else {
+        run_config::<T>(config, output_path, tolerance, acc, resume, stream, format);
+        return;
+    };
+    assert!(resume.is_none(), "Sweeps can not be resumed from a checkpoint");
+    let records: Vec<_> = points.iter().enumerate().map(|(i, point)| {
+        let result = point_result_path(output_path, i);
+        SweepRecord { parameters: point.parameters.clone(), result }
+    }).collect();
+    let run_point = |(i, (point, record)): (usize, (&sweep::SweepPoint, &SweepRecord))| {
+        info!("Running the sweep point {:?}", point.parameters);
+        let stream = stream.map(|stream| point_result_path(stream, i));
+        run_config::<T>(point.config.clone(), &record.result, tolerance, acc, None, stream.as_deref(), format);
+    };
+    // small tasks do not load all the threads, so sweep points are run concurrently
+    if points.iter().all(|point| point.is_small()) {
+        points.par_iter().zip(&records).enumerate().for_each(run_point);
+    } else {
+        points.iter().zip(&records).enumerate().for_each(run_point);
+    }
+    let pickled_records = serde_pickle::to_vec(&records, Default::default()).unwrap();
+    write(output_path, pickled_records).expect("impossible write results to a file");
+}
+
+/// Runs either a single task or a protocol of several tasks
+fn run_config<T>(config: serde_yaml::Value, output_path: &str, tolerance: f64, acc: T::Real, resume: Option<&str>, stream: Option<&str>, format: Format)
+where
+    T: Value + TrueComplex + FromComplex64 + std::iter::Sum + NpyElement,
+    T::Real: Value + NpyElement,
+{
+    // a resumed run continues the stream of the interrupted one
+    if let (Some(path), None) = (stream, resume) {
+        Stream::create(path).expect("Unable to create a stream");
+    }
+    let npz = format == Format::Npz;
+    let mut metadata = Metadata::start(&config, std::any::type_name::<T::Real>(), tolerance);
+    let serialized_result = if is_protocol(&config) {
+        assert!(resume.is_none(), "Protocols can not be resumed from a checkpoint");
+        let mut protocol = Protocol::<T>::from_value(config).expect("Unable to recognize a protocol");
+        assert!(!npz || protocol.npz_supported(), "Results of some stages can not be written in the .npz format");
+        if let Some(path) = stream {
+            protocol.stream_to(path);
+        }
+        let result = protocol.run(tolerance, acc);
+        metadata.finish(protocol.metadata(&result));
+        serialize_result(format, &metadata, &result, || protocol.npz_arrays(&result))
+    } else {
+        let mut task = Task::<T>::from_value(config).expect("Unable to recognize a config");
+        assert!(!npz || task.npz_supported(), "Results of the task can not be written in the .npz format");
+        if let Some(path) = resume {
+            task.resume_from(path).expect("Unable to resume a run");
+        }
+        if let Some(path) = stream {
+            if !task.stream_to(Stream::new(path, None)) {
+                warn!("The task has no time steps, thus its observables are not streamed");
+            }
+        }
+        let (result, _) = task.run(None, tolerance, acc);
+        metadata.finish(vec![task.metadata(&result, None)]);
+        serialize_result(format, &metadata, &result, || task.npz_arrays(&result, ""))
+    };
+    write(output_path, serialized_result).expect("impossible write results to a file");
 }
 
-fn run<T>(config: String, output_path: &str, order: usize, acc: T::Real)
+/// Serializes results together with the metadata, `npz_arrays` gives arrays of results for the .npz format
+fn serialize_result<R>(format: Format, metadata: &Metadata, result: &R, npz_arrays: impl FnOnce() -> Vec<(String, NpyArray)>) -> Vec<u8>
 where
-    T: Value + TrueComplex + FromComplex64 + std::iter::Sum,
-    T::Real: Value,
+    R: Serialize,
 {
-    let task = serde_yaml::from_str::<Task<T>>(&config).expect("Unable to recognize a config");
-    match task {
-        Task::ChebyshevDynamics(task) => {
-            let density_matrices = task.run(order, acc);
-            let pickled_result = serde_pickle::to_vec(&density_matrices, Default::default()).unwrap();
-            write(output_path, pickled_result).expect("impossible write results to a file");
+    match format {
+        Format::Pickle => serde_pickle::to_vec(&ResultFile { metadata, result }, Default::default()).unwrap(),
+        Format::Npz => {
+            let mut arrays = npz_arrays();
+            let metadata = serde_yaml::to_string(metadata).expect("Unable to serialize metadata");
+            arrays.push(("metadata".to_owned(), NpyArray::string(&metadata)));
+            to_npz(&arrays).expect("Unable to write results in the .npz format")
         },
     }
 }
@@ -60,8 +179,8 @@ fn main() {
     let args = Args::parse();
     let config = read_to_string(&args.config).expect(&format!("Could not read a config file {:?}", &args.config));
     match args.dtype.as_str() {
-        "f32" => run::<Complex32>(config, &args.result, 7, 1e-3),
-        "f64" => run::<Complex64>(config, &args.result, 14, 1e-8),
+        "f32" => run::<Complex32>(config, &args.result, args.tolerance.unwrap_or(1e-6), 1e-3, args.resume.as_deref(), args.stream.as_deref(), args.format),
+        "f64" => run::<Complex64>(config, &args.result, args.tolerance.unwrap_or(1e-14), 1e-8, args.resume.as_deref(), args.stream.as_deref(), args.format),
         other => { panic!("Data-type \"{}\" is not recognized", other) }
     }
 }
\ No newline at end of file
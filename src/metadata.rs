@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use crate::lattice::disorder_seeds;
+
+/// Metadata of a task in a result file: positions of modes of requested density matrices,
+/// times of steps and the largest deviation of traces of density matrices from 1 at every step
+#[derive(
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+pub struct TaskMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) stage: Option<String>,
+    pub(super) task: &'static str,
+    pub(super) density_matrices: Vec<Vec<usize>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(super) times: Vec<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(super) trace_deviations: Vec<f64>,
+}
+
+/// Metadata that make a result file interpretable without the config it was made from:
+/// the program version, the config itself, the precision, seeds of disordered lattice couplings,
+/// the start time (seconds since the Unix epoch), the wall time of the run in seconds and
+/// metadata of tasks (stages of a protocol)
+#[derive(
+    Serialize,
+    Debug,
+    Clone,
+)]
+pub struct Metadata {
+    program: &'static str,
+    version: &'static str,
+    config: String,
+    dtype: &'static str,
+    tolerance: f64,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    disorder_seeds: BTreeMap<String, u64>,
+    started_at: u64,
+    wall_time: f64,
+    tasks: Vec<TaskMetadata>,
+    #[serde(skip)]
+    started: Instant,
+}
+
+/// A result file, results of a task or a protocol together with the metadata
+#[derive(Serialize)]
+pub(super) struct ResultFile<'a, R> {
+    pub(super) metadata: &'a Metadata,
+    pub(super) result: &'a R,
+}
+
+impl Metadata {
+
+    /// Metadata of a run starting now
+    pub(super) fn start(config: &serde_yaml::Value, dtype: &'static str, tolerance: f64) -> Self
+    {
+        let started_at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        Metadata {
+            program: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            config: serde_yaml::to_string(config).expect("Unable to serialize a config"),
+            dtype,
+            tolerance,
+            disorder_seeds: disorder_seeds(config),
+            started_at,
+            wall_time: 0.,
+            tasks: Vec::new(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Completes the metadata once the run is over
+    pub(super) fn finish(&mut self, tasks: Vec<TaskMetadata>)
+    {
+        self.wall_time = self.started.elapsed().as_secs_f64();
+        self.tasks = tasks;
+    }
+}
@@ -0,0 +1,208 @@
+use num_complex::{Complex32, Complex64};
+
+/// An element of an array in the .npy format, written in the little-endian byte order
+pub trait NpyElement: Copy {
+    /// The numpy type string of the element
+    const DESCR: &'static str;
+
+    fn write_le(&self, dst: &mut Vec<u8>);
+}
+
+impl NpyElement for f32 {
+    const DESCR: &'static str = "<f4";
+
+    fn write_le(&self, dst: &mut Vec<u8>)
+    {
+        dst.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl NpyElement for f64 {
+    const DESCR: &'static str = "<f8";
+
+    fn write_le(&self, dst: &mut Vec<u8>)
+    {
+        dst.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl NpyElement for Complex32 {
+    const DESCR: &'static str = "<c8";
+
+    fn write_le(&self, dst: &mut Vec<u8>)
+    {
+        self.re.write_le(dst);
+        self.im.write_le(dst);
+    }
+}
+
+impl NpyElement for Complex64 {
+    const DESCR: &'static str = "<c16";
+
+    fn write_le(&self, dst: &mut Vec<u8>)
+    {
+        self.re.write_le(dst);
+        self.im.write_le(dst);
+    }
+}
+
+/// A C-ordered array in the .npy format (version 1.0)
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+)]
+pub struct NpyArray {
+    descr: String,
+    shape: Vec<usize>,
+    data: Vec<u8>,
+}
+
+impl NpyArray {
+
+    /// An array of the given shape, elements are taken in the C order
+    pub(super) fn new<'a, E: NpyElement + 'a>(shape: Vec<usize>, elements: impl IntoIterator<Item = &'a E>) -> Self
+    {
+        let mut data = Vec::with_capacity(shape.iter().product::<usize>() * std::mem::size_of::<E>());
+        let mut size = 0;
+        for element in elements {
+            element.write_le(&mut data);
+            size += 1;
+        }
+        assert_eq!(size, shape.iter().product::<usize>(), "The number of elements does not match the shape {:?}", shape);
+        NpyArray { descr: E::DESCR.to_owned(), shape, data }
+    }
+
+    /// A scalar array of a unicode string
+    pub(super) fn string(text: &str) -> Self
+    {
+        let data = text.chars().flat_map(|c| (c as u32).to_le_bytes()).collect();
+        NpyArray { descr: format!("<U{}", text.chars().count()), shape: Vec::new(), data }
+    }
+
+    fn to_npy(&self) -> Vec<u8>
+    {
+        let shape = match self.shape.as_slice() {
+            [dim] => format!("({},)", dim),
+            shape => format!("({})", shape.iter().map(usize::to_string).collect::<Vec<_>>().join(", ")),
+        };
+        let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", self.descr, shape);
+        // the magic string, the version and the header length take 10 bytes,
+        // the header is padded by spaces and ends with a newline so that data is aligned by 64 bytes
+        let padding = 63 - (10 + header.len()) % 64;
+        header.push_str(&" ".repeat(padding));
+        header.push('\n');
+        let mut npy = Vec::with_capacity(10 + header.len() + self.data.len());
+        npy.extend_from_slice(b"\x93NUMPY\x01\x00");
+        npy.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        npy.extend_from_slice(header.as_bytes());
+        npy.extend_from_slice(&self.data);
+        npy
+    }
+}
+
+const fn crc32_table() -> [u32; 256]
+{
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+fn crc32(data: &[u8]) -> u32
+{
+    !data.iter().fold(!0u32, |crc, byte| (crc >> 8) ^ CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize])
+}
+
+/// Packs named arrays into an uncompressed zip archive of `name.npy` files as `numpy.savez` does,
+/// the archive (without the zip64 extension) must be smaller than 4 GiB
+pub(super) fn to_npz(arrays: &[(String, NpyArray)]) -> Result<Vec<u8>, String>
+{
+    // 1980-01-01, the earliest date of the zip format
+    const DATE: u16 = (1 << 5) | 1;
+    let mut archive = Vec::new();
+    let mut central_directory = Vec::new();
+    for (name, array) in arrays {
+        let npy = array.to_npy();
+        let name = format!("{}.npy", name);
+        let offset = u32::try_from(archive.len()).map_err(|_| "The .npz archive exceeds 4 GiB".to_owned())?;
+        let size = u32::try_from(npy.len()).map_err(|_| format!("The array `{}` exceeds 4 GiB", name))?;
+        // the fields shared by the local header and the central directory record:
+        // the version needed (2.0), flags, the compression method (stored), time, date, CRC-32, sizes, the name length
+        let mut common = Vec::with_capacity(26);
+        common.extend_from_slice(&20u16.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        common.extend_from_slice(&DATE.to_le_bytes());
+        common.extend_from_slice(&crc32(&npy).to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        archive.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        archive.extend_from_slice(&common);
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(&npy);
+        central_directory.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        central_directory.extend_from_slice(&20u16.to_le_bytes());
+        central_directory.extend_from_slice(&common);
+        // the comment length, the disk number, internal and external attributes
+        central_directory.extend_from_slice(&[0; 10]);
+        central_directory.extend_from_slice(&offset.to_le_bytes());
+        central_directory.extend_from_slice(name.as_bytes());
+    }
+    let offset = u32::try_from(archive.len()).map_err(|_| "The .npz archive exceeds 4 GiB".to_owned())?;
+    let entries = u16::try_from(arrays.len()).map_err(|_| "Too many arrays for the .npz archive".to_owned())?;
+    archive.extend_from_slice(&central_directory);
+    archive.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    archive.extend_from_slice(&[0; 4]);
+    archive.extend_from_slice(&entries.to_le_bytes());
+    archive.extend_from_slice(&entries.to_le_bytes());
+    archive.extend_from_slice(&(central_directory.len() as u32).to_le_bytes());
+    archive.extend_from_slice(&offset.to_le_bytes());
+    archive.extend_from_slice(&0u16.to_le_bytes());
+    Ok(archive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_npz()
+    {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        let times = NpyArray::new(vec![3], &[0., 0.5, 1.]);
+        let npy = times.to_npy();
+        assert_eq!(&npy[..8], b"\x93NUMPY\x01\x00");
+        assert_eq!((npy.len() - 24) % 64, 0);
+        assert!(String::from_utf8_lossy(&npy).contains("{'descr': '<f8', 'fortran_order': False, 'shape': (3,), }"));
+        assert_eq!(&npy[npy.len() - 8..], &1f64.to_le_bytes());
+        let dens = NpyArray::new(vec![1, 2, 2], &[Complex32::new(1., 0.), Complex32::new(0., 0.5), Complex32::new(0., -0.5), Complex32::new(0., 0.)]);
+        assert!(String::from_utf8_lossy(&dens.to_npy()).contains("'descr': '<c8'"));
+        assert!(String::from_utf8_lossy(&dens.to_npy()).contains("'shape': (1, 2, 2)"));
+        let text = NpyArray::string("ρ: 1");
+        assert!(String::from_utf8_lossy(&text.to_npy()).contains("{'descr': '<U4', 'fortran_order': False, 'shape': (), }"));
+        let npy = text.to_npy();
+        assert_eq!(&npy[npy.len() - 16..npy.len() - 12], &('ρ' as u32).to_le_bytes());
+        let npz = to_npz(&[("times".to_owned(), times), ("density_matrix_0".to_owned(), dens)]).unwrap();
+        let end = &npz[npz.len() - 22..];
+        assert_eq!(&end[..4], &0x06054b50u32.to_le_bytes());
+        assert_eq!(&end[8..10], &2u16.to_le_bytes());
+        let central_directory = u32::from_le_bytes(end[16..20].try_into().unwrap()) as usize;
+        assert_eq!(&npz[central_directory..central_directory + 4], &0x02014b50u32.to_le_bytes());
+        assert_eq!(&npz[30..39], b"times.npy");
+    }
+}
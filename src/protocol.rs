@@ -0,0 +1,206 @@
+use std::fmt::Debug;
+use log::info;
+use num_complex::ComplexFloat;
+use serde::{Serialize, Serializer, Deserialize};
+use serde_yaml::Value as YamlValue;
+use crate::chebyshev::FromComplex64;
+use crate::subroutines_utils::{TrueComplex, Value};
+use crate::tasks::{Task, TaskResult};
+use crate::stream::Stream;
+use crate::npz::{NpyArray, NpyElement};
+use crate::metadata::TaskMetadata;
+
+/// An ordered list of tasks (stages) run one after another, the final state of a stage
+/// is the initial state of the next one, thus `init_state` matters only for the first stage.
+/// All the keys of a protocol besides `stages` (e.g. `qubits_per_mode`, `init_state` or `density_matrices`)
+/// are shared by all the stages unless a stage overrides them. Results of a stage are written under
+/// its `name`, or its index if the name is not given. Shared keys that no stage uses are rejected. Only `LindbladDynamics` may follow `LindbladDynamics`,
+/// since it ends with a density matrix, and `QuantumTrajectories` may only be the last stage.
+pub struct Protocol<T>
+where
+    T: ComplexFloat,
+    T::Real: Serialize + for<'a > Deserialize<'a> + Debug
+{
+    stages: Vec<(String, Task<T>)>,
+}
+
+/// Results of stages of a protocol keyed by names of stages
+pub struct ProtocolResult<T>(Vec<(String, TaskResult<T>)>)
+where
+    T: ComplexFloat,
+    T::Real: Serialize;
+
+impl<T> Serialize for ProtocolResult<T>
+where
+    T: ComplexFloat + Serialize,
+    T::Real: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    {
+        serializer.collect_map(self.0.iter().map(|(name, result)| (name, result)))
+    }
+}
+
+/// Whether a config is a protocol, i.e. it is tagged by `!Protocol`
+pub(super) fn is_protocol(config: &YamlValue) -> bool
+{
+    matches!(config, YamlValue::Tagged(tagged) if tagged.tag == "Protocol")
+}
+
+impl<T> Protocol<T>
+where
+    T: Value + TrueComplex + FromComplex64 + std::iter::Sum + Debug + for<'a > Deserialize<'a>,
+    T::Real: Value + Serialize + for<'a > Deserialize<'a>,
+{
+    /// Parses a protocol config, each stage is parsed as a separate task config
+    /// with the shared keys of the protocol added to it
+    pub fn from_value(config: YamlValue) -> Result<Self, String>
+    {
+        let YamlValue::Tagged(tagged) = config else {
+            return Err("A protocol must be tagged by `!Protocol`".to_owned());
+        };
+        let YamlValue::Mapping(mut shared) = tagged.value else {
+            return Err("A protocol must be a map".to_owned());
+        };
+        let Some(YamlValue::Sequence(stages)) = shared.remove("stages") else {
+            return Err("A protocol must have a list of `stages`".to_owned());
+        };
+        let mut parsed_stages: Vec<(String, Task<T>)> = Vec::with_capacity(stages.len());
+        let mut used_keys = Vec::new();
+        for (i, mut stage) in stages.into_iter().enumerate() {
+            let Some(task) = (match &mut stage {
+                YamlValue::Tagged(tagged) => tagged.value.as_mapping_mut(),
+                _ => None,
+            }) else {
+                return Err(format!("The stage {} must be a task tagged by its name", i));
+            };
+            let mut inserted_keys = Vec::new();
+            for (key, value) in &shared {
+                if !task.contains_key(key) {
+                    task.insert(key.clone(), value.clone());
+                    inserted_keys.push(key.clone());
+                }
+            }
+            let name = match task.remove("name") {
+                Some(YamlValue::String(name)) => name,
+                Some(_) => return Err(format!("The name of the stage {} must be a string", i)),
+                None => i.to_string(),
+            };
+            if parsed_stages.iter().any(|(other, _)| *other == name) {
+                return Err(format!("The name `{}` is used by several stages", name));
+            }
+            let task = Task::from_value(stage).map_err(|err| format!("Stage `{}`: {}", name, err))?;
+            let config_keys = task.config_keys();
+            used_keys.extend(inserted_keys.into_iter().filter(|key| key.as_str().is_some_and(|key| config_keys.iter().any(|k| k == key))));
+            if let Some((previous_name, previous)) = parsed_stages.last() {
+                if previous.local_dims() != task.local_dims() {
+                    return Err(format!("Stages `{}` and `{}` have different local dimensions of modes", previous_name, name));
+                }
+                if matches!(previous, Task::QuantumTrajectories(_)) {
+                    return Err(format!("The stage `{}` follows quantum trajectories that do not end with a state", name));
+                }
+                if matches!(previous, Task::LindbladDynamics(_)) && !matches!(task, Task::LindbladDynamics(_)) {
+                    return Err(format!("The stage `{}` requires a pure state, while `{}` ends with a density matrix", name, previous_name));
+                }
+            }
+            parsed_stages.push((name, task));
+        }
+        if parsed_stages.is_empty() {
+            return Err("A protocol must have at least one stage".to_owned());
+        }
+        let unused_keys: Vec<_> = shared.keys().filter(|key| !used_keys.contains(key)).collect();
+        if !unused_keys.is_empty() {
+            return Err(format!("The shared keys {:?} are not used by any stage", unused_keys));
+        }
+        Ok(Protocol { stages: parsed_stages })
+    }
+
+    /// Makes all the stages stream observables of time steps to the same file
+    pub fn stream_to(&mut self, path: &str)
+    {
+        for (name, task) in &mut self.stages {
+            task.stream_to(Stream::new(path, Some(name)));
+        }
+    }
+
+    /// Whether results of all the stages may be written in the .npz format
+    pub fn npz_supported(&self) -> bool
+    {
+        self.stages.iter().all(|(_, task)| task.npz_supported())
+    }
+
+    /// Arrays of results of all the stages in the .npz format, named with the prefix `name/`
+    /// of a stage, see `Task::npz_arrays`
+    pub fn npz_arrays(&self, result: &ProtocolResult<T>) -> Vec<(String, NpyArray)>
+    where
+        T: NpyElement,
+        T::Real: NpyElement,
+    {
+        self.stages.iter().zip(&result.0).flat_map(|((name, task), (_, result))| {
+            task.npz_arrays(result, &format!("{}/", name))
+        }).collect()
+    }
+
+    /// Metadata of all the stages labeled by names of stages
+    pub fn metadata(&self, result: &ProtocolResult<T>) -> Vec<TaskMetadata>
+    {
+        self.stages.iter().zip(&result.0).map(|((name, task), (_, result))| task.metadata(result, Some(name))).collect()
+    }
+
+    pub fn run(&self, tolerance: f64, acc: T::Real) -> ProtocolResult<T>
+    {
+        let mut handoff = None;
+        let mut results = Vec::with_capacity(self.stages.len());
+        for (name, task) in &self.stages {
+            info!("Running the stage `{}`", name);
+            let (result, state) = task.run(handoff.as_ref(), tolerance, acc);
+            results.push((name.clone(), result));
+            handoff = state;
+        }
+        ProtocolResult(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_complex::Complex64;
+
+    fn protocol(stages: &str) -> Result<Protocol<Complex64>, String>
+    {
+        let config = format!(
+            "!Protocol {{qubits_per_mode: [1, 1], init_state: [1, 0], density_matrices: [[0], [1]], \
+            total_time_steps_number: 10, time_step_size: 0.1, \
+            hamiltonian: [{{ampl: -1, pos: [0, 1], ops: [A+, A-], hc: true}}], stages: {}}}",
+            stages,
+        );
+        Protocol::from_value(serde_yaml::from_str(&config).unwrap())
+    }
+
+    #[test]
+    fn test_protocol()
+    {
+        let quench = protocol(
+            "[!GroundState {name: prepare, hamiltonian: [{ampl: 1, pos: [0], ops: [N1]}], init_state: [0, 1], \
+            krylov_dim: 4, eigenvalues_number: 1, tolerance: 1e-12, max_restarts: 5}, \
+            !ChebyshevDynamics {name: quench, conserve_particle_number: true}, !LindbladDynamics {jump_operators: []}]"
+        ).unwrap();
+        let names: Vec<_> = quench.stages.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["prepare", "quench", "2"]);
+        let ProtocolResult(results) = quench.run(1e-14, 1e-8);
+        // the ground state of n_0 is the particle at the mode 1 which then hops between the modes
+        let TaskResult::LindbladDynamics(result) = &results[2].1 else { panic!("Unexpected result") };
+        let density_matrices = &result.density_matrices;
+        for step in [0, 5, 10] {
+            let time = 1. + 0.1 * step as f64;
+            assert!((density_matrices[0][step][3].re - time.sin().powi(2)).abs() < 1e-8);
+        }
+        assert!(protocol("[!LindbladDynamics {jump_operators: []}, !ChebyshevDynamics {}]").is_err());
+        assert!(protocol("[!ChebyshevDynamics {name: a}, !ChebyshevDynamics {name: a}]").is_err());
+        assert!(protocol("[!ChebyshevDynamics {}, !ChebyshevDynamics {qubits_per_mode: [1, 2]}]").is_err());
+        assert!(protocol("[]").is_err());
+        // the shared time step keys are used by no stage
+        let unused = protocol("[!GroundState {krylov_dim: 4, eigenvalues_number: 1, tolerance: 1e-12, max_restarts: 5}]");
+        assert!(unused.is_err_and(|err| err.contains("time_step_size")));
+    }
+}
@@ -0,0 +1,122 @@
+/// Basis of the sector with a fixed total number of particles. Basis states are
+/// ordered lexicographically by occupation numbers starting from the last mode and
+/// are indexed by the combinatorial ranking, i.e. without any lookup tables of states.
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+)]
+pub struct Sector {
+    local_dims: Vec<usize>,
+    particles_number: usize,
+    // counts[m][n] is the number of configurations of the first m modes with n particles
+    counts: Vec<Vec<usize>>,
+}
+
+impl Sector {
+
+    pub(super) fn new(local_dims: &[usize], particles_number: usize) -> Self
+    {
+        let mut counts = Vec::with_capacity(local_dims.len() + 1);
+        let mut count = vec![0usize; particles_number + 1];
+        count[0] = 1;
+        counts.push(count);
+        for dim in local_dims {
+            let prev = counts.last().unwrap();
+            let count = (0..=particles_number).map(|n| {
+                (0..=std::cmp::min(n, dim - 1)).map(|k| prev[n - k]).sum()
+            }).collect();
+            counts.push(count);
+        }
+        assert!(
+            counts.last().unwrap()[particles_number] > 0,
+            "{} particles do not fit into modes with local dimensions {:?}", particles_number, local_dims,
+        );
+        Sector { local_dims: local_dims.to_owned(), particles_number, counts }
+    }
+
+    pub(super) fn local_dims(&self) -> &[usize]
+    {
+        &self.local_dims
+    }
+
+    pub(super) fn modes_number(&self) -> usize
+    {
+        self.local_dims.len()
+    }
+
+    /// Number of basis states in the sector
+    pub(super) fn size(&self) -> usize
+    {
+        self.counts.last().unwrap()[self.particles_number]
+    }
+
+    /// Index of the basis state with the given occupation numbers,
+    /// None if the state does not belong to the sector.
+    pub(super) fn rank(&self, occupations: &[usize]) -> Option<usize>
+    {
+        let mut remaining = self.particles_number;
+        let mut index = 0;
+        for (m, occupation) in occupations.iter().enumerate().rev() {
+            if *occupation >= self.local_dims[m] || *occupation > remaining {
+                return None;
+            }
+            for k in 0..*occupation {
+                index += self.counts[m][remaining - k];
+            }
+            remaining -= occupation;
+        }
+        if remaining == 0 { Some(index) } else { None }
+    }
+
+    /// Occupation numbers of the basis state with the given index.
+    pub(super) fn unrank(&self, mut index: usize, occupations: &mut [usize])
+    {
+        let mut remaining = self.particles_number;
+        for (m, occupation) in occupations.iter_mut().enumerate().rev() {
+            let mut k = 0;
+            while index >= self.counts[m][remaining - k] {
+                index -= self.counts[m][remaining - k];
+                k += 1;
+            }
+            *occupation = k;
+            remaining -= k;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sector_ranking()
+    {
+        let dims = [2, 3, 2, 8];
+        let sector = Sector::new(&dims, 4);
+        let mut states = Vec::new();
+        for index in 0..dims.iter().product() {
+            let mut occupations = [0; 4];
+            let mut rest = index;
+            for (occupation, dim) in occupations.iter_mut().zip(dims) {
+                *occupation = rest % dim;
+                rest /= dim;
+            }
+            if occupations.iter().sum::<usize>() == 4 {
+                states.push(occupations);
+            } else {
+                assert_eq!(sector.rank(&occupations), None);
+            }
+        }
+        assert_eq!(sector.size(), states.len());
+        let mut indices: Vec<_> = states.iter().map(|state| sector.rank(state).unwrap()).collect();
+        for (state, index) in states.iter().zip(&indices) {
+            let mut occupations = [0; 4];
+            sector.unrank(*index, &mut occupations);
+            assert_eq!(&occupations, state);
+        }
+        indices.sort();
+        assert_eq!(indices, (0..states.len()).collect::<Vec<_>>());
+    }
+}
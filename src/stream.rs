@@ -0,0 +1,107 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use serde::Serialize;
+
+/// A file observables are streamed to as soon as they are computed. It is a sequence of pickles,
+/// one record per time step, which are read by calling `pickle.load` until `EOFError`. Records of
+/// stages of a protocol are labeled by names of stages. A resumed run appends to the same file,
+/// thus records made after the checkpoint are repeated, the later ones replace them.
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    PartialOrd
+)]
+pub struct Stream {
+    path: String,
+    stage: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Record<'a, O> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stage: Option<&'a str>,
+    step: usize,
+    #[serde(flatten)]
+    observables: O,
+}
+
+/// Observables after a time step, the absent ones are not written
+#[derive(Serialize)]
+pub(super) struct StepObservables<'a, T, R> {
+    pub(super) time: R,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(super) density_matrices: Vec<&'a Vec<T>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(super) top_level_populations: Vec<R>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) energy: Option<R>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(super) correlators: Vec<T>,
+}
+
+impl Stream {
+
+    pub fn new(path: &str, stage: Option<&str>) -> Self
+    {
+        Stream { path: path.to_owned(), stage: stage.map(str::to_owned) }
+    }
+
+    /// Creates an empty stream file, an existing one is truncated
+    pub fn create(path: &str) -> Result<(), String>
+    {
+        File::create(path).map(|_| ()).map_err(|err| format!("Unable to create the stream {}: {}", path, err))
+    }
+
+    /// Appends the record of observables after `step` time steps, the whole record
+    /// is written at once, thus only the last record may be incomplete after a crash
+    pub(super) fn append(&self, step: usize, observables: impl Serialize)
+    {
+        let record = Record { stage: self.stage.as_deref(), step, observables };
+        let pickled = serde_pickle::to_vec(&record, Default::default()).expect("Unable to serialize observables");
+        OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(&pickled))
+            .unwrap_or_else(|err| panic!("Unable to write to the stream {}: {}", self.path, err));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_pickle::{Value, HashableValue};
+
+    #[test]
+    fn test_stream()
+    {
+        let path = std::env::temp_dir().join(format!("stream_test_{}.pkl", std::process::id()));
+        let path = path.to_str().unwrap();
+        Stream::create(path).unwrap();
+        let dens = vec![1., 0., 0., 0.];
+        for (step, stage) in [(0, None), (1, Some("quench"))] {
+            let observables = StepObservables {
+                time: step as f64,
+                density_matrices: vec![&dens],
+                top_level_populations: vec![0.5],
+                energy: None,
+                correlators: Vec::<f64>::new(),
+            };
+            Stream::new(path, stage).append(step, observables);
+        }
+        // records are read one after another like by repeated `pickle.load`
+        let file = File::open(path).unwrap();
+        let mut deserializer = serde_pickle::Deserializer::new(file, Default::default());
+        let records: Vec<Value> = (0..2).map(|_| serde::Deserialize::deserialize(&mut deserializer).unwrap()).collect();
+        deserializer.end().unwrap();
+        let Value::Dict(first) = &records[0] else { panic!("Unexpected record") };
+        assert_eq!(first.get(&HashableValue::String("step".to_owned())), Some(&Value::I64(0)));
+        assert!(first.get(&HashableValue::String("stage".to_owned())).is_none());
+        assert!(first.get(&HashableValue::String("correlators".to_owned())).is_none());
+        let Value::Dict(second) = &records[1] else { panic!("Unexpected record") };
+        assert_eq!(second.get(&HashableValue::String("stage".to_owned())), Some(&Value::String("quench".to_owned())));
+        assert_eq!(second.get(&HashableValue::String("time".to_owned())), Some(&Value::F64(1.)));
+        std::fs::remove_file(path).unwrap();
+    }
+}
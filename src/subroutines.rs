@@ -2,40 +2,43 @@ use rayon::prelude::{
     IntoParallelIterator,
     IndexedParallelIterator,
     ParallelIterator,
+    IntoParallelRefIterator,
     IntoParallelRefMutIterator,
 };
 use rayon::ThreadPoolBuilder;
 use num_cpus::get_physical;
+use num_traits::{Float, ToPrimitive};
 use crate::subroutines_utils::{
     get_diagonal,
     get_size,
     get_operator_index,
-    masks_and_offsets,
+    get_offset,
     get_global_offset,
     get_batch_size,
     get_strides,
-    get_masks,
-    get_target_encodings,
+    get_target_dims,
     get_density_size,
     get_batch_index,
-    density_matrix_shifts_and_masks,
-    density_matrix_index_to_state_index,
     Value,
     Term,
+    Op,
 };
+use crate::sector::Sector;
 
-pub(super) fn apply_term<const N: usize, T: Value>(
+pub(super) fn apply_term<T: Value>(
     dst: &mut [T],
     src: &[T],
-    term: &Term<N>,
-    all_encodings: &[usize],
+    term: &Term,
+    local_dims: &[usize],
     delta: T,
 )
 {
-    let size = 2usize.pow(get_size(all_encodings) as u32);
-    let (masks, shifts) = masks_and_offsets(all_encodings, &term.positions);
-    let (diagonal, _) = get_diagonal::<N, T>(term, all_encodings);
-    let offset = get_global_offset(&term, &all_encodings);
+    let size = get_size(local_dims);
+    let modes = term.modes();
+    let strides = get_strides(local_dims, &modes);
+    let target_dims = get_target_dims(local_dims, &modes);
+    let (diagonal, _) = get_diagonal::<T>(term, local_dims);
+    let offset = get_global_offset(term, local_dims);
     let (dst_iter, src_iter, enumerator) = if offset > 0 {
         let offset = offset as usize;
         (
@@ -52,27 +55,79 @@ pub(super) fn apply_term<const N: usize, T: Value>(
         )
     };
     enumerator.zip(dst_iter.zip(src_iter)).for_each(|(index, (dst, src))| {
-        let operator_index = get_operator_index(index, &shifts, &masks);
+        let operator_index = get_operator_index(index, &strides, &target_dims);
         *dst = *dst + delta * *unsafe { diagonal.get_unchecked(operator_index) } * *src;
     });
 }
 
-pub(super) fn get_density<T, const N: usize>(
+/// dst += delta * term^dagger * term * src, the product is diagonal with
+/// the squared absolute values of the term's diagonal on the main diagonal.
+pub(super) fn apply_hermitian_square<T: Value>(
+    dst: &mut [T],
     src: &[T],
-    positions: &[usize; N],
-    all_encodings: &[usize],
+    term: &Term,
+    local_dims: &[usize],
+    delta: T,
+)
+{
+    let size = get_size(local_dims);
+    let modes = term.modes();
+    let strides = get_strides(local_dims, &modes);
+    let target_dims = get_target_dims(local_dims, &modes);
+    let (diagonal, _) = get_diagonal::<T>(term, local_dims);
+    let offset = get_global_offset(term, local_dims);
+    let (start, end) = if offset > 0 {
+        (offset as usize, size)
+    } else {
+        (0, size - (-offset) as usize)
+    };
+    (start..end).into_par_iter().zip((&mut dst[start..end]).into_par_iter().zip(&src[start..end]))
+        .for_each(|(index, (dst, src))| {
+            let operator_index = get_operator_index(index, &strides, &target_dims);
+            let elem = unsafe { *diagonal.get_unchecked(operator_index) };
+            *dst = *dst + delta * elem.conj() * elem * *src;
+        });
+}
+
+/// dst += delta * op * src for a dense operator acting on the modes `positions`
+/// (in the order of digits of its matrix indices), `rows[j]` are the non-zero elements
+/// (k, op[j][k]) of the j-th row of its matrix.
+pub(super) fn apply_dense<T: Value>(
+    dst: &mut [T],
+    src: &[T],
+    positions: &[usize],
+    rows: &[Vec<(usize, T)>],
+    local_dims: &[usize],
+    delta: T,
+)
+{
+    let strides = get_strides(local_dims, positions);
+    let target_dims = get_target_dims(local_dims, positions);
+    let offsets: Vec<_> = (0..rows.len()).map(|k| get_offset(k, &strides, &target_dims)).collect();
+    dst.par_iter_mut().enumerate().for_each(|(index, dst)| {
+        let j = get_operator_index(index, &strides, &target_dims);
+        let batch_index = index - offsets[j];
+        let value = rows[j].iter().fold(T::zero(), |acc, (k, elem)| {
+            acc + *elem * unsafe { *src.get_unchecked(batch_index + offsets[*k]) }
+        });
+        *dst = *dst + delta * value;
+    });
+}
+
+pub(super) fn get_density<T>(
+    src: &[T],
+    positions: &[usize],
+    local_dims: &[usize],
 ) -> Vec<T>
 where
     T: Value,
 {
-    let mut positions = positions.clone();
+    let mut positions = positions.to_owned();
     positions.sort();
-    let batch_size = get_batch_size(&all_encodings, &positions);
-    let strides = get_strides(&all_encodings, &positions);
-    let target_encodings = get_target_encodings(&all_encodings, &positions);
-    let masks = get_masks(&strides);
-    let density_size = get_density_size(all_encodings, &positions);
-    let (density_matrix_masks, shifts) = density_matrix_shifts_and_masks(&all_encodings, &positions);
+    let batch_size = get_batch_size(local_dims, &positions);
+    let strides = get_strides(local_dims, &positions);
+    let target_dims = get_target_dims(local_dims, &positions);
+    let density_size = get_density_size(local_dims, &positions);
     let threads_num = get_physical() + 1;
     let batch_size_per_thread = batch_size / threads_num + 1;
     let thread_pool = ThreadPoolBuilder::new()
@@ -81,6 +136,7 @@ where
         .unwrap();
     let mut density_per_thread = vec![vec![T::zero(); density_size * density_size]; threads_num];
     let mut stack_per_thread = vec![Vec::with_capacity(density_size); threads_num];
+    let (strides, target_dims) = (&strides, &target_dims);
     thread_pool.scope(|s|{
         for (i, (density, stack)) in density_per_thread.iter_mut().zip(&mut stack_per_thread).enumerate() {
             s.spawn(move |_| {
@@ -88,9 +144,9 @@ where
                 let end = std::cmp::min((i + 1) * batch_size_per_thread, batch_size);
                 for index in start..end
                 {
-                    let bi = get_batch_index(index, &masks, &target_encodings);
+                    let bi = get_batch_index(index, strides, target_dims);
                     for j in 0..density_size {
-                        let state_j = density_matrix_index_to_state_index(j, &shifts, &density_matrix_masks);
+                        let state_j = get_offset(j, strides, target_dims);
                         stack.push(unsafe { *src.get_unchecked(state_j + bi) });
                         for k in 0..=j {
                             unsafe {
@@ -124,11 +180,286 @@ where
     }).unwrap()
 }
 
+/// Reduced density matrix of a mixed state, `rho` is a vectorized density matrix
+/// of the doubled system (ket modes followed by bra modes), the layout of the result
+/// is the same as the one of `get_density`.
+pub(super) fn get_mixed_density<T>(
+    rho: &[T],
+    positions: &[usize],
+    local_dims: &[usize],
+) -> Vec<T>
+where
+    T: Value,
+{
+    let mut positions = positions.to_owned();
+    positions.sort();
+    let size = get_size(local_dims);
+    let batch_size = get_batch_size(local_dims, &positions);
+    let strides = get_strides(local_dims, &positions);
+    let target_dims = get_target_dims(local_dims, &positions);
+    let density_size = get_density_size(local_dims, &positions);
+    (0..(density_size * density_size)).into_par_iter().map(|index| {
+        let state_j = get_offset(index / density_size, &strides, &target_dims);
+        let state_k = get_offset(index % density_size, &strides, &target_dims);
+        (0..batch_size).fold(T::zero(), |acc, batch_index| {
+            let bi = get_batch_index(batch_index, &strides, &target_dims);
+            acc + unsafe { *rho.get_unchecked(state_k + bi + size * (state_j + bi)) }
+        })
+    }).collect()
+}
+
+/// Population of the highest allowed level of each mode, i.e. the weight of the state
+/// at the truncation boundary of the local Hilbert spaces.
+pub(super) fn get_top_level_populations<T: Value>(
+    src: &[T],
+    local_dims: &[usize],
+) -> Vec<f64>
+{
+    src.par_iter().enumerate().fold(
+        || vec![0f64; local_dims.len()],
+        |mut populations, (mut index, x)| {
+            let weight = (x.conj() * *x).re().to_f64().unwrap();
+            for (population, dim) in populations.iter_mut().zip(local_dims) {
+                if index % dim == dim - 1 {
+                    *population += weight;
+                }
+                index /= dim;
+            }
+            populations
+        },
+    ).reduce(
+        || vec![0f64; local_dims.len()],
+        |mut lhs, rhs| {
+            for (dst, src) in lhs.iter_mut().zip(rhs) {
+                *dst += src;
+            }
+            lhs
+        },
+    )
+}
+
+/// dst += delta * term * src for state vectors in a sector with a fixed number of particles,
+/// the term must conserve the number of particles. Every element of dst is gathered
+/// from the only element of src connected to it by the term.
+pub(super) fn apply_term_sector<T: Value>(
+    dst: &mut [T],
+    src: &[T],
+    term: &Term,
+    sector: &Sector,
+    delta: T,
+)
+{
+    let local_dims = sector.local_dims();
+    dst.par_iter_mut().enumerate().for_each_init(
+        || vec![0usize; sector.modes_number()],
+        |occupations, (index, dst)| {
+            sector.unrank(index, occupations);
+            // <occupations| op_1 ... op_N is proportional to a single basis bra
+            let mut coeff = 1f64;
+            for (pos, op) in term.positions.iter().zip(&term.op_types) {
+                let occupation = occupations[*pos];
+                if op.is_pauli() {
+                    assert_eq!(local_dims[*pos], 2, "Pauli operators act on two-level modes only");
+                }
+                match op {
+                    Op::Rising | Op::SigmaPlus => {
+                        if occupation == 0 { return; }
+                        coeff *= (occupation as f64).sqrt();
+                        occupations[*pos] -= 1;
+                    },
+                    Op::Lowering | Op::SigmaMinus => {
+                        if occupation + 1 >= local_dims[*pos] { return; }
+                        coeff *= ((occupation + 1) as f64).sqrt();
+                        occupations[*pos] += 1;
+                    },
+                    Op::N => coeff *= occupation as f64,
+                    Op::N2 => coeff *= (occupation * occupation) as f64,
+                    Op::Z => coeff *= 2. * occupation as f64 - 1.,
+                    Op::Parity => if occupation % 2 == 1 { coeff = -coeff },
+                    Op::X | Op::Y | Op::MinusY => unreachable!("Pauli X and Y operators must be expanded"),
+                    Op::FermionCreation | Op::FermionAnnihilation => {
+                        unreachable!("Fermionic operators must be given by the Jordan–Wigner representation")
+                    },
+                }
+            }
+            let src_index = sector.rank(occupations).expect("A term does not conserve the number of particles");
+            *dst = *dst + delta * T::from(coeff).unwrap() * unsafe { *src.get_unchecked(src_index) };
+        },
+    );
+}
+
+/// The same as `apply_dense` for state vectors in a sector with a fixed number of particles,
+/// elements of the operator leading out of the sector are ignored.
+pub(super) fn apply_dense_sector<T: Value>(
+    dst: &mut [T],
+    src: &[T],
+    positions: &[usize],
+    rows: &[Vec<(usize, T)>],
+    sector: &Sector,
+    delta: T,
+)
+{
+    let local_dims = sector.local_dims();
+    dst.par_iter_mut().enumerate().for_each_init(
+        || vec![0usize; sector.modes_number()],
+        |occupations, (index, dst)| {
+            sector.unrank(index, occupations);
+            let j = positions.iter().rev().fold(0, |j, pos| j * local_dims[*pos] + occupations[*pos]);
+            let mut value = T::zero();
+            for (k, elem) in &rows[j] {
+                let mut k = *k;
+                for pos in positions {
+                    occupations[*pos] = k % local_dims[*pos];
+                    k /= local_dims[*pos];
+                }
+                if let Some(src_index) = sector.rank(occupations) {
+                    value = value + *elem * unsafe { *src.get_unchecked(src_index) };
+                }
+            }
+            *dst = *dst + delta * value;
+        },
+    );
+}
+
+/// Reduced density matrix of a state vector from a sector with a fixed number of particles,
+/// the layout of the result is the same as the one of `get_density`.
+pub(super) fn get_density_sector<T>(
+    src: &[T],
+    positions: &[usize],
+    sector: &Sector,
+) -> Vec<T>
+where
+    T: Value,
+{
+    let mut positions = positions.to_owned();
+    positions.sort();
+    let local_dims = sector.local_dims();
+    let density_size: usize = positions.iter().map(|pos| local_dims[*pos]).product();
+    // occupation numbers of the target modes for each index of the density matrix
+    let target_occupations: Vec<Vec<usize>> = (0..density_size).map(|mut index| {
+        let mut occupations = vec![0; positions.len()];
+        for (occupation, pos) in occupations.iter_mut().zip(&positions) {
+            *occupation = index % local_dims[*pos];
+            index /= local_dims[*pos];
+        }
+        occupations
+    }).collect();
+    (0..sector.size()).into_par_iter().fold(
+        || (vec![T::zero(); density_size * density_size], vec![0usize; sector.modes_number()]),
+        |(mut density, mut occupations), index| {
+            sector.unrank(index, &mut occupations);
+            let (j, particles_number) = positions.iter().rev().fold((0, 0), |(j, n), pos| {
+                (j * local_dims[*pos] + occupations[*pos], n + occupations[*pos])
+            });
+            let bra = unsafe { *src.get_unchecked(index) }.conj();
+            for (k, target) in target_occupations.iter().enumerate() {
+                if target.iter().sum::<usize>() != particles_number {
+                    continue;
+                }
+                for (pos, occupation) in positions.iter().zip(target) {
+                    occupations[*pos] = *occupation;
+                }
+                let ket_index = sector.rank(&occupations).unwrap();
+                density[j * density_size + k] = density[j * density_size + k] + bra * unsafe { *src.get_unchecked(ket_index) };
+            }
+            (density, occupations)
+        },
+    ).map(|(density, _)| density).reduce(
+        || vec![T::zero(); density_size * density_size],
+        |mut lhs, rhs| {
+            for (dst, src) in lhs.iter_mut().zip(rhs) {
+                *dst = *dst + src;
+            }
+            lhs
+        },
+    )
+}
+
+/// The same as `get_top_level_populations` for a state vector from a sector
+/// with a fixed number of particles.
+pub(super) fn get_top_level_populations_sector<T: Value>(
+    src: &[T],
+    sector: &Sector,
+) -> Vec<f64>
+{
+    let local_dims = sector.local_dims();
+    src.par_iter().enumerate().fold(
+        || (vec![0f64; local_dims.len()], vec![0usize; local_dims.len()]),
+        |(mut populations, mut occupations), (index, x)| {
+            sector.unrank(index, &mut occupations);
+            let weight = (x.conj() * *x).re().to_f64().unwrap();
+            for ((population, occupation), dim) in populations.iter_mut().zip(&occupations).zip(local_dims) {
+                if *occupation == dim - 1 {
+                    *population += weight;
+                }
+            }
+            (populations, occupations)
+        },
+    ).map(|(populations, _)| populations).reduce(
+        || vec![0f64; local_dims.len()],
+        |mut lhs, rhs| {
+            for (dst, src) in lhs.iter_mut().zip(rhs) {
+                *dst += src;
+            }
+            lhs
+        },
+    )
+}
+
+/// Basis state of a sector with a fixed number of particles given by occupation numbers.
+pub(super) fn init_custom_sector<T: Value>(
+    particles_number_per_mode: &[usize],
+    sector: &Sector,
+) -> Vec<T>
+{
+    let index = sector.rank(particles_number_per_mode)
+        .expect("The initial state does not belong to the sector");
+    let mut state = init_zero_sized(sector.size());
+    state[index] = T::one();
+    state
+}
+
+/// Components of a state vector of the whole space that belong to a sector
+/// with a fixed number of particles.
+pub(super) fn project_to_sector<T: Value>(
+    src: &[T],
+    sector: &Sector,
+) -> Vec<T>
+{
+    let positions: Vec<_> = (0..sector.modes_number()).collect();
+    let strides = get_strides(sector.local_dims(), &positions);
+    (0..sector.size()).into_par_iter().map_init(
+        || vec![0usize; sector.modes_number()],
+        |occupations, index| {
+            sector.unrank(index, occupations);
+            src[occupations.iter().zip(&strides).map(|(occupation, stride)| occupation * stride).sum::<usize>()]
+        },
+    ).collect()
+}
+
+/// State vector of the whole space given by a state vector of a sector
+/// with a fixed number of particles.
+pub(super) fn embed_from_sector<T: Value>(
+    src: &[T],
+    sector: &Sector,
+) -> Vec<T>
+{
+    let positions: Vec<_> = (0..sector.modes_number()).collect();
+    let strides = get_strides(sector.local_dims(), &positions);
+    let mut dst = init_zero(sector.local_dims());
+    let mut occupations = vec![0usize; sector.modes_number()];
+    for (index, x) in src.iter().enumerate() {
+        sector.unrank(index, &mut occupations);
+        dst[occupations.iter().zip(&strides).map(|(occupation, stride)| occupation * stride).sum::<usize>()] = *x;
+    }
+    dst
+}
+
 pub(super) fn init_std<T: Value>(
-    all_encodings: &[usize],
+    local_dims: &[usize],
 ) -> Vec<T>
 {
-    let size = 2usize.pow(get_size(&all_encodings) as u32);
+    let size = get_size(local_dims);
     let mut state = Vec::with_capacity(size);
     unsafe { state.set_len(size) };
     state[1..].par_iter_mut().for_each(|x: &mut T| {
@@ -139,10 +470,10 @@ pub(super) fn init_std<T: Value>(
 }
 
 pub(super) fn init_zero<T: Value>(
-    all_encodings: &[usize],
+    local_dims: &[usize],
 ) -> Vec<T>
 {
-    let size = 2usize.pow(get_size(&all_encodings) as u32);
+    let size = get_size(local_dims);
     let mut state = Vec::with_capacity(size);
     unsafe { state.set_len(size) };
     state.par_iter_mut().for_each(|x: &mut T| {
@@ -183,17 +514,53 @@ pub(super) fn state_cpy<T: Value>(
 
 pub(super) fn init_custom<T: Value>(
     particles_number_per_mode: &[usize],
-    all_encodings: &[usize],
+    local_dims: &[usize],
 ) -> Vec<T>
 {
     let mut index = 0usize;
-    for (particles_num, qubits_num)
-    in particles_number_per_mode[1..].into_iter().zip(all_encodings).rev() {
-        index += *particles_num;
-        index *= 2usize.pow(*qubits_num as u32);
+    for (particles_num, dim)
+    in particles_number_per_mode.iter().zip(local_dims).rev() {
+        assert!(
+            particles_num < dim,
+            "{} particles do not fit into a mode with the local dimension {}", particles_num, dim,
+        );
+        index = index * dim + particles_num;
     }
-    index += particles_number_per_mode[0];
-    let mut state = init_zero(all_encodings);
+    let mut state = init_zero(local_dims);
     state[index] = T::one();
     state
 }
+
+pub(super) fn init_zero_sized<T: Value>(
+    size: usize,
+) -> Vec<T>
+{
+    vec![T::zero(); size]
+}
+
+pub(super) fn scale_inplace<T: Value>(
+    dst: &mut [T],
+    delta: T,
+)
+{
+    dst.par_iter_mut().for_each(|d| {
+        *d = *d * delta;
+    });
+}
+
+pub(super) fn dot<T: Value>(
+    lhs: &[T],
+    rhs: &[T],
+) -> T
+{
+    lhs.into_par_iter().zip(rhs.into_par_iter())
+        .map(|(l, r)| l.conj() * *r)
+        .reduce(|| T::zero(), |acc, x| acc + x)
+}
+
+pub(super) fn norm<T: Value>(
+    src: &[T],
+) -> T::Real
+{
+    Float::sqrt(dot(src, src).re())
+}
@@ -5,27 +5,25 @@ use rand::{thread_rng, Rng};
 
 use crate::subroutines::*;
 use crate::subroutines_utils::{get_size, Term, Op};
+use crate::sector::Sector;
 use crate::test_utils::*;
 
 fn _test_apply_term<const N: usize>(
-    all_encodings: &[usize],
+    local_dims: &[usize],
     positions: [usize; N],
     op_types: [Op; N],
 )
 {
-    let size = 2usize.pow(get_size(all_encodings) as u32);
-    let term = Term {
-        positions,
-        op_types,
-    };
+    let size = get_size(local_dims);
+    let term = Term::new(&positions, &op_types);
     let delta = Complex64::new(0.3, 0.7);
     let mut rng = thread_rng();
     let src: Vec<_> = (0..size).map(|_| Complex64::new(rng.gen(), rng.gen())).collect();
     let mut dst: Vec<_> = (0..size).map(|_| Complex64::new(rng.gen(), rng.gen())).collect();
     let src_clone = src.clone();
     let mut dst_clone = dst.clone();
-    apply_term(&mut dst, &src, &term, &all_encodings, delta);
-    apply_term_test(&mut dst_clone, &src_clone, &term, &all_encodings, delta);
+    apply_term(&mut dst, &src, &term, &local_dims, delta);
+    apply_term_test(&mut dst_clone, &src_clone, &term, &local_dims, delta);
     for (i, (v1, v2)) in dst.into_iter().zip(dst_clone).enumerate() {
         assert!((v1 - v2).abs() < 1e-10, "Iter. number: {}, lhs: {}, rhs: {}", i, v1, v2);
     }
@@ -34,77 +32,131 @@ fn _test_apply_term<const N: usize>(
 #[test]
 fn test_apply_term()
 {
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0], [Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [1], [Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [2], [Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [3], [Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [4], [Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [5], [Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0], [Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [1], [Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [2], [Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [3], [Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [4], [Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [5], [Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0], [Op::N]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [1], [Op::N]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [2], [Op::N]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [3], [Op::N]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [4], [Op::N]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [5], [Op::N]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0], [Op::N2]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [1], [Op::N2]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [2], [Op::N2]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [3], [Op::N2]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [4], [Op::N2]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [5], [Op::N2]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 1], [Op::Rising, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 2], [Op::Lowering, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 3], [Op::Rising, Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 5], [Op::Rising, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [1, 3], [Op::Lowering, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [2, 5], [Op::Rising, Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [3, 4], [Op::Rising, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [4, 5], [Op::Lowering, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 1], [Op::N, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 2], [Op::Lowering, Op::N2]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 3], [Op::Rising, Op::N]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 5], [Op::N2, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [1, 3], [Op::Lowering, Op::N]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 1, 2], [Op::Rising, Op::Lowering, Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 2, 3], [Op::Rising, Op::Lowering, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 3, 5], [Op::Rising, Op::Rising, Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 1, 5], [Op::Rising, Op::Lowering, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [1, 3, 4], [Op::Lowering, Op::Lowering, Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [2, 3, 5], [Op::Rising, Op::Rising, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [1, 3, 4], [Op::Rising, Op::Lowering, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 4, 5], [Op::Lowering, Op::Lowering, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 1, 2], [Op::Rising, Op::Lowering, Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 2, 3], [Op::N, Op::Lowering, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 3, 5], [Op::Rising, Op::N, Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 1, 5], [Op::Rising, Op::Lowering, Op::N]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [1, 3, 4], [Op::N, Op::N2, Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [2, 3, 5], [Op::Rising, Op::N2, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [1, 3, 4], [Op::N, Op::Lowering, Op::N2]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 4, 5], [Op::N, Op::N2, Op::N]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 1, 2, 3], [Op::Rising, Op::Lowering, Op::Rising, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [1, 2, 3, 4], [Op::Rising, Op::Lowering, Op::Lowering, Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 2, 3, 5], [Op::Rising, Op::Rising, Op::Rising, Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 1, 3, 5], [Op::Rising, Op::Lowering, Op::Lowering, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 1, 4, 5], [Op::Lowering, Op::Lowering, Op::Lowering, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 1, 2, 3], [Op::N, Op::Lowering, Op::Rising, Op::N]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [1, 2, 3, 4], [Op::Rising, Op::N2, Op::Lowering, Op::N2]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 2, 3, 5], [Op::Rising, Op::Rising, Op::Rising, Op::Rising]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 1, 3, 5], [Op::N, Op::N, Op::Lowering, Op::Lowering]);
-    _test_apply_term(&[2, 1, 2, 3, 2, 1], [0, 1, 4, 5], [Op::N, Op::Lowering, Op::N2, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0], [Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [1], [Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [2], [Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [3], [Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [4], [Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [5], [Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0], [Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [1], [Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [2], [Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [3], [Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [4], [Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [5], [Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0], [Op::N]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [1], [Op::N]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [2], [Op::N]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [3], [Op::N]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [4], [Op::N]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [5], [Op::N]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0], [Op::N2]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [1], [Op::N2]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [2], [Op::N2]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [3], [Op::N2]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [4], [Op::N2]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [5], [Op::N2]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 1], [Op::Rising, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 2], [Op::Lowering, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 3], [Op::Rising, Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 5], [Op::Rising, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [1, 3], [Op::Lowering, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [2, 5], [Op::Rising, Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [3, 4], [Op::Rising, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [4, 5], [Op::Lowering, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 1], [Op::N, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 2], [Op::Lowering, Op::N2]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 3], [Op::Rising, Op::N]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 5], [Op::N2, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [1, 3], [Op::Lowering, Op::N]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 1, 2], [Op::Rising, Op::Lowering, Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 2, 3], [Op::Rising, Op::Lowering, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 3, 5], [Op::Rising, Op::Rising, Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 1, 5], [Op::Rising, Op::Lowering, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [1, 3, 4], [Op::Lowering, Op::Lowering, Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [2, 3, 5], [Op::Rising, Op::Rising, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [1, 3, 4], [Op::Rising, Op::Lowering, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 4, 5], [Op::Lowering, Op::Lowering, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 1, 2], [Op::Rising, Op::Lowering, Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 2, 3], [Op::N, Op::Lowering, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 3, 5], [Op::Rising, Op::N, Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 1, 5], [Op::Rising, Op::Lowering, Op::N]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [1, 3, 4], [Op::N, Op::N2, Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [2, 3, 5], [Op::Rising, Op::N2, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [1, 3, 4], [Op::N, Op::Lowering, Op::N2]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 4, 5], [Op::N, Op::N2, Op::N]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 1, 2, 3], [Op::Rising, Op::Lowering, Op::Rising, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [1, 2, 3, 4], [Op::Rising, Op::Lowering, Op::Lowering, Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 2, 3, 5], [Op::Rising, Op::Rising, Op::Rising, Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 1, 3, 5], [Op::Rising, Op::Lowering, Op::Lowering, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 1, 4, 5], [Op::Lowering, Op::Lowering, Op::Lowering, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 1, 2, 3], [Op::N, Op::Lowering, Op::Rising, Op::N]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [1, 2, 3, 4], [Op::Rising, Op::N2, Op::Lowering, Op::N2]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 2, 3, 5], [Op::Rising, Op::Rising, Op::Rising, Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 1, 3, 5], [Op::N, Op::N, Op::Lowering, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 1, 4, 5], [Op::N, Op::Lowering, Op::N2, Op::Lowering]);
+    _test_apply_term(&[3, 2, 5, 4, 3, 2], [0], [Op::Rising]);
+    _test_apply_term(&[3, 2, 5, 4, 3, 2], [2], [Op::Lowering]);
+    _test_apply_term(&[3, 2, 5, 4, 3, 2], [4], [Op::N2]);
+    _test_apply_term(&[3, 2, 5, 4, 3, 2], [0, 2], [Op::Rising, Op::Lowering]);
+    _test_apply_term(&[3, 2, 5, 4, 3, 2], [1, 4], [Op::Lowering, Op::Rising]);
+    _test_apply_term(&[3, 2, 5, 4, 3, 2], [0, 2, 4], [Op::Lowering, Op::N, Op::Rising]);
+    _test_apply_term(&[3, 2, 5, 4, 3, 2], [0, 2, 3, 4], [Op::Rising, Op::Lowering, Op::Rising, Op::Lowering]);
+    // repeated positions and terms acting on more than four modes
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [3, 3, 3, 3], [Op::Rising, Op::Rising, Op::Lowering, Op::Lowering]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [3, 3], [Op::Lowering, Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [2, 0, 2], [Op::Lowering, Op::N, Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [4, 1, 4, 4], [Op::Rising, Op::Lowering, Op::N2, Op::Rising]);
+    _test_apply_term(&[4, 2, 4, 8, 4, 2], [0, 1, 2, 3, 4], [Op::Rising, Op::Lowering, Op::Rising, Op::Lowering, Op::N]);
+    _test_apply_term(&[3, 2, 5, 4, 3, 2], [5, 4, 3, 2, 1, 0], [Op::Rising, Op::Lowering, Op::Rising, Op::Lowering, Op::Rising, Op::Lowering]);
+    _test_apply_term(&[3, 2, 5, 4, 3, 2], [2, 3, 2, 3], [Op::Rising, Op::Lowering, Op::Rising, Op::Lowering]);
+}
+
+fn _test_apply_pauli_term<const N: usize>(
+    local_dims: &[usize],
+    positions: [usize; N],
+    op_types: [Op; N],
+)
+{
+    let size = get_size(local_dims);
+    let term = Term::new(&positions, &op_types);
+    let delta = Complex64::new(0.3, 0.7);
+    let mut rng = thread_rng();
+    let src: Vec<_> = (0..size).map(|_| Complex64::new(rng.gen(), rng.gen())).collect();
+    let mut dst: Vec<_> = (0..size).map(|_| Complex64::new(rng.gen(), rng.gen())).collect();
+    let mut dst_clone = dst.clone();
+    for (coeff, expanded_term) in term.expand_paulis() {
+        apply_term(&mut dst, &src, &expanded_term, local_dims, delta * coeff);
+    }
+    apply_term_test(&mut dst_clone, &src, &term, local_dims, delta);
+    for (i, (v1, v2)) in dst.into_iter().zip(dst_clone).enumerate() {
+        assert!((v1 - v2).abs() < 1e-10, "Iter. number: {}, lhs: {}, rhs: {}", i, v1, v2);
+    }
+}
+
+#[test]
+fn test_apply_pauli_term()
+{
+    _test_apply_pauli_term(&[4, 2, 4, 8, 4, 2], [1], [Op::SigmaPlus]);
+    _test_apply_pauli_term(&[4, 2, 4, 8, 4, 2], [5], [Op::SigmaMinus]);
+    _test_apply_pauli_term(&[4, 2, 4, 8, 4, 2], [1], [Op::X]);
+    _test_apply_pauli_term(&[4, 2, 4, 8, 4, 2], [1], [Op::Y]);
+    _test_apply_pauli_term(&[4, 2, 4, 8, 4, 2], [5], [Op::Z]);
+    _test_apply_pauli_term(&[4, 2, 4, 8, 4, 2], [0, 1], [Op::Lowering, Op::SigmaPlus]);
+    _test_apply_pauli_term(&[4, 2, 4, 8, 4, 2], [1, 3], [Op::X, Op::Rising]);
+    _test_apply_pauli_term(&[4, 2, 4, 8, 4, 2], [1, 5], [Op::Y, Op::Y]);
+    _test_apply_pauli_term(&[4, 2, 4, 8, 4, 2], [1, 2, 5], [Op::Z, Op::N, Op::X]);
+    _test_apply_pauli_term(&[4, 2, 4, 8, 4, 2], [1, 1], [Op::X, Op::Y]);
+    _test_apply_pauli_term(&[4, 2, 4, 8, 4, 2], [5, 5, 5], [Op::SigmaPlus, Op::Z, Op::Y]);
+    _test_apply_pauli_term(&[4, 2, 4, 8, 4, 2], [1], [Op::MinusY]);
 }
 
 #[test]
 fn test_init_std()
 {
-    let all_encodings = [3, 2, 1, 2, 3, 2, 1];
-    let size = 2usize.pow(get_size(&all_encodings) as u32);
-    let state = init_std::<Complex64>(&all_encodings);
+    let local_dims = [8, 4, 2, 4, 8, 4, 2];
+    let size = get_size(&local_dims);
+    let state = init_std::<Complex64>(&local_dims);
     assert_eq!(size, state.len());
     for elem in &state[1..] {
         assert!(elem.abs() < 1e-10);
@@ -114,15 +166,15 @@ fn test_init_std()
 
 
 fn _test_get_density<const N: usize>(
-    all_encodings: &[usize],
+    local_dims: &[usize],
     positions: [usize; N],
 )
 {
-    let size = 2usize.pow(get_size(all_encodings) as u32);
+    let size = get_size(local_dims);
     let mut rng = thread_rng();
     let src: Vec<_> = (0..size).map(|_| Complex64::new(rng.gen(), rng.gen())).collect();
-    let test_dens = get_density_test(&src, &positions, &all_encodings);
-    let dens = get_density(&src, &positions, &all_encodings);
+    let test_dens = get_density_test(&src, &positions, &local_dims);
+    let dens = get_density(&src, &positions, &local_dims);
     for (d1, d2) in dens.into_iter().zip(test_dens).skip(200)
     {
         assert!((d1 - d2).abs() < 1e-10);
@@ -132,37 +184,301 @@ fn _test_get_density<const N: usize>(
 #[test]
 fn test_get_density()
 {
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [0]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [1]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [2]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [3]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [4]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [5]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [0]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [1]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [2]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [3]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [4]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [5]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [0, 1]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [0, 2]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [0, 3]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [0, 5]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [1, 3]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [2, 5]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [3, 4]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [4, 5]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [0, 1, 2]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [0, 2, 3]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [0, 3, 5]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [0, 1, 5]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [1, 3, 4]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [2, 3, 5]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [1, 3, 4]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [0, 4, 5]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [0, 1, 2, 3]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [1, 2, 3, 4]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [0, 2, 3, 5]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [0, 1, 3, 5]);
-    _test_get_density(&[2, 1, 2, 3, 2, 1], [0, 1, 4, 5]);
-}
\ No newline at end of file
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [0]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [1]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [2]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [3]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [4]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [5]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [0]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [1]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [2]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [3]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [4]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [5]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [0, 1]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [0, 2]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [0, 3]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [0, 5]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [1, 3]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [2, 5]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [3, 4]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [4, 5]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [0, 1, 2]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [0, 2, 3]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [0, 3, 5]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [0, 1, 5]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [1, 3, 4]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [2, 3, 5]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [1, 3, 4]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [0, 4, 5]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [0, 1, 2, 3]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [1, 2, 3, 4]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [0, 2, 3, 5]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [0, 1, 3, 5]);
+    _test_get_density(&[4, 2, 4, 8, 4, 2], [0, 1, 4, 5]);
+    _test_get_density(&[3, 2, 5, 4, 3, 2], [0]);
+    _test_get_density(&[3, 2, 5, 4, 3, 2], [2]);
+    _test_get_density(&[3, 2, 5, 4, 3, 2], [0, 2]);
+    _test_get_density(&[3, 2, 5, 4, 3, 2], [2, 4]);
+    _test_get_density(&[3, 2, 5, 4, 3, 2], [0, 2, 4]);
+    _test_get_density(&[3, 2, 5, 4, 3, 2], [0, 2, 3, 4]);
+    _test_get_density(&[3, 2, 5, 4, 3, 2], [0, 1, 2, 4, 5]);
+}
+fn _test_apply_hermitian_square<const N: usize>(
+    local_dims: &[usize],
+    positions: [usize; N],
+    op_types: [Op; N],
+)
+{
+    let size = get_size(local_dims);
+    let term = Term::new(&positions, &op_types);
+    let delta = Complex64::new(0.3, 0.7);
+    let mut rng = thread_rng();
+    let src: Vec<_> = (0..size).map(|_| Complex64::new(rng.gen(), rng.gen())).collect();
+    let mut dst: Vec<_> = (0..size).map(|_| Complex64::new(rng.gen(), rng.gen())).collect();
+    let mut aux = vec![Complex64::new(0., 0.); size];
+    let mut dst_clone = dst.clone();
+    apply_hermitian_square(&mut dst, &src, &term, local_dims, delta);
+    apply_term_test(&mut aux, &src, &term, local_dims, Complex64::one());
+    apply_term_test(&mut dst_clone, &aux, &term.transpose(), local_dims, delta);
+    for (i, (v1, v2)) in dst.into_iter().zip(dst_clone).enumerate() {
+        assert!((v1 - v2).abs() < 1e-10, "Iter. number: {}, lhs: {}, rhs: {}", i, v1, v2);
+    }
+}
+
+#[test]
+fn test_apply_hermitian_square()
+{
+    _test_apply_hermitian_square(&[4, 2, 4, 8, 4, 2], [0], [Op::Rising]);
+    _test_apply_hermitian_square(&[4, 2, 4, 8, 4, 2], [3], [Op::Lowering]);
+    _test_apply_hermitian_square(&[4, 2, 4, 8, 4, 2], [5], [Op::N]);
+    _test_apply_hermitian_square(&[4, 2, 4, 8, 4, 2], [0, 3], [Op::Rising, Op::Lowering]);
+    _test_apply_hermitian_square(&[4, 2, 4, 8, 4, 2], [1, 2, 4], [Op::Lowering, Op::N2, Op::Rising]);
+    _test_apply_hermitian_square(&[4, 2, 4, 8, 4, 2], [0, 1, 3, 5], [Op::N, Op::Lowering, Op::Lowering, Op::Rising]);
+    _test_apply_hermitian_square(&[3, 2, 5, 4, 3, 2], [0, 2], [Op::Rising, Op::Lowering]);
+    _test_apply_hermitian_square(&[3, 2, 5, 4, 3, 2], [2, 2, 0], [Op::Lowering, Op::Lowering, Op::Rising]);
+}
+
+fn _test_get_mixed_density<const N: usize>(
+    local_dims: &[usize],
+    positions: [usize; N],
+)
+{
+    let size = get_size(local_dims);
+    let mut rng = thread_rng();
+    let src: Vec<_> = (0..size).map(|_| Complex64::new(rng.gen(), rng.gen())).collect();
+    let rho: Vec<_> = (0..(size * size)).map(|i| src[i % size] * src[i / size].conj()).collect();
+    let test_dens = get_density(&src, &positions, local_dims);
+    let dens = get_mixed_density(&rho, &positions, local_dims);
+    for (d1, d2) in dens.into_iter().zip(test_dens)
+    {
+        assert!((d1 - d2).abs() < 1e-10);
+    }
+}
+
+#[test]
+fn test_get_mixed_density()
+{
+    _test_get_mixed_density(&[4, 2, 2, 4], [0]);
+    _test_get_mixed_density(&[4, 2, 2, 4], [3]);
+    _test_get_mixed_density(&[4, 2, 2, 4], [1, 3]);
+    _test_get_mixed_density(&[4, 2, 2, 4], [0, 1, 2]);
+    _test_get_mixed_density(&[3, 2, 3, 5], [0, 3]);
+}
+
+/// Indices of the sector basis states in the full product space
+fn sector_embedding(sector: &Sector) -> Vec<usize>
+{
+    let mut occupations = vec![0; sector.modes_number()];
+    (0..sector.size()).map(|index| {
+        sector.unrank(index, &mut occupations);
+        occupations.iter().zip(sector.local_dims()).rev().fold(0, |acc, (n, dim)| acc * dim + n)
+    }).collect()
+}
+
+fn _test_apply_term_sector<const N: usize>(
+    local_dims: &[usize],
+    particles_number: usize,
+    positions: [usize; N],
+    op_types: [Op; N],
+)
+{
+    let size = get_size(local_dims);
+    let sector = Sector::new(local_dims, particles_number);
+    let embedding = sector_embedding(&sector);
+    let term = Term::new(&positions, &op_types);
+    let delta = Complex64::new(0.3, 0.7);
+    let mut rng = thread_rng();
+    let src: Vec<_> = (0..sector.size()).map(|_| Complex64::new(rng.gen(), rng.gen())).collect();
+    let mut dst: Vec<_> = (0..sector.size()).map(|_| Complex64::new(rng.gen(), rng.gen())).collect();
+    let mut full_src = vec![Complex64::new(0., 0.); size];
+    let mut full_dst = vec![Complex64::new(0., 0.); size];
+    for (i, j) in embedding.iter().enumerate() {
+        full_src[*j] = src[i];
+        full_dst[*j] = dst[i];
+    }
+    apply_term_sector(&mut dst, &src, &term, &sector, delta);
+    apply_term_test(&mut full_dst, &full_src, &term, local_dims, delta);
+    for (i, (v1, j)) in dst.into_iter().zip(embedding).enumerate() {
+        assert!((v1 - full_dst[j]).abs() < 1e-10, "Iter. number: {}, lhs: {}, rhs: {}", i, v1, full_dst[j]);
+    }
+}
+
+#[test]
+fn test_apply_term_sector()
+{
+    _test_apply_term_sector(&[4, 2, 4, 8, 4, 2], 5, [0], [Op::N]);
+    _test_apply_term_sector(&[4, 2, 4, 8, 4, 2], 5, [3], [Op::N2]);
+    _test_apply_term_sector(&[4, 2, 4, 8, 4, 2], 5, [0, 3], [Op::Rising, Op::Lowering]);
+    _test_apply_term_sector(&[4, 2, 4, 8, 4, 2], 4, [1, 2], [Op::Lowering, Op::Rising]);
+    _test_apply_term_sector(&[4, 2, 4, 8, 4, 2], 7, [1, 2, 4], [Op::Lowering, Op::N2, Op::Rising]);
+    _test_apply_term_sector(&[4, 2, 4, 8, 4, 2], 6, [0, 1, 3, 5], [Op::Rising, Op::Lowering, Op::Lowering, Op::Rising]);
+    _test_apply_term_sector(&[3, 2, 5, 4, 3, 2], 6, [0, 2], [Op::Rising, Op::Lowering]);
+    _test_apply_term_sector(&[3, 2, 5, 4, 3, 2], 6, [2, 2, 2, 2], [Op::Rising, Op::Rising, Op::Lowering, Op::Lowering]);
+    _test_apply_term_sector(&[3, 2, 5, 4, 3, 2], 5, [3, 0, 2, 4, 3], [Op::Rising, Op::Lowering, Op::Rising, Op::Lowering, Op::N]);
+    _test_apply_term_sector(&[4, 2, 4, 8, 4, 2], 5, [0, 1], [Op::Lowering, Op::SigmaPlus]);
+    _test_apply_term_sector(&[4, 2, 4, 8, 4, 2], 5, [1, 1, 5], [Op::Z, Op::SigmaMinus, Op::SigmaPlus]);
+}
+
+fn _test_get_density_sector<const N: usize>(
+    local_dims: &[usize],
+    particles_number: usize,
+    positions: [usize; N],
+)
+{
+    let size = get_size(local_dims);
+    let sector = Sector::new(local_dims, particles_number);
+    let mut rng = thread_rng();
+    let src: Vec<_> = (0..sector.size()).map(|_| Complex64::new(rng.gen(), rng.gen())).collect();
+    let mut full_src = vec![Complex64::new(0., 0.); size];
+    for (i, j) in sector_embedding(&sector).into_iter().enumerate() {
+        full_src[j] = src[i];
+    }
+    let test_dens = get_density_test(&full_src, &positions, local_dims);
+    let dens = get_density_sector(&src, &positions, &sector);
+    assert_eq!(dens.len(), test_dens.len());
+    for (d1, d2) in dens.into_iter().zip(test_dens)
+    {
+        assert!((d1 - d2).abs() < 1e-10);
+    }
+}
+
+#[test]
+fn test_get_density_sector()
+{
+    _test_get_density_sector(&[4, 2, 4, 8, 4, 2], 5, [0]);
+    _test_get_density_sector(&[4, 2, 4, 8, 4, 2], 5, [3]);
+    _test_get_density_sector(&[4, 2, 4, 8, 4, 2], 3, [1, 3]);
+    _test_get_density_sector(&[4, 2, 4, 8, 4, 2], 6, [0, 2, 5]);
+    _test_get_density_sector(&[4, 2, 4, 8, 4, 2], 4, [0, 1, 4, 5]);
+    _test_get_density_sector(&[3, 2, 5, 4, 3, 2], 5, [0, 2]);
+}
+
+#[test]
+fn test_get_top_level_populations()
+{
+    let local_dims = [3, 2, 5, 4];
+    let size = get_size(&local_dims);
+    let mut rng = thread_rng();
+    let src: Vec<_> = (0..size).map(|_| Complex64::new(rng.gen(), rng.gen())).collect();
+    let populations = get_top_level_populations(&src, &local_dims);
+    for (pos, (population, dim)) in populations.into_iter().zip(local_dims).enumerate() {
+        let dens = get_density_test(&src, &[pos], &local_dims);
+        assert!((population - dens[dim * dim - 1].re).abs() < 1e-10);
+    }
+    let sector = Sector::new(&local_dims, 5);
+    let src: Vec<_> = (0..sector.size()).map(|_| Complex64::new(rng.gen(), rng.gen())).collect();
+    let populations = get_top_level_populations_sector(&src, &sector);
+    for (pos, (population, dim)) in populations.into_iter().zip(local_dims).enumerate() {
+        let dens = get_density_sector(&src, &[pos], &sector);
+        assert!((population - dens[dim * dim - 1].re).abs() < 1e-10);
+    }
+}
+
+/// dst += delta * op * src by the summation over all pairs of basis states
+fn apply_dense_test(
+    dst: &mut [Complex64],
+    src: &[Complex64],
+    positions: &[usize],
+    matrix: &[Vec<Complex64>],
+    local_dims: &[usize],
+    delta: Complex64,
+)
+{
+    let occupations = |mut index: usize| -> Vec<usize> {
+        local_dims.iter().map(|dim| {
+            let occupation = index % dim;
+            index /= dim;
+            occupation
+        }).collect()
+    };
+    let target_index = |occupations: &[usize]| {
+        positions.iter().rev().fold(0, |acc, pos| acc * local_dims[*pos] + occupations[*pos])
+    };
+    for (i, dst) in dst.iter_mut().enumerate() {
+        let dst_occupations = occupations(i);
+        for (j, src) in src.iter().enumerate() {
+            let src_occupations = occupations(j);
+            let spectators_match = (0..local_dims.len())
+                .filter(|pos| !positions.contains(pos))
+                .all(|pos| dst_occupations[pos] == src_occupations[pos]);
+            if spectators_match {
+                *dst += delta * matrix[target_index(&dst_occupations)][target_index(&src_occupations)] * src;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_apply_dense()
+{
+    let mut rng = thread_rng();
+    for (local_dims, positions) in [
+        (vec![3, 2, 5, 4], vec![2]),
+        (vec![3, 2, 5, 4], vec![2, 0]),
+        (vec![3, 2, 5, 4], vec![1, 3]),
+        (vec![2, 3, 2, 3], vec![3, 0, 1]),
+    ] {
+        let size = get_size(&local_dims);
+        let dim: usize = positions.iter().map(|pos| local_dims[*pos]).product();
+        // a banded matrix
+        let matrix: Vec<Vec<_>> = (0..dim).map(|j| (0..dim).map(|k| {
+            if j.abs_diff(k) < 3 { Complex64::new(rng.gen(), rng.gen()) } else { Complex64::new(0., 0.) }
+        }).collect()).collect();
+        let rows: Vec<Vec<_>> = matrix.iter().map(|row| row.iter().copied().enumerate().collect()).collect();
+        let delta = Complex64::new(0.3, 0.7);
+        let src: Vec<_> = (0..size).map(|_| Complex64::new(rng.gen(), rng.gen())).collect();
+        let mut dst: Vec<_> = (0..size).map(|_| Complex64::new(rng.gen(), rng.gen())).collect();
+        let mut dst_clone = dst.clone();
+        apply_dense(&mut dst, &src, &positions, &rows, &local_dims, delta);
+        apply_dense_test(&mut dst_clone, &src, &positions, &matrix, &local_dims, delta);
+        for (i, (v1, v2)) in dst.into_iter().zip(dst_clone).enumerate() {
+            assert!((v1 - v2).abs() < 1e-10, "Iter. number: {}, lhs: {}, rhs: {}", i, v1, v2);
+        }
+        // the same in a sector for the part of the matrix conserving the number of particles
+        let particles_number = |mut index: usize| positions.iter().map(|pos| {
+            let occupation = index % local_dims[*pos];
+            index /= local_dims[*pos];
+            occupation
+        }).sum::<usize>();
+        let matrix: Vec<Vec<_>> = matrix.iter().enumerate().map(|(j, row)| row.iter().enumerate().map(|(k, elem)| {
+            if particles_number(j) == particles_number(k) { *elem } else { Complex64::new(0., 0.) }
+        }).collect()).collect();
+        let rows: Vec<Vec<_>> = matrix.iter().map(|row| row.iter().copied().enumerate().collect()).collect();
+        let sector = Sector::new(&local_dims, 4);
+        let embedding = sector_embedding(&sector);
+        let src: Vec<_> = (0..sector.size()).map(|_| Complex64::new(rng.gen(), rng.gen())).collect();
+        let mut dst = vec![Complex64::new(0., 0.); sector.size()];
+        let mut full_src = vec![Complex64::new(0., 0.); size];
+        let mut full_dst = vec![Complex64::new(0., 0.); size];
+        for (i, j) in embedding.iter().enumerate() {
+            full_src[*j] = src[i];
+        }
+        apply_dense_sector(&mut dst, &src, &positions, &rows, &sector, delta);
+        apply_dense_test(&mut full_dst, &full_src, &positions, &matrix, &local_dims, delta);
+        for (i, (v1, j)) in dst.into_iter().zip(embedding).enumerate() {
+            assert!((v1 - full_dst[j]).abs() < 1e-10, "Iter. number: {}, lhs: {}, rhs: {}", i, v1, full_dst[j]);
+        }
+    }
+}
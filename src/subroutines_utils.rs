@@ -60,8 +60,32 @@ pub enum Op {
     N,
     #[serde(rename = "N2")]
     N2,
+    /// Pauli operators acting on two-level modes (qubits), the level 1 is the excited state:
+    /// S+ = |1><0|, S- = |0><1|, Z = |1><1| - |0><0|, X = S+ + S-, Y = -i S+ + i S-
+    #[serde(rename = "S+", alias = "σ+")]
+    SigmaPlus,
+    #[serde(rename = "S-", alias = "σ-")]
+    SigmaMinus,
+    X,
+    Y,
+    Z,
+    /// -Y, appears only in transposed and complex conjugate terms
+    #[serde(rename = "-Y", skip_deserializing)]
+    MinusY,
+    /// Fermionic creation and annihilation operators acting on two-level modes,
+    /// they are represented by S+ and S- with Jordan–Wigner strings
+    #[serde(rename = "F+")]
+    FermionCreation,
+    #[serde(rename = "F-")]
+    FermionAnnihilation,
+    /// Parity (-1)^n, appears only in Jordan–Wigner strings
+    #[serde(rename = "P", skip_deserializing)]
+    Parity,
 }
 
+/// A product of operators acting on modes, operators are applied from right to left.
+/// Operators acting on different modes commute, thus terms are kept stably sorted by
+/// positions, i.e. the order of operators acting on the same mode is preserved.
 #[derive(
     Debug,
     PartialEq,
@@ -69,48 +93,185 @@ pub enum Op {
     PartialOrd,
     Ord,
     Clone,
-    Copy
 )]
-pub struct Term<const N: usize>
+pub struct Term
 {
-    pub positions: [usize; N],
-    pub op_types: [Op; N],
+    pub positions: Vec<usize>,
+    pub op_types: Vec<Op>,
 }
 
-impl<const N: usize> Term<N> {
+impl Term {
+
+    pub(super) fn new(positions: &[usize], op_types: &[Op]) -> Self
+    {
+        assert_eq!(
+            positions.len(), op_types.len(),
+            "Numbers of positions and operators of a term differ: {:?} vs {:?}", positions, op_types,
+        );
+        assert!(!positions.is_empty(), "A term must contain at least one operator");
+        let mut term = Term { positions: positions.to_owned(), op_types: op_types.to_owned() };
+        term.sort();
+        term
+    }
 
     pub(super) fn transpose(&self) -> Self
     {
         let mut transposed_term = self.clone();
+        transposed_term.positions.reverse();
+        transposed_term.op_types.reverse();
         for op in transposed_term.op_types.iter_mut() {
-            match op {
-                Op::Lowering => *op = Op::Rising,
-                Op::Rising => *op = Op::Lowering,
-                Op::N => {},
-                Op::N2 => {},
-            }
+            *op = op.transposed();
         }
+        transposed_term.sort();
         transposed_term
     }
 
-    pub(super) fn sort(&mut self)
+    /// Jordan–Wigner representation of a string of operators given in the original order
+    /// (before sorting by positions), `fermionic_modes` are all the fermionic modes. A fermionic
+    /// operator acting on the mode j turns into the string of parities of fermionic modes k < j
+    /// followed by S+ or S- acting on the mode j.
+    pub(super) fn jordan_wigner(positions: &[usize], op_types: &[Op], fermionic_modes: &[usize]) -> Self
     {
-        let mut pairs = [(0usize, Op::Lowering); N];
-        for ((dst_pos, dst_op_type), (src_pos, src_op_type)) in
-        pairs.iter_mut().zip(self.positions.iter().zip(self.op_types.iter()))
-        {
-            *dst_pos = *src_pos;
-            *dst_op_type = *src_op_type;
+        let mut jw_positions = Vec::with_capacity(positions.len());
+        let mut jw_op_types = Vec::with_capacity(op_types.len());
+        for (pos, op) in positions.iter().zip(op_types) {
+            let sigma = match op {
+                Op::FermionCreation => Op::SigmaPlus,
+                Op::FermionAnnihilation => Op::SigmaMinus,
+                _ => {
+                    jw_positions.push(*pos);
+                    jw_op_types.push(*op);
+                    continue;
+                },
+            };
+            for mode in fermionic_modes.iter().filter(|mode| *mode < pos) {
+                jw_positions.push(*mode);
+                jw_op_types.push(Op::Parity);
+            }
+            jw_positions.push(*pos);
+            jw_op_types.push(sigma);
+        }
+        Term::new(&jw_positions, &jw_op_types)
+    }
+
+    /// Expansion of the term into a sum of terms without Pauli X and Y operators
+    /// (each of them has a single non-zero diagonal) with their coefficients.
+    pub(super) fn expand_paulis(&self) -> Vec<(Complex<f64>, Term)>
+    {
+        let mut expansion = vec![(Complex::new(1., 0.), self.clone())];
+        for (i, op) in self.op_types.iter().enumerate() {
+            let branches = match op {
+                Op::X => [(Complex::new(1., 0.), Op::SigmaPlus), (Complex::new(1., 0.), Op::SigmaMinus)],
+                Op::Y => [(Complex::new(0., -1.), Op::SigmaPlus), (Complex::new(0., 1.), Op::SigmaMinus)],
+                Op::MinusY => [(Complex::new(0., 1.), Op::SigmaPlus), (Complex::new(0., -1.), Op::SigmaMinus)],
+                _ => continue,
+            };
+            expansion = expansion.into_iter().flat_map(|(coeff, term)| {
+                branches.iter().map(move |(branch_coeff, branch_op)| {
+                    let mut term = term.clone();
+                    term.op_types[i] = *branch_op;
+                    (coeff * branch_coeff, term)
+                })
+            }).collect();
         }
-        pairs.sort();
+        expansion
+    }
+
+    pub(super) fn sort(&mut self)
+    {
+        let mut pairs: Vec<_> = self.positions.iter().copied().zip(self.op_types.iter().copied()).collect();
+        pairs.sort_by_key(|(pos, _)| *pos);
         for ((dst_pos, dst_op_type), (src_pos, src_op_type)) in
-        self.positions.iter_mut().zip(&mut self.op_types).zip(pairs.into_iter())
+        self.positions.iter_mut().zip(&mut self.op_types).zip(pairs)
         {
             *dst_pos = src_pos;
             *dst_op_type = src_op_type;
         }
     }
 
+    /// Distinct positions of modes the term acts on in the ascending order
+    pub(super) fn modes(&self) -> Vec<usize>
+    {
+        let mut modes = self.positions.clone();
+        modes.dedup();
+        modes
+    }
+
+    /// Strings of operators acting on each of the distinct modes
+    fn local_strings(&self) -> impl Iterator<Item = (usize, &[Op])> + '_
+    {
+        self.modes().into_iter().scan(0, |start, pos| {
+            let len = self.positions[*start..].iter().take_while(|p| **p == pos).count();
+            let ops = &self.op_types[*start..(*start + len)];
+            *start += len;
+            Some((pos, ops))
+        })
+    }
+
+    /// Change of the number of particles in each of the distinct modes
+    fn local_shifts(&self) -> impl Iterator<Item = (usize, isize)> + '_
+    {
+        self.local_strings().map(|(pos, ops)| {
+            let shift = ops.iter().map(|op| match op {
+                Op::Rising | Op::SigmaPlus => 1,
+                Op::Lowering | Op::SigmaMinus => -1,
+                Op::N | Op::N2 | Op::Z | Op::Parity => 0,
+                Op::X | Op::Y | Op::MinusY => unreachable!("Pauli X and Y operators must be expanded"),
+                Op::FermionCreation | Op::FermionAnnihilation => {
+                    unreachable!("Fermionic operators must be given by the Jordan–Wigner representation")
+                },
+            }).sum();
+            (pos, shift)
+        })
+    }
+}
+
+impl Op {
+    pub(super) fn is_pauli(&self) -> bool
+    {
+        matches!(self, Op::SigmaPlus | Op::SigmaMinus | Op::X | Op::Y | Op::Z | Op::MinusY)
+    }
+
+    pub(super) fn is_fermionic(&self) -> bool
+    {
+        matches!(self, Op::FermionCreation | Op::FermionAnnihilation)
+    }
+
+    pub(super) fn transposed(self) -> Self
+    {
+        match self {
+            Op::Lowering => Op::Rising,
+            Op::Rising => Op::Lowering,
+            Op::SigmaMinus => Op::SigmaPlus,
+            Op::SigmaPlus => Op::SigmaMinus,
+            Op::FermionAnnihilation => Op::FermionCreation,
+            Op::FermionCreation => Op::FermionAnnihilation,
+            Op::Y => Op::MinusY,
+            Op::MinusY => Op::Y,
+            Op::N | Op::N2 | Op::X | Op::Z | Op::Parity => self,
+        }
+    }
+
+    /// Complex conjugate of the operator, Y is the only operator with a non-real matrix
+    pub(super) fn conj(self) -> Self
+    {
+        match self {
+            Op::Y => Op::MinusY,
+            Op::MinusY => Op::Y,
+            _ => self,
+        }
+    }
+}
+
+/// Whether the stable sorting of a string of operators by positions permutes
+/// fermionic operators acting on different modes an odd number of times
+pub(super) fn fermionic_sign(positions: &[usize], op_types: &[Op]) -> bool
+{
+    let fermionic: Vec<_> = positions.iter().zip(op_types).filter(|(_, op)| op.is_fermionic()).map(|(pos, _)| *pos).collect();
+    let inversions = fermionic.iter().enumerate()
+        .map(|(i, lhs)| fermionic[(i + 1)..].iter().filter(|rhs| lhs > *rhs).count())
+        .sum::<usize>();
+    inversions % 2 == 1
 }
 
 // ----------------------------------------------------------------------------------------
@@ -139,281 +300,213 @@ where
 }
 
 #[inline]
-fn get_diagonal_offset<const N: usize>(
-    term: &Term<N>,
-    all_encodings: &[usize],
+fn get_diagonal_offset(
+    term: &Term,
+    local_dims: &[usize],
 ) -> isize
 {
+    let shifts: Vec<_> = term.local_shifts().collect();
     let mut diag_pos = 0isize;
-    for (pos, op) in term.positions.into_iter().zip(term.op_types).rev() {
-        diag_pos *= 2usize.pow(all_encodings[pos] as u32) as isize;
-        match op {
-            Op::Rising => diag_pos -= 1,
-            Op::Lowering => diag_pos += 1,
-            Op::N => {},
-            Op::N2 => {},
-        };
+    for (pos, shift) in shifts.into_iter().rev() {
+        diag_pos *= local_dims[pos] as isize;
+        diag_pos -= shift;
     }
     diag_pos
 }
 
+/// Diagonal of a string of operators acting on a single mode, the i-th element is
+/// the coefficient of the string applied to the i-th level. The string is applied
+/// operator by operator, thus intermediate levels out of the local space give zero.
 #[inline]
-fn diag_per_operator<T: Value>(
-    op: Op,
+fn diag_per_string<T: Value>(
+    ops: &[Op],
     size: usize,
 ) -> Vec<T>
 {
-    match op {
-        Op::Rising => {
-            (1..size).chain(0..1).map(|x| T::from(x).unwrap().sqrt()).collect::<Vec<_>>()
-        },
-        Op::Lowering => {
-            (0..size).map(|x| T::from(x).unwrap().sqrt()).collect::<Vec<_>>()
-        },
-        Op::N => {
-            (0..size).map(|x| T::from(x).unwrap()).collect::<Vec<_>>()
-        }
-        Op::N2 => {
-            (0..size).map(|x| T::from(x).unwrap().powi(2)).collect::<Vec<_>>()
-        }
+    if ops.iter().any(|op| op.is_pauli()) {
+        assert_eq!(size, 2, "Pauli operators act on two-level modes only");
     }
+    (0..size).map(|level| {
+        let mut level = level;
+        let mut value = T::one();
+        for op in ops.iter().rev() {
+            match op {
+                Op::Rising | Op::SigmaPlus => {
+                    level += 1;
+                    if level == size {
+                        return T::zero();
+                    }
+                    value = value * T::from(level).unwrap().sqrt();
+                },
+                Op::Lowering | Op::SigmaMinus => {
+                    if level == 0 {
+                        return T::zero();
+                    }
+                    value = value * T::from(level).unwrap().sqrt();
+                    level -= 1;
+                },
+                Op::N => value = value * T::from(level).unwrap(),
+                Op::N2 => value = value * T::from(level).unwrap().powi(2),
+                Op::Z => value = value * T::from(2. * level as f64 - 1.).unwrap(),
+                Op::Parity => if level % 2 == 1 { value = -value },
+                Op::X | Op::Y | Op::MinusY => unreachable!("Pauli X and Y operators must be expanded"),
+                Op::FermionCreation | Op::FermionAnnihilation => {
+                    unreachable!("Fermionic operators must be given by the Jordan–Wigner representation")
+                },
+            }
+        }
+        value
+    }).collect()
 }
 
 #[inline]
-pub(super) fn get_diagonal<const N: usize, T: Value>(
-    term: &Term<N>,
-    all_encodings: &[usize],
+pub(super) fn get_diagonal<T: Value>(
+    term: &Term,
+    local_dims: &[usize],
 ) -> (Vec<T>, isize)
 {
-    let offset = get_diagonal_offset(term, all_encodings);
-    let mut operands = Vec::with_capacity(N);
-    for (pos, op) in term.positions.into_iter().zip(term.op_types) {
-        operands.push(diag_per_operator(op, 2usize.pow(all_encodings[pos] as u32)));
-    }
+    let offset = get_diagonal_offset(term, local_dims);
+    let operands: Vec<Vec<T>> = term.local_strings()
+        .map(|(pos, ops)| diag_per_string(ops, local_dims[pos]))
+        .collect();
     let diagonal = get_tensor_product(&operands);
     (diagonal, offset)
 }
 
-// ----------------------------------------------------------------------------------------
-
+/// Operator norm of a term, i.e. the largest absolute value on its diagonal,
+/// since a term has a single non-zero diagonal.
 #[inline]
-pub(super) fn masks_and_offsets<const N: usize>(
-    all_encodings: &[usize],
-    terms: &[usize; N],
-) -> ([usize; N], [usize; N])
+pub(super) fn get_operator_norm(
+    term: &Term,
+    local_dims: &[usize],
+) -> f64
 {
-    let mut shifts = [0; N];
-    let mut masks = [0; N];
-    all_encodings.into_iter().fold((0, 0, 0), |(mut iter, mut start_full, mut start_reduced), &x|
-    {
-        for (i, term) in terms.into_iter().enumerate()
-        {
-            if *term == iter {
-                masks[i] = ((1 << x) - 1) << start_full;
-                shifts[i] = start_full - start_reduced;
-                start_reduced += x;
-            }
-        }
-        start_full += x;
-        iter += 1;
-        (iter, start_full, start_reduced)
-    });
-    (masks, shifts)
+    term.local_strings().map(|(pos, ops)| {
+        diag_per_string::<f64>(ops, local_dims[pos])
+            .into_iter()
+            .fold(0., |acc, x| acc.max(x.abs()))
+    }).product()
 }
 
+// ----------------------------------------------------------------------------------------
+
+/// Index of the state of target modes (the first target mode is the least significant digit)
+/// given the index of a state of all modes. `strides` and `target_dims` are the strides
+/// and the local dimensions of the target modes.
 #[inline(always)]
-pub(super) fn get_operator_index<const N: usize>(
+pub(super) fn get_operator_index(
     index: usize,
-    shifts: &[usize; N],
-    masks: &[usize; N],
+    strides: &[usize],
+    target_dims: &[usize],
 ) -> usize
 {
-    let mut operator_index = (masks[0] & index) >> shifts[0];
-    for (mask, shift) in masks.into_iter().zip(shifts.into_iter()).skip(1)
+    let mut operator_index = 0;
+    for (stride, dim) in strides.iter().zip(target_dims).rev()
     {
-        operator_index |= (mask & index) >> shift;
+        operator_index = operator_index * dim + (index / stride) % dim;
     }
     operator_index
 }
 
-#[inline]
-pub(super) fn get_offset<const N: usize>(
-    operator_index: usize,
-    shifts: &[usize; N],
-    masks: &[usize; N],
+/// Index of the state of all modes with the target modes in the state `operator_index`
+/// and the remaining modes in the vacuum, inverse of `get_operator_index`.
+#[inline(always)]
+pub(super) fn get_offset(
+    mut operator_index: usize,
+    strides: &[usize],
+    target_dims: &[usize],
 ) -> usize
 {
-    let mut index = ((masks[0] >> shifts[0]) & operator_index) << shifts[0];
-    for (mask, shift) in masks.into_iter().zip(shifts.into_iter()).skip(1)
+    let mut index = 0;
+    for (stride, dim) in strides.iter().zip(target_dims)
     {
-        index |= ((mask >> shift) & operator_index) << shift;
+        index += (operator_index % dim) * stride;
+        operator_index /= dim;
     }
     index
 }
 
 #[inline]
-pub(super) fn get_global_offset<const N: usize>(
-    term: &Term<N>,
-    all_encodings: &[usize],
+pub(super) fn get_global_offset(
+    term: &Term,
+    local_dims: &[usize],
 ) -> isize
 {
-    let mut all_encodings = all_encodings.to_owned();
-    all_encodings.reverse();
     let mut diag_pos = 0isize;
-    let mut start = 0usize;
-    for (pos, op_type) in term.positions.iter().zip(term.op_types).rev()
+    for (pos, shift) in term.local_shifts()
     {
-        let pos = all_encodings.len() - pos - 1;
-        let dim = 2usize.pow((&all_encodings[start..=pos]).iter().map(|x| *x).sum::<usize>() as u32);
-        diag_pos *= dim as isize;
-        match op_type {
-            Op::Rising => diag_pos -= 1,
-            Op::Lowering => diag_pos += 1,
-            Op::N => {},
-            Op::N2 => {},
-        }
-        start = pos + 1;
-    }
-    if start < all_encodings.len() {
-        let dim = 2usize.pow((&all_encodings[start..]).iter().map(|x| *x).sum::<usize>() as u32);
-        diag_pos *= dim as isize;
+        let stride: usize = local_dims[..pos].iter().product();
+        diag_pos -= shift * stride as isize;
     }
     diag_pos
 }
 
+/// Dimension of the Hilbert space of all modes
 #[inline]
 pub(super) fn get_size(
-    all_encodings: &[usize]
+    local_dims: &[usize]
 ) -> usize
 {
-    all_encodings.into_iter().sum()
+    local_dims.iter().product()
 }
 
 // ---------------------------------------------------------------------------------------
 
 #[inline]
-pub(super) fn get_batch_size<const N: usize>(
-    all_encodings: &[usize],
-    positions: &[usize; N],
+pub(super) fn get_batch_size(
+    local_dims: &[usize],
+    positions: &[usize],
 ) -> usize
 {
-    all_encodings.into_iter()
+    local_dims.iter()
         .enumerate()
-        .filter(|(encoding_pos, _)| {
-            let mut predicate = true;
-            for pos in positions {
-                if *pos == *encoding_pos {
-                    predicate = false;
-                }
-            }
-            predicate
-        })
-        .map(|(_, val)| 2usize.pow(*val as u32))
+        .filter(|(pos, _)| !positions.contains(pos))
+        .map(|(_, dim)| *dim)
         .product()
 }
 
 #[inline]
-pub(super) fn get_strides<const N: usize>(
-    all_encodings: &[usize],
-    positions: &[usize; N],
-) -> [usize; N]
-{
-    let mut strides = [0usize; N];
-    for (stride, pos) in strides.iter_mut().zip(positions.into_iter()) {
-        *stride = all_encodings[..*pos].iter()
-            .map(|x| 2usize.pow(*x as u32))
-            .product();
-    }
-    strides
-}
-
-#[inline]
-pub(super) fn get_target_encodings<const N: usize>(
-    all_encodings: &[usize],
-    positions: &[usize; N],
-) -> [usize; N]
+pub(super) fn get_strides(
+    local_dims: &[usize],
+    positions: &[usize],
+) -> Vec<usize>
 {
-    let mut target_encodings = [0; N];
-    for (pos, encoding)
-    in positions.into_iter().zip(target_encodings.iter_mut())
-    {
-        *encoding = all_encodings[*pos];
-    }
-    target_encodings
+    positions.iter().map(|pos| local_dims[..*pos].iter().product()).collect()
 }
 
 #[inline]
-pub(super) fn get_masks<const N: usize>(sorted_strides: &[usize; N]) -> [usize; N]
+pub(super) fn get_target_dims(
+    local_dims: &[usize],
+    positions: &[usize],
+) -> Vec<usize>
 {
-    let mut masks = [0; N];
-    for (mask, stride) in masks.iter_mut().zip(sorted_strides.iter())
-    {
-        *mask = usize::MAX - (stride - 1);
-    }
-    masks
+    positions.iter().map(|pos| local_dims[*pos]).collect()
 }
 
+/// Index of the state of all modes with the target modes in the vacuum given
+/// the index of a state of the remaining modes. A digit of each target mode is
+/// inserted into `index`, thus strides must be sorted in the ascending order.
 #[inline(always)]
-pub(super) fn get_batch_index<const N: usize>(
+pub(super) fn get_batch_index(
     mut index: usize,
-    sorted_masks: &[usize; N],
-    sorted_target_encodings: &[usize; N],
+    sorted_strides: &[usize],
+    sorted_target_dims: &[usize],
 ) -> usize
 {
-    for (mask, encoding) in sorted_masks.into_iter().zip(sorted_target_encodings)
+    for (stride, dim) in sorted_strides.iter().zip(sorted_target_dims)
     {
-        index = ((mask & index) << encoding) | ((!mask) & index)
+        index = (index / stride) * stride * dim + index % stride;
     }
     index
 }
 
 #[inline]
-pub(super) fn get_density_size<const N: usize>(
-    all_encodings: &[usize],
-    positions: &[usize; N],
+pub(super) fn get_density_size(
+    local_dims: &[usize],
+    positions: &[usize],
 ) -> usize
 {
-    2usize.pow(positions.into_iter().map(|index| all_encodings[*index]).sum::<usize>() as u32)
-}
-
-#[inline]
-pub(super) fn density_matrix_shifts_and_masks<const N: usize>(
-    all_encodings: &[usize],
-    terms: &[usize; N],
-) -> ([usize; N], [usize; N])
-{
-    let mut shifts = [0; N];
-    let mut masks = [0; N];
-    all_encodings.into_iter().fold((0, 0, 0), |(mut iter, mut start_full, mut start_reduced), &x|
-    {
-        for (i, term) in terms.into_iter().enumerate()
-        {
-            if *term == iter {
-                masks[i] = ((1 << x) - 1) << start_reduced;
-                shifts[i] = start_full - start_reduced;
-                start_reduced += x;
-            }
-        }
-        start_full += x;
-        iter += 1;
-        (iter, start_full, start_reduced)
-    });
-    (masks, shifts)
-}
-
-#[inline(always)]
-pub(super) fn density_matrix_index_to_state_index<const N: usize>(
-    index: usize,
-    shifts: &[usize; N],
-    masks: &[usize; N],
-) -> usize
-{
-    let mut operator_index = (masks[0] & index) << shifts[0];
-    for (mask, shift) in masks.into_iter().zip(shifts.into_iter()).skip(1)
-    {
-        operator_index |= (mask & index) << shift;
-    }
-    operator_index
+    positions.iter().map(|pos| local_dims[*pos]).product()
 }
 
 // ---------------------------------------------------------------------------------------
@@ -426,79 +519,94 @@ mod tests {
 
     #[test]
         fn test_dens_matrix_utils() {
-            let all_encodings = [1, 2, 3, 2, 1, 1, 2, 1, 2, 3];
+            let local_dims = [2, 4, 8, 4, 2, 2, 4, 2, 4, 8];
             let positions = [];
-            let size = get_batch_size(&all_encodings, &positions);
+            let size = get_batch_size(&local_dims, &positions);
             assert_eq!(2usize.pow(18), size);
-            let strides = get_strides(&all_encodings, &positions);
-            let target_encodings = get_target_encodings(&all_encodings, &positions);
-            let masks = get_masks(&strides);
-            let batch_index = get_batch_index(usize::MAX, &masks, &target_encodings);
-            assert_eq!(usize::MAX, batch_index);
-            let batch_index = get_batch_index(0usize, &masks, &target_encodings);
+            let strides = get_strides(&local_dims, &positions);
+            let target_dims = get_target_dims(&local_dims, &positions);
+            let batch_index = get_batch_index(0usize, &strides, &target_dims);
             assert_eq!(0, batch_index);
-            let batch_index = get_batch_index(512usize - 1, &masks, &target_encodings);
+            let batch_index = get_batch_index(512usize - 1, &strides, &target_dims);
             assert_eq!(512usize - 1, batch_index);
             // --------------------------------------------------------------------------
-            let all_encodings = [1, 2, 3, 2, 1, 1, 2, 1, 2, 3];
+            let local_dims = [2, 4, 8, 4, 2, 2, 4, 2, 4, 8];
             let mut positions = [5, 2, 8];
             positions.sort();
-            let size = get_batch_size(&all_encodings, &positions);
+            let size = get_batch_size(&local_dims, &positions);
             assert_eq!(2usize.pow(12), size);
-            let strides = get_strides(&all_encodings, &positions);
-            assert_eq!([8, 512, 8192], strides);
-            let target_encodings = get_target_encodings(&all_encodings, &positions);
-            assert_eq!(target_encodings, [3, 1, 2]);
-            let masks = get_masks(&strides);
-            assert_eq!([usize::MAX << 3, usize::MAX << 9, usize::MAX << 13], masks);
-            let batch_index = get_batch_index(usize::MAX, &masks, &target_encodings);
-            assert_eq!(0b1111111111111111111111111111111111111111111111111001110111000111, batch_index);
-            let batch_index = get_batch_index(0usize, &masks, &target_encodings);
+            let strides = get_strides(&local_dims, &positions);
+            assert_eq!(strides, [8, 512, 8192]);
+            let target_dims = get_target_dims(&local_dims, &positions);
+            assert_eq!(target_dims, [8, 2, 4]);
+            let batch_index = get_batch_index(0usize, &strides, &target_dims);
             assert_eq!(0, batch_index);
-            let batch_index = get_batch_index(512usize - 1, &masks, &target_encodings);
+            let batch_index = get_batch_index(512usize - 1, &strides, &target_dims);
             assert_eq!(0b1110111000111, batch_index);
             // --------------------------------------------------------------------------
-            let all_encodings = [1, 2, 3, 2, 1, 1, 2, 1, 2, 3];
+            let local_dims = [2, 4, 8, 4, 2, 2, 4, 2, 4, 8];
             let mut positions = [7, 0, 3, 9];
             positions.sort();
-            let size = get_batch_size(&all_encodings, &positions);
+            let size = get_batch_size(&local_dims, &positions);
             assert_eq!(2usize.pow(11), size);
-            let strides = get_strides(&all_encodings, &positions);
-            assert_eq!([1, 64, 4096, 32768], strides);
-            let target_encodings = get_target_encodings(&all_encodings, &positions);
-            assert_eq!(target_encodings, [1, 2, 1, 3]);
-            let masks = get_masks(&strides);
-            assert_eq!([usize::MAX, usize::MAX << 6, usize::MAX << 12, usize::MAX << 15], masks);
-            let batch_index = get_batch_index(usize::MAX, &masks, &target_encodings);
-            assert_eq!(0b1111111111111111111111111111111111111111111111000110111100111110, batch_index);
-            let batch_index = get_batch_index(512usize - 1, &masks, &target_encodings);
+            let strides = get_strides(&local_dims, &positions);
+            assert_eq!(strides, [1, 64, 4096, 32768]);
+            let target_dims = get_target_dims(&local_dims, &positions);
+            assert_eq!(target_dims, [2, 4, 2, 8]);
+            let batch_index = get_batch_index(512usize - 1, &strides, &target_dims);
             assert_eq!(0b111100111110, batch_index);
-            let batch_index = get_batch_index(0, &masks, &target_encodings);
+            let batch_index = get_batch_index(0, &strides, &target_dims);
             assert_eq!(0, batch_index);
+            // --------------------------------------------------------------------------
+            // batch indices and offsets of target modes enumerate the whole space exactly once
+            let local_dims = [3, 2, 5, 4, 3];
+            let positions = [1, 2, 4];
+            let strides = get_strides(&local_dims, &positions);
+            let target_dims = get_target_dims(&local_dims, &positions);
+            let batch_size = get_batch_size(&local_dims, &positions);
+            let density_size = get_density_size(&local_dims, &positions);
+            assert_eq!(batch_size * density_size, get_size(&local_dims));
+            let (strides, target_dims) = (&strides, &target_dims);
+            let mut indices: Vec<_> = (0..batch_size).flat_map(|batch_index| {
+                let bi = get_batch_index(batch_index, strides, target_dims);
+                assert_eq!(get_operator_index(bi, strides, target_dims), 0);
+                (0..density_size).map(move |j| get_offset(j, strides, target_dims) + bi)
+            }).collect();
+            indices.sort();
+            assert_eq!(indices, (0..get_size(&local_dims)).collect::<Vec<_>>());
         }
 
     #[test]
     fn test_indexing()
     {
-        let (masks, shifts) = masks_and_offsets(
-            &[1, 2, 1, 3, 4, 3, 1, 3],
-            &[3],
-        );
+        let local_dims = [2, 4, 2, 8, 16, 8, 2, 8];
+        let positions = [3];
+        let strides = get_strides(&local_dims, &positions);
+        let target_dims = get_target_dims(&local_dims, &positions);
         let index: usize = 0b000000000000000000000010000;
-        let operator_index = get_operator_index(index, &shifts, &masks);
+        let operator_index = get_operator_index(index, &strides, &target_dims);
         assert_eq!(1, operator_index);
-        let offset = get_offset(operator_index, &shifts, &masks);
+        let offset = get_offset(operator_index, &strides, &target_dims);
         assert_eq!(index, offset);
         // ------------------------------------------------------------
-        let (masks, shifts) = masks_and_offsets(
-            &[1, 2, 1, 3, 4, 3, 1, 3],
-            &[2, 4, 7],
-        );
+        let positions = [2, 4, 7];
+        let strides = get_strides(&local_dims, &positions);
+        let target_dims = get_target_dims(&local_dims, &positions);
         let index: usize = 0b0000111000011110001000;
-        let operator_index = get_operator_index(index, &shifts, &masks);
+        let operator_index = get_operator_index(index, &strides, &target_dims);
         assert_eq!(2usize.pow(8) - 1, operator_index);
-        let offset = get_offset(operator_index, &shifts, &masks);
+        let offset = get_offset(operator_index, &strides, &target_dims);
         assert_eq!(index, offset);
+        // ------------------------------------------------------------
+        let local_dims = [3, 2, 5, 4, 3];
+        let positions = [0, 2, 3];
+        let strides = get_strides(&local_dims, &positions);
+        let target_dims = get_target_dims(&local_dims, &positions);
+        // occupations (2, 1, 3, 2, 1)
+        let index = 2 + 3 * (1 + 2 * (3 + 5 * (2 + 4)));
+        let operator_index = get_operator_index(index, &strides, &target_dims);
+        assert_eq!(2 + 3 * (3 + 5 * 2), operator_index);
+        assert_eq!(2 + 6 * (3 + 5 * 2), get_offset(operator_index, &strides, &target_dims));
     }
 
     #[test]
@@ -529,31 +637,40 @@ mod tests {
 
     #[test]
     fn test_get_offset() {
-        let term = Term {
-            positions: [1, 3, 5],
-            op_types: [Op::Lowering, Op::Rising, Op::Lowering]
-        };
-        let all_encodings = [1, 3, 1, 4, 2, 2, 1];
-        let (masks, shifts) = masks_and_offsets(&all_encodings, &term.positions);
-        let offset = get_offset(123, &shifts, &masks);
-        let index = get_operator_index(offset, &shifts, &masks);
+        let term = Term::new(
+            &[1, 3, 5],
+            &[Op::Lowering, Op::Rising, Op::Lowering],
+        );
+        let local_dims = [2, 8, 2, 16, 4, 4, 2];
+        let strides = get_strides(&local_dims, &term.positions);
+        let target_dims = get_target_dims(&local_dims, &term.positions);
+        let offset = get_offset(123, &strides, &target_dims);
+        let index = get_operator_index(offset, &strides, &target_dims);
         assert_eq!(123, index);
+        assert_eq!(get_global_offset(&term, &local_dims), 2 - 32 + 2048);
+        let term = Term::new(
+            &[3, 1, 3, 3],
+            &[Op::Rising, Op::Lowering, Op::Rising, Op::N],
+        );
+        assert_eq!(get_global_offset(&term, &local_dims), 2 - 2 * 32);
+        assert_eq!(term.positions, [1, 3, 3, 3]);
+        assert_eq!(term.transpose().op_types, [Op::Rising, Op::N, Op::Lowering, Op::Lowering]);
     }
 
-    fn _test_get_diagonal<const N: usize>(
-        term: Term<N>,
-        all_encodings: &[usize],
+    fn _test_get_diagonal(
+        term: Term,
+        local_dims: &[usize],
         true_diag: impl Iterator<Item=Complex64>,
     )
     {
         let transposed_term = term.transpose();
-        let (diag, offset) = get_diagonal::<N, Complex64>(
+        let (diag, offset) = get_diagonal::<Complex64>(
             &term,
-            &all_encodings,
+            local_dims,
         );
-        let (transposed_diag, transposed_offset) = get_diagonal::<N, Complex64>(
+        let (transposed_diag, transposed_offset) = get_diagonal::<Complex64>(
             &transposed_term,
-            &all_encodings,
+            local_dims,
         );
         for (a, b) in true_diag.into_iter().zip(&diag) {
             assert!((a - *b).abs() < 1e-10);
@@ -572,10 +689,10 @@ mod tests {
 
     #[test]
     fn test_get_diagonal() {
-        let term = Term {
-            positions: [1, 3, 5],
-            op_types: [Op::Lowering, Op::Rising, Op::Lowering]
-        };
+        let term = Term::new(
+            &[1, 3, 5],
+            &[Op::Lowering, Op::Rising, Op::Lowering],
+        );
         let true_diag = (0..4).map(|a| Complex64::from(a as f64).sqrt())
             .flat_map(|x| {
                 (1..16).chain(0..1).map(|a| Complex64::from(a as f64).sqrt()).map(move |y| x * y)
@@ -583,12 +700,12 @@ mod tests {
             .flat_map(|x| {
                 (0..8).map(|a| Complex64::from(a as f64).sqrt()).map(move |y| x * y)
             });
-        let all_encodings = [1, 3, 1, 4, 2, 2, 1];
-        _test_get_diagonal(term, &all_encodings, true_diag);
-        let term = Term {
-            positions: [1, 2, 3, 7],
-            op_types: [Op::Rising, Op::Lowering, Op::Rising, Op::Lowering]
-        };
+        let local_dims = [2, 8, 2, 16, 4, 4, 2];
+        _test_get_diagonal(term, &local_dims, true_diag);
+        let term = Term::new(
+            &[1, 2, 3, 7],
+            &[Op::Rising, Op::Lowering, Op::Rising, Op::Lowering],
+        );
         let true_diag = (0..2).map(|a| Complex64::from(a as f64).sqrt())
             .flat_map(|x| {
                 (1..16).chain(0..1).map(|a| Complex64::from(a as f64).sqrt()).map(move |y| x * y)
@@ -599,18 +716,106 @@ mod tests {
             .flat_map(|x| {
                 (1..8).chain(0..1).map(|a| Complex64::from(a as f64).sqrt()).map(move |y| x * y)
             });
-        let all_encodings = [1, 3, 1, 4, 2, 2, 1, 1, 2, 3, 4, 3, 1];
-        _test_get_diagonal(term, &all_encodings, true_diag);
+        let local_dims = [2, 8, 2, 16, 4, 4, 2, 2, 4, 8, 16, 8, 2];
+        _test_get_diagonal(term, &local_dims, true_diag);
+        let term = Term::new(
+            &[0, 2],
+            &[Op::Rising, Op::N],
+        );
+        let true_diag = (0..5).map(|a| Complex64::from(a as f64))
+            .flat_map(|x| {
+                (1..3).chain(0..1).map(|a| Complex64::from(a as f64).sqrt()).map(move |y| x * y)
+            });
+        let local_dims = [3, 2, 5];
+        _test_get_diagonal(term, &local_dims, true_diag);
+        // a^dagger a^dagger a a = n (n - 1) and a^dagger a^dagger a on the same mode
+        let term = Term::new(
+            &[1, 1, 1, 1],
+            &[Op::Rising, Op::Rising, Op::Lowering, Op::Lowering],
+        );
+        let true_diag = (0..5).map(|a| Complex64::from((a * (a - 1)) as f64));
+        let local_dims = [3, 5];
+        _test_get_diagonal(term, &local_dims, true_diag);
+        let term = Term::new(
+            &[1, 1, 1],
+            &[Op::Rising, Op::Rising, Op::Lowering],
+        );
+        let true_diag = (0..5).map(|a| if a == 4 { 0. } else { a as f64 * ((a + 1) as f64).sqrt() })
+            .map(Complex64::from);
+        _test_get_diagonal(term, &local_dims, true_diag);
+    }
+
+    #[test]
+    fn test_get_operator_norm() {
+        let term = Term::new(
+            &[1, 3, 5],
+            &[Op::Lowering, Op::N, Op::N2],
+        );
+        let local_dims = [2, 8, 2, 16, 4, 4, 2];
+        assert!((get_operator_norm(&term, &local_dims) - 7f64.sqrt() * 15. * 9.).abs() < 1e-10);
+        let local_dims = [2, 6, 2, 5, 4, 3, 2];
+        assert!((get_operator_norm(&term, &local_dims) - 5f64.sqrt() * 4. * 4.).abs() < 1e-10);
+        let term = Term::new(
+            &[3, 1, 3],
+            &[Op::Lowering, Op::N, Op::Rising],
+        );
+        assert!((get_operator_norm(&term, &local_dims) - 5. * 4.).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_pauli_operators() {
+        let term = Term::new(&[2, 0], &[Op::Y, Op::X]);
+        assert_eq!(term.transpose(), Term::new(&[0, 2], &[Op::X, Op::MinusY]));
+        assert_eq!(term.transpose().transpose(), term);
+        let expansion = term.expand_paulis();
+        assert_eq!(expansion.len(), 4);
+        assert_eq!(expansion[3], (Complex::new(0., 1.), Term::new(&[0, 2], &[Op::SigmaMinus, Op::SigmaMinus])));
+        let term = Term::new(&[1, 1], &[Op::Z, Op::SigmaPlus]);
+        let true_diag = [Complex64::from(1.), Complex64::from(0.)];
+        _test_get_diagonal(term.clone(), &[3, 2], true_diag.into_iter());
+        assert!((get_operator_norm(&term, &[3, 2]) - 1.).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_jordan_wigner() {
+        let fermionic_modes = [0, 2, 3];
+        let term = Term::jordan_wigner(&[3, 1, 0], &[Op::FermionCreation, Op::N, Op::FermionAnnihilation], &fermionic_modes);
+        assert_eq!(term, Term::new(
+            &[0, 2, 3, 1, 0],
+            &[Op::Parity, Op::Parity, Op::SigmaPlus, Op::N, Op::SigmaMinus],
+        ));
+        assert!(!fermionic_sign(&[0, 3], &[Op::FermionCreation, Op::FermionAnnihilation]));
+        assert!(fermionic_sign(&[3, 1, 0], &[Op::FermionCreation, Op::N, Op::FermionAnnihilation]));
+        assert!(fermionic_sign(&[3, 2, 0], &[Op::FermionCreation, Op::FermionCreation, Op::FermionAnnihilation]));
+        assert!(!fermionic_sign(&[2, 2, 0], &[Op::FermionCreation, Op::FermionAnnihilation, Op::FermionAnnihilation]));
+        // c_0 c_3^dagger = - c_3^dagger c_0
+        let lhs = Term::jordan_wigner(&[0, 3], &[Op::FermionAnnihilation, Op::FermionCreation], &fermionic_modes);
+        let rhs = Term::jordan_wigner(&[3, 0], &[Op::FermionCreation, Op::FermionAnnihilation], &fermionic_modes);
+        let local_dims = [2, 3, 2, 2];
+        let (lhs_diag, lhs_offset) = get_diagonal::<Complex64>(&lhs, &local_dims);
+        let (rhs_diag, rhs_offset) = get_diagonal::<Complex64>(&rhs, &local_dims);
+        assert_eq!(lhs_offset, rhs_offset);
+        assert!(lhs_diag.iter().any(|x| x.norm() > 0.5));
+        for (l, r) in lhs_diag.iter().zip(&rhs_diag) {
+            assert!((l + r).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_pauli_operator_dimension() {
+        get_operator_norm(&Term::new(&[1], &[Op::Z]), &[2, 3]);
     }
 
     #[test]
     fn test_density_matrix_indexing()
     {
-        let all_encodings = [1, 4, 1, 3, 2, 1, 5];
+        let local_dims = [2, 16, 2, 8, 4, 2, 32];
         let positions = [1, 3, 6];
-        let (masks, shifts) = density_matrix_shifts_and_masks(&all_encodings, &positions);
+        let strides = get_strides(&local_dims, &positions);
+        let target_dims = get_target_dims(&local_dims, &positions);
         let index = 0b100011011001;
-        let state_index = density_matrix_index_to_state_index(index, &shifts, &masks);
+        let state_index = get_offset(index, &strides, &target_dims);
         assert_eq!(0b10001000101010010, state_index);
     }
 }
\ No newline at end of file
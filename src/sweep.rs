@@ -0,0 +1,249 @@
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use serde_yaml::{Value as YamlValue, Mapping};
+
+/// Full state vectors of at most this size are small enough
+/// to run several points of a sweep concurrently
+pub(super) const SMALL_STATE_SIZE: usize = 1 << 14;
+
+/// How values of parameters are combined into points of a sweep
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+)]
+pub enum SweepMode {
+    /// All combinations of values of parameters (the Cartesian product)
+    #[default]
+    Product,
+    /// Values of parameters taken together at the same index, lists of values must be of equal length
+    Zip,
+}
+
+/// A parameter of a sweep, `path` is a path to a value in a task config given by keys
+/// and indices of lists separated by dots, e.g. `time_step_size`, `lattice.U` or `hamiltonian.2.ampl`
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+pub struct SweepParameter {
+    path: String,
+    values: Vec<YamlValue>,
+}
+
+/// The `sweep` section of a task config, the task is run for each point of the sweep
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+pub struct Sweep {
+    #[serde(default)]
+    mode: SweepMode,
+    parameters: Vec<SweepParameter>,
+}
+
+/// A point of a sweep, i.e. values of parameters and the task config with these values substituted
+pub(super) struct SweepPoint {
+    pub(super) parameters: Mapping,
+    pub(super) config: YamlValue,
+}
+
+/// A record of the index of sweep results
+#[derive(
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+pub(super) struct SweepRecord {
+    pub(super) parameters: Mapping,
+    pub(super) result: String,
+}
+
+impl Sweep {
+
+    /// Indices of values of parameters for each point of the sweep
+    fn indices(&self) -> Result<Vec<Vec<usize>>, String>
+    {
+        let lengths: Vec<_> = self.parameters.iter().map(|parameter| parameter.values.len()).collect();
+        if lengths.is_empty() || lengths.contains(&0) {
+            return Err("A sweep must have parameters with non-empty lists of values".to_owned());
+        }
+        match self.mode {
+            SweepMode::Zip => {
+                if lengths.iter().any(|length| *length != lengths[0]) {
+                    return Err(format!("Parameters of a `Zip` sweep must have equal numbers of values, got {:?}", lengths));
+                }
+                Ok((0..lengths[0]).map(|i| vec![i; lengths.len()]).collect())
+            },
+            SweepMode::Product => {
+                // the last parameter changes the fastest
+                Ok(lengths.iter().fold(vec![Vec::new()], |points, length| {
+                    points.into_iter().flat_map(|point| {
+                        (0..*length).map(move |i| {
+                            let mut point = point.clone();
+                            point.push(i);
+                            point
+                        })
+                    }).collect()
+                }))
+            },
+        }
+    }
+}
+
+/// Replaces the value at the path in a config, the last key of the path may be missing in a map
+fn set_value(config: &mut YamlValue, path: &str, value: YamlValue) -> Result<(), String>
+{
+    let mut node = config;
+    let keys: Vec<_> = path.split('.').collect();
+    for (i, key) in keys.iter().enumerate() {
+        if let YamlValue::Tagged(tagged) = node {
+            node = &mut tagged.value;
+        }
+        let last = i + 1 == keys.len();
+        node = match node {
+            YamlValue::Mapping(mapping) => {
+                if last && !mapping.contains_key(*key) {
+                    mapping.insert((*key).into(), YamlValue::Null);
+                }
+                mapping.get_mut(*key)
+                    .ok_or_else(|| format!("The key `{}` of the sweep parameter `{}` is not found", key, path))?
+            },
+            YamlValue::Sequence(sequence) => key.parse::<usize>().ok()
+                .and_then(|index| sequence.get_mut(index))
+                .ok_or_else(|| format!("The index `{}` of the sweep parameter `{}` is out of range", key, path))?,
+            _ => return Err(format!("The sweep parameter `{}` does not point into a map or a list", path)),
+        };
+    }
+    *node = value;
+    Ok(())
+}
+
+/// Suffixes checkpoint paths of a task config, or of stages of a protocol, by the index of
+/// a sweep point, so that points run concurrently do not overwrite checkpoints of each other
+fn suffix_checkpoint_paths(config: &mut YamlValue, i: usize)
+{
+    let task = match config {
+        YamlValue::Tagged(tagged) => &mut tagged.value,
+        other => other,
+    };
+    if let Some(YamlValue::String(path)) = task.get_mut("checkpoint").and_then(|checkpoint| checkpoint.get_mut("path")) {
+        *path = point_result_path(path, i);
+    }
+    if let Some(YamlValue::Sequence(stages)) = task.get_mut("stages") {
+        stages.iter_mut().for_each(|stage| suffix_checkpoint_paths(stage, i));
+    }
+}
+
+/// Splits a task config with the `sweep` section into configs of all points of the sweep,
+/// None if there is no sweep. Checkpoint paths are suffixed by indices of points.
+pub(super) fn expand_sweep(config: &YamlValue) -> Result<Option<Vec<SweepPoint>>, String>
+{
+    let mut config = config.clone();
+    let task = match &mut config {
+        YamlValue::Tagged(tagged) => &mut tagged.value,
+        other => other,
+    };
+    let Some(sweep) = task.as_mapping_mut().and_then(|task| task.remove("sweep")) else {
+        return Ok(None);
+    };
+    let sweep: Sweep = serde_yaml::from_value(sweep).map_err(|err| format!("Invalid `sweep` section: {}", err))?;
+    let points = sweep.indices()?.into_iter().enumerate().map(|(i, indices)| {
+        let mut point = SweepPoint { parameters: Mapping::new(), config: config.clone() };
+        for (parameter, i) in sweep.parameters.iter().zip(indices) {
+            let value = parameter.values[i].clone();
+            set_value(&mut point.config, &parameter.path, value.clone())?;
+            point.parameters.insert(parameter.path.as_str().into(), value);
+        }
+        suffix_checkpoint_paths(&mut point.config, i);
+        Ok(point)
+    }).collect::<Result<_, String>>()?;
+    Ok(Some(points))
+}
+
+impl SweepPoint {
+
+    /// Whether the full state vector of the point is small, the local dimensions
+    /// are taken from `qubits_per_mode` or `max_occupation`
+    pub(super) fn is_small(&self) -> bool
+    {
+        let task = match &self.config {
+            YamlValue::Tagged(tagged) => &tagged.value,
+            other => other,
+        };
+        let dims = |key: &str, dim: fn(u64) -> Option<usize>| {
+            task.get(key).and_then(|dims| dims.as_sequence()).map(|dims| {
+                dims.iter().map(|value| value.as_u64().and_then(dim)).product::<Option<usize>>()
+            })
+        };
+        let size = dims("qubits_per_mode", |q| 1usize.checked_shl(q as u32))
+            .or_else(|| dims("max_occupation", |m| Some(m as usize + 1)))
+            .flatten();
+        size.is_some_and(|size| size <= SMALL_STATE_SIZE)
+    }
+}
+
+/// Path of the result of the i-th point of a sweep, e.g. `result_3.pkl` for `result.pkl`
+pub(super) fn point_result_path(result_path: &str, i: usize) -> String
+{
+    let path = Path::new(result_path);
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("result");
+    let name = match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => format!("{}_{}.{}", stem, i, ext),
+        None => format!("{}_{}", stem, i),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep()
+    {
+        let expand_sweep = |config: &str| expand_sweep(&serde_yaml::from_str(config).unwrap());
+        let config = "!Task {qubits_per_mode: [1, 2], init_state: [0, 1], time_step_size: 0.1, \
+            lattice: {geometry: !Chain 2, U: 1}, \
+            sweep: {parameters: [{path: lattice.U, values: [1, 2, 3]}, {path: init_state, values: [[1, 0], [0, 3]]}]}}";
+        let points = expand_sweep(config).unwrap().unwrap();
+        assert_eq!(points.len(), 6);
+        let YamlValue::Tagged(task) = &points[3].config else { panic!("Unexpected config") };
+        assert!(task.value.get("sweep").is_none());
+        assert_eq!(task.value["lattice"]["U"], YamlValue::from(2));
+        assert_eq!(task.value["init_state"], serde_yaml::from_str::<YamlValue>("[0, 3]").unwrap());
+        assert_eq!(points[3].parameters["lattice.U"], YamlValue::from(2));
+        assert!(points[3].is_small());
+        let config = "{qubits_per_mode: [10, 10], hamiltonian: [{ampl: 1, pos: [0], ops: [N1]}], \
+            sweep: {mode: Zip, parameters: [{path: hamiltonian.0.ampl, values: [1, 2]}, {path: time_step_size, values: [0.1, 0.2]}]}}";
+        let points = expand_sweep(config).unwrap().unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[1].config["hamiltonian"][0]["ampl"], YamlValue::from(2));
+        assert_eq!(points[1].config["time_step_size"], YamlValue::from(0.2));
+        assert!(!points[1].is_small());
+        let config = "{sweep: {mode: Zip, parameters: [{path: a, values: [1, 2]}, {path: b, values: [1]}]}}";
+        assert!(expand_sweep(config).is_err());
+        let config = "{hamiltonian: [], sweep: {parameters: [{path: hamiltonian.0.ampl, values: [1]}]}}";
+        assert!(expand_sweep(config).is_err());
+        assert!(expand_sweep("{time_step_size: 0.1}").unwrap().is_none());
+        let config = "!Protocol {checkpoint: {path: run.pkl, every: 2}, stages: [!ChebyshevDynamics {checkpoint: {path: stage.pkl, every: 2}}], \
+            sweep: {parameters: [{path: time_step_size, values: [0.1, 0.2]}]}}";
+        let points = expand_sweep(config).unwrap().unwrap();
+        let YamlValue::Tagged(task) = &points[1].config else { panic!("Unexpected config") };
+        assert_eq!(task.value["checkpoint"]["path"], YamlValue::from("run_1.pkl"));
+        let YamlValue::Tagged(stage) = &task.value["stages"][0] else { panic!("Unexpected config") };
+        assert_eq!(stage.value["checkpoint"]["path"], YamlValue::from("stage_1.pkl"));
+        assert_eq!(point_result_path("out/result.pkl", 3), "out/result_3.pkl");
+    }
+}
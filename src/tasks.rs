@@ -1,22 +1,65 @@
 use std::fmt::Debug;
-use num_traits::Zero;
+use std::cell::RefCell;
+use num_traits::{Zero, One, Float, NumCast, ToPrimitive};
 use num_complex::ComplexFloat;
-use serde::{Serialize, Deserialize};
-use log::error;
+use serde::{Serialize, Deserialize, Deserializer, de::Error as _};
+use log::{error, info, warn};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use num_cpus::get_physical;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use indicatif::ProgressIterator;
 
-use crate::chebyshev::{cheb_exp, FromComplex64};
-use crate::subroutines_utils::{TrueComplex, Value, Op, Term};
+use crate::chebyshev::{cheb_exp, cheb_exp_real, cheb_exp_unitary, unitary_order, exp_order, real_order, FromComplex64};
+use crate::lanczos::{lanczos, lanczos_bounds};
+use crate::kpm::{KPM_EPSILON, chebyshev_moments, chebyshev_nodes, spectral_density};
+use crate::amplitude::Amplitude;
+use crate::sector::Sector;
+use crate::dense::{DenseOperator, expand_dense_operators};
+use crate::lattice::expand_lattice;
+use crate::checkpoint::{Checkpoint, CheckpointPolicy, deserialize_checkpoint_policy, load_state, config_hash};
+use crate::stream::{Stream, StepObservables};
+use crate::npz::{NpyArray, NpyElement};
+use crate::metadata::TaskMetadata;
+use crate::subroutines_utils::{TrueComplex, Value, Op, Term, get_operator_norm, fermionic_sign, get_size};
 use crate::subroutines::{
     init_std,
     apply_term,
+    apply_hermitian_square,
+    apply_dense,
+    apply_dense_sector,
     add_inplace,
     get_density,
+    get_mixed_density,
+    apply_term_sector,
+    get_density_sector,
+    get_top_level_populations,
+    get_top_level_populations_sector,
+    init_custom_sector,
+    init_zero_sized,
     init_zero,
     set2zero,
     init_custom,
+    project_to_sector,
+    embed_from_sector,
+    dot,
+    norm,
+    scale_inplace,
 };
 
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+    PartialOrd
+)]
+/// Positions of modes of a reduced density matrix, any number of distinct modes.
+#[serde(transparent)]
+struct DensPositions(Vec<usize>);
+
+/// The initial state of a task, either a Fock state given by occupation numbers of modes
+/// or the path of a checkpoint file whose state vector is taken.
 #[derive(
     Deserialize,
     Serialize,
@@ -26,11 +69,34 @@ use crate::subroutines::{
     PartialOrd
 )]
 #[serde(untagged)]
-enum DensEnum {
-    One([usize; 1]),
-    Two([usize; 2]),
-    Three([usize; 3]),
-    Four([usize; 4]),
+pub enum InitState {
+    Fock(Vec<usize>),
+    Checkpoint(String),
+}
+
+impl InitState {
+
+    /// Occupation numbers of the Fock state, panics if the state is given by a checkpoint
+    /// that must have been loaded with `load` beforehand
+    fn fock(&self) -> &[usize]
+    {
+        match self {
+            InitState::Fock(occupations) => occupations,
+            InitState::Checkpoint(path) => panic!("The initial state from the checkpoint {} is not loaded", path),
+        }
+    }
+
+    /// The state vector of the checkpoint, None for a Fock state
+    fn load<T: for<'a > Deserialize<'a>>(&self) -> Option<StageState<T>>
+    {
+        match self {
+            InitState::Fock(_) => None,
+            InitState::Checkpoint(path) => {
+                info!("The initial state is loaded from the checkpoint {}", path);
+                Some(StageState::Pure(load_state(path).expect("Unable to load the initial state")))
+            },
+        }
+    }
 }
 
 #[derive(
@@ -41,30 +107,203 @@ enum DensEnum {
     PartialEq,
     PartialOrd
 )]
-#[serde(untagged)]
-pub enum TermAndAmpl<T: ComplexFloat> {
-    One {
-        ampl: T::Real,
-        pos: [usize; 1],
-        ops: [Op; 1],
-    },
-    Two {
-        ampl: T::Real,
-        pos: [usize; 2],
-        ops: [Op; 2],
-    },
-    Three {
-        ampl: T::Real,
-        pos: [usize; 3],
-        ops: [Op; 3],
-    },
-    Four {
-        ampl: T::Real,
-        pos: [usize; 4],
-        ops: [Op; 4],
-    },
+/// A term of an operator given by its amplitude, positions of modes and operators acting on them.
+/// The operators form a string of any length applied from right to left, several operators
+/// may act on the same mode, e.g. `pos: [0, 0, 0, 0], ops: [A+, A+, A-, A-]` is a^dagger a^dagger a a.
+/// Two-level modes (`qubits_per_mode: 1` or `max_occupation: 1`) may be acted on by Pauli operators
+/// `X`, `Y`, `Z`, `S+` and `S-` (also `σ+` and `σ-`), e.g. `pos: [0, 1], ops: [A+, S-]` is a^dagger sigma^-
+/// of the Jaynes–Cummings model, the level 1 of a qubit is the excited state with Z = +1.
+/// Fermionic creation and annihilation operators `F+` and `F-` act on two-level modes as well, all
+/// the modes they act on in any term of a task are fermionic and the Jordan–Wigner strings run over
+/// them in the order of positions, e.g. `pos: [0, 2], ops: [F+, F-]` is c_0^dagger c_2. Fermionic
+/// modes may be acted on only by `F+`, `F-`, `N1`, `N2` and `Z`.
+/// Instead of `ops`, a term may refer to a dense operator acting on the modes `pos` by `dense: name`,
+/// where `name` is defined in the `dense_operators` section of a config.
+/// If `hc` is set, the Hermitian conjugate of the term is added to a Hamiltonian as well.
+#[serde(bound(
+    serialize = "T::Real: Serialize",
+    deserialize = "T::Real: Deserialize<'de>",
+))]
+pub struct TermAndAmpl<T: ComplexFloat> {
+    ampl: Amplitude<T::Real>,
+    pos: Vec<usize>,
+    #[serde(default)]
+    ops: Vec<Op>,
+    #[serde(default)]
+    dense: Option<DenseOperator<T::Real>>,
+    #[serde(default)]
+    hc: bool,
+    #[serde(skip)]
+    fermionic_modes: Vec<usize>,
+}
+
+/// Scheme of the propagation over a time step for time-dependent Hamiltonians.
+/// `Midpoint` is the exponential midpoint rule (second order), `Magnus4` is the fourth order
+/// commutator-free Magnus scheme with two exponentials per step. For time-independent Hamiltonians
+/// a single exponential per step is used irrespectively of the scheme.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    PartialOrd
+)]
+pub enum Propagator {
+    #[default]
+    Midpoint,
+    Magnus4,
+}
+
+impl Propagator {
+    /// Exponentials approximating the propagator over the time step [time, time + time_step_size]
+    /// in the order of application, each exponential is given by a list of (time, weight) pairs
+    /// defining its exponent sum_k weight_k H(time_k)
+    fn exponents<R: Float>(&self, time: R, time_step_size: R, time_dependent: bool) -> Vec<Vec<(R, R)>>
+    {
+        let half = R::from(0.5).unwrap();
+        if !time_dependent {
+            return vec![vec![(time, R::one())]];
+        }
+        match self {
+            Propagator::Midpoint => vec![vec![(time + half * time_step_size, R::one())]],
+            Propagator::Magnus4 => {
+                let sqrt3 = R::from(3f64.sqrt()).unwrap();
+                let twelve = R::from(12.).unwrap();
+                let six = R::from(6.).unwrap();
+                let alpha1 = (R::from(3.).unwrap() - R::from(2.).unwrap() * sqrt3) / twelve;
+                let alpha2 = (R::from(3.).unwrap() + R::from(2.).unwrap() * sqrt3) / twelve;
+                let time1 = time + (half - sqrt3 / six) * time_step_size;
+                let time2 = time + (half + sqrt3 / six) * time_step_size;
+                vec![
+                    vec![(time1, alpha2), (time2, alpha1)],
+                    vec![(time1, alpha1), (time2, alpha2)],
+                ]
+            },
+        }
+    }
+}
+
+/// Bounds of the spectrum of a Hamiltonian used to map it onto [-1, 1] for Chebyshev expansions.
+/// `TermNorms` (default) sums operator norms of all terms at every time, `Lanczos(steps)` runs
+/// a few Lanczos steps from a random vector and is applicable to time-independent Hamiltonians
+/// only, `Fixed(lower, upper)` takes the given bounds as they are.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    PartialOrd
+)]
+pub enum SpectralBounds<R> {
+    #[default]
+    TermNorms,
+    Lanczos(usize),
+    Fixed(R, R),
+}
+
+impl<R: Float + Debug> SpectralBounds<R> {
+    /// Replaces the Lanczos estimation by fixed bounds, the Lanczos bounds are
+    /// widened by 1% of the spectral width to stay on the safe side
+    fn resolve<T>(self, hamiltonian: &[TermAndAmpl<T>], basis: &Basis) -> Self
+    where
+        T: Value + TrueComplex<Real = R>,
+        T::Real: Value,
+    {
+        let bounds = match self {
+            SpectralBounds::Lanczos(steps) => {
+                assert!(
+                    hamiltonian.iter().all(|term| !term.is_time_dependent()),
+                    "Lanczos spectral bounds are not applicable to a time-dependent Hamiltonian",
+                );
+                let init_state = random_phase_state::<T>(basis, &mut StdRng::seed_from_u64(0));
+                let (lower, upper) = lanczos_bounds(
+                    &init_state,
+                    |dst, src| apply_hamiltonian(dst, src, hamiltonian, basis, R::zero(), T::one()),
+                    steps,
+                );
+                let margin = 0.01 * (upper - lower);
+                SpectralBounds::Fixed(
+                    <R as NumCast>::from(lower - margin).unwrap(),
+                    <R as NumCast>::from(upper + margin).unwrap(),
+                )
+            },
+            other => other,
+        };
+        if let SpectralBounds::Fixed(lower, upper) = bounds {
+            assert!(upper >= lower, "Invalid spectral bounds [{:?}, {:?}]", lower, upper);
+            info!("Spectral bounds: [{:?}, {:?}]", lower, upper);
+        }
+        bounds
+    }
+
+    /// Lower and upper bounds of the spectrum of H(time), the Lanczos estimation must be resolved
+    fn at<T>(&self, hamiltonian: &[TermAndAmpl<T>], basis: &Basis, time: R) -> (f64, f64)
+    where
+        T: Value + TrueComplex<Real = R>,
+        T::Real: Value,
+    {
+        match self {
+            SpectralBounds::TermNorms => {
+                let radius = hamiltonian.iter().map(|term| term.norm(basis.local_dims(), time)).sum::<f64>();
+                (-radius, radius)
+            },
+            SpectralBounds::Lanczos(_) => unreachable!("Lanczos spectral bounds must be resolved first"),
+            SpectralBounds::Fixed(lower, upper) => (lower.to_f64().unwrap(), upper.to_f64().unwrap()),
+        }
+    }
+}
+
+/// Action taken when the population of the highest allowed level of a mode exceeds the threshold.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    PartialOrd
+)]
+pub enum LeakageAction {
+    #[default]
+    Warn,
+    Abort,
+}
+
+/// Threshold on the population of the highest allowed level of each mode, a large population
+/// there signals that the truncation of local Hilbert spaces is not sufficient. Modes acted on
+/// by Pauli or fermionic operators are two-level systems rather than truncated bosonic modes,
+/// thus they are not checked.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    PartialOrd
+)]
+pub struct LeakagePolicy<R> {
+    threshold: R,
+    #[serde(default)]
+    action: LeakageAction,
 }
 
+/// Unitary dynamics, exp(-iHt), of reduced density matrices starting from the Fock state `init_state`.
+/// Every time step is made with a single Chebyshev expansion per exponential of the propagator
+/// whose order is chosen from the requested tolerance. H is shifted and rescaled onto [-1, 1]
+/// according to `spectral_bounds`, thus time steps of any size are allowed.
+/// If `conserve_particle_number` is set, the state is stored in the sector with the total
+/// number of particles of `init_state`, all Hamiltonian terms must conserve it.
+/// Populations of the highest allowed level of each mode are reported at every step and
+/// checked against `leakage_policy` if it is given. If `checkpoint` is given, the state and
+/// the observables are saved periodically, and a run may be resumed from the checkpoint.
 #[derive(
     Deserialize,
     Serialize,
@@ -77,12 +316,30 @@ pub struct ChebyshevDynamics<T>
 where
     T: ComplexFloat
 {
-    qubits_per_mode: Vec<usize>,
-    init_state: Vec<usize>,
+    #[serde(default)]
+    qubits_per_mode: Option<Vec<usize>>,
+    #[serde(default)]
+    max_occupation: Option<Vec<usize>>,
+    init_state: InitState,
     total_time_steps_number: usize,
     time_step_size: T::Real,
+    #[serde(deserialize_with = "deserialize_hamiltonian")]
     hamiltonian: Vec<TermAndAmpl<T>>,
-    density_matrices: Vec<DensEnum>,
+    density_matrices: Vec<DensPositions>,
+    #[serde(default)]
+    propagator: Propagator,
+    #[serde(default)]
+    spectral_bounds: SpectralBounds<T::Real>,
+    #[serde(default)]
+    conserve_particle_number: bool,
+    #[serde(default)]
+    leakage_policy: Option<LeakagePolicy<T::Real>>,
+    #[serde(default, deserialize_with = "deserialize_checkpoint_policy")]
+    checkpoint: Option<CheckpointPolicy>,
+    #[serde(skip)]
+    resume: Option<String>,
+    #[serde(skip)]
+    stream: Option<Stream>,
 }
 
 #[derive(
@@ -91,140 +348,2716 @@ where
     Debug,
     Clone,
     PartialEq,
-    PartialOrd,
 )]
-pub enum Task<T>
+#[serde(bound(deserialize = "T: Deserialize<'de>, T::Real: Deserialize<'de>"))]
+pub struct ChebyshevDynamicsResult<T>
 where
     T: ComplexFloat,
-    T::Real: Serialize + for<'a > Deserialize<'a> + Debug
+    T::Real: Serialize,
 {
-    ChebyshevDynamics(ChebyshevDynamics<T>)
+    density_matrices: Vec<Vec<Vec<T>>>,
+    top_level_populations: Vec<Vec<T::Real>>,
+    #[serde(default)]
+    diagnostics: Diagnostics<T::Real>,
 }
 
-impl<T> ChebyshevDynamics<T>
-where
-    T: Value + TrueComplex + FromComplex64 + std::iter::Sum + Debug,
-    T::Real: Value,
-{
-    pub fn run(&self, order: usize, acc: T::Real) -> Vec<Vec<Vec<T>>>
+/// Diagnostics of a time evolution: norms of the state at every time step starting from the initial one
+/// and the largest order of Chebyshev expansions. The norms are traces of the density matrix for
+/// `LindbladDynamics`, norms before renormalization for `ImaginaryTimeDynamics` (a step multiplies
+/// the state by exp(-time_step_size (H - c)), c is the middle of spectral bounds), norms of the propagated
+/// state |psi(t)> for `TwoTimeCorrelators` and means over trajectories of norms before renormalization
+/// for `QuantumTrajectories`.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+pub struct Diagnostics<R> {
+    norms: Vec<R>,
+    chebyshev_order: usize,
+}
+
+impl<R> Default for Diagnostics<R> {
+    fn default() -> Self
     {
-        let mut state = init_custom::<T>(&self.init_state, &self.qubits_per_mode);
-        let mut exp = init_zero::<T>(&self.qubits_per_mode);
-        let mut aux = init_zero::<T>(&self.qubits_per_mode);
-        let mut density_matrices = vec![
-            Vec::with_capacity(self.total_time_steps_number + 1); self.density_matrices.len()
-        ];
-        for (dens, dst) in self.density_matrices.iter().zip(&mut density_matrices)
-        {
-            match dens
-            {
-                DensEnum::One(positions) => {
-                    dst.push(get_density(&state, positions, &self.qubits_per_mode));
-                },
-                DensEnum::Two(positions) => {
-                    dst.push(get_density(&state, positions, &self.qubits_per_mode));
-                },
-                DensEnum::Three(positions) => {
-                    dst.push(get_density(&state, positions, &self.qubits_per_mode));
-                },
-                DensEnum::Four(positions) => {
-                    dst.push(get_density(&state, positions, &self.qubits_per_mode));
-                },
-            }
-        }
-        for _ in (0..self.total_time_steps_number).progress() {
-            let update_fn = |dst: &mut Vec<T>, src: &Vec<T>, delta| {
-                for term in &self.hamiltonian {
-                    match term {
-                        TermAndAmpl::One { ampl, pos, ops } => {
-                            apply_term(
-                                dst, src,
-                                &Term { positions: *pos, op_types: *ops },
-                                &self.qubits_per_mode,
-                                delta * <T as TrueComplex>::new(T::Real::zero(), *ampl * self.time_step_size),
-                            );
-                        },
-                        TermAndAmpl::Two { ampl, pos, ops } => {
-                            apply_term(
-                                dst, src,
-                                &Term { positions: *pos, op_types: *ops },
-                                &self.qubits_per_mode,
-                                delta * <T as TrueComplex>::new(T::Real::zero(), *ampl * self.time_step_size),
-                            );
-                        },
-                        TermAndAmpl::Three { ampl, pos, ops } => {
-                            apply_term(
-                                dst, src,
-                                &Term { positions: *pos, op_types: *ops },
-                                &self.qubits_per_mode,
-                                delta * <T as TrueComplex>::new(T::Real::zero(), *ampl * self.time_step_size),
-                            );
-                        },
-                        TermAndAmpl::Four { ampl, pos, ops } => {
-                            apply_term(
-                                dst, src,
-                                &Term { positions: *pos, op_types: *ops },
-                                &self.qubits_per_mode,
-                                delta * <T as TrueComplex>::new(T::Real::zero(), *ampl * self.time_step_size),
-                            );
-                        },
-                    }
-                }
-            };
-            cheb_exp::<Vec<T>, T>(
-                &mut exp,
-                &mut state, 
-                &mut aux,
-                update_fn,
-                |dst, src, delta| add_inplace(dst.as_mut_slice(), src.as_slice(), delta),
-                order
-            );
-            std::mem::swap(&mut exp, &mut state);
-            set2zero(&mut exp);
-            set2zero(&mut aux);
-            for (dens, dst) in self.density_matrices.iter().zip(&mut density_matrices)
-            {
-                match dens
-                {
-                    DensEnum::One(positions) => {
-                        let dens = get_density(&state, positions, &self.qubits_per_mode);
-                        let dim = (dens.len() as f64).sqrt() as usize;
-                        let trace = dens.iter().enumerate().filter(|(i, _)| i % (dim + 1) == 0).map(|(_, x)| *x).sum::<T>();
-                        if (trace - T::one()).abs() > acc {
-                            error!("Trace of a density matrix sufficiently deviates from 1, trace value: {:?}", trace);
-                        }
-                        dst.push(dens);
-                    },
-                    DensEnum::Two(positions) => {
-                        let dens = get_density(&state, positions, &self.qubits_per_mode);
-                        let dim = (dens.len() as f64).sqrt() as usize;
-                        let trace = dens.iter().enumerate().filter(|(i, _)| i % (dim + 1) == 0).map(|(_, x)| *x).sum::<T>();
-                        if (trace - T::one()).abs() > acc {
-                            error!("Trace of a density matrix sufficiently deviates from 1, trace value: {:?}", trace);
-                        }
-                        dst.push(dens);
-                    },
-                    DensEnum::Three(positions) => {
-                        let dens = get_density(&state, positions, &self.qubits_per_mode);
-                        let dim = (dens.len() as f64).sqrt() as usize;
-                        let trace = dens.iter().enumerate().filter(|(i, _)| i % (dim + 1) == 0).map(|(_, x)| *x).sum::<T>();
-                        if (trace - T::one()).abs() > acc {
-                            error!("Trace of a density matrix sufficiently deviates from 1, trace value: {:?}", trace);
-                        }
-                        dst.push(dens);
-                    },
-                    DensEnum::Four(positions) => {
-                        let dens = get_density(&state, positions, &self.qubits_per_mode);
-                        let dim = (dens.len() as f64).sqrt() as usize;
-                        let trace = dens.iter().enumerate().filter(|(i, _)| i % (dim + 1) == 0).map(|(_, x)| *x).sum::<T>();
-                        if (trace - T::one()).abs() > acc {
-                            error!("Trace of a density matrix sufficiently deviates from 1, trace value: {:?}", trace);
-                        }
-                        dst.push(dens);
-                    },
-                }
-            }
-        }
-        density_matrices
+        Diagnostics { norms: Vec::new(), chebyshev_order: 0 }
     }
-}
\ No newline at end of file
+}
+
+/// Lowest eigenvalues and the ground state of a Hamiltonian found by the thick-restarted
+/// Lanczos iteration starting from the Fock state `init_state`, `krylov_dim` must exceed
+/// `eigenvalues_number`. Note, that the iteration never leaves the symmetry sector of the initial state,
+/// e.g. the sector with a fixed total number of particles for number conserving Hamiltonians,
+/// thus fewer eigenvalues are reported (with a warning) if the sector is too small. If `conserve_particle_number` is set,
+/// the iteration runs directly in this sector and `state` is given in the sector basis
+/// (occupation numbers ordered lexicographically starting from the last mode).
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+    PartialOrd
+)]
+pub struct GroundState<T>
+where
+    T: ComplexFloat
+{
+    #[serde(default)]
+    qubits_per_mode: Option<Vec<usize>>,
+    #[serde(default)]
+    max_occupation: Option<Vec<usize>>,
+    init_state: InitState,
+    #[serde(deserialize_with = "deserialize_hamiltonian")]
+    hamiltonian: Vec<TermAndAmpl<T>>,
+    density_matrices: Vec<DensPositions>,
+    krylov_dim: usize,
+    eigenvalues_number: usize,
+    tolerance: T::Real,
+    max_restarts: usize,
+    #[serde(default)]
+    conserve_particle_number: bool,
+}
+
+#[derive(
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+pub struct GroundStateResult<T>
+where
+    T: ComplexFloat,
+    T::Real: Serialize,
+{
+    eigenvalues: Vec<T::Real>,
+    state: Vec<T>,
+    density_matrices: Vec<Vec<T>>,
+}
+
+/// Propagation in the imaginary time, exp(-time_step_size * H), with the renormalization
+/// of the state after every step. The propagation stops earlier if the energy changes
+/// by less than `energy_tolerance` during a step. H is shifted and rescaled onto [-1, 1]
+/// according to `spectral_bounds`, the shift only changes the norm which is restored anyway.
+/// `conserve_particle_number` has the same meaning as in `GroundState`.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+    PartialOrd
+)]
+pub struct ImaginaryTimeDynamics<T>
+where
+    T: ComplexFloat
+{
+    #[serde(default)]
+    qubits_per_mode: Option<Vec<usize>>,
+    #[serde(default)]
+    max_occupation: Option<Vec<usize>>,
+    init_state: InitState,
+    total_time_steps_number: usize,
+    time_step_size: T::Real,
+    #[serde(deserialize_with = "deserialize_hamiltonian")]
+    hamiltonian: Vec<TermAndAmpl<T>>,
+    density_matrices: Vec<DensPositions>,
+    #[serde(default)]
+    energy_tolerance: Option<T::Real>,
+    #[serde(default)]
+    spectral_bounds: SpectralBounds<T::Real>,
+    #[serde(default)]
+    conserve_particle_number: bool,
+    #[serde(skip)]
+    stream: Option<Stream>,
+}
+
+#[derive(
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+pub struct ImaginaryTimeDynamicsResult<T>
+where
+    T: ComplexFloat,
+    T::Real: Serialize,
+{
+    energies: Vec<T::Real>,
+    state: Vec<T>,
+    density_matrices: Vec<Vec<Vec<T>>>,
+    diagnostics: Diagnostics<T::Real>,
+}
+
+/// Dynamics of a density matrix driven by the Lindblad master equation
+/// d rho / dt = -i [H, rho] + sum_k rate_k (L_k rho L_k^dagger - {L_k^dagger L_k, rho} / 2).
+/// Jump operators L_k are given in the same format as Hamiltonian terms, `ampl` plays the role
+/// of the rate and `hc` adds L_k^dagger as one more jump operator with the same rate. The density matrix is stored as a state of the doubled system
+/// (ket modes followed by bra modes), thus the memory footprint is the square of the pure state one.
+/// A time step is split into sub-steps whenever an upper bound of the norm of the Liouvillian
+/// times `time_step_size` exceeds 1.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+    PartialOrd
+)]
+pub struct LindbladDynamics<T>
+where
+    T: ComplexFloat
+{
+    #[serde(default)]
+    qubits_per_mode: Option<Vec<usize>>,
+    #[serde(default)]
+    max_occupation: Option<Vec<usize>>,
+    init_state: InitState,
+    total_time_steps_number: usize,
+    time_step_size: T::Real,
+    #[serde(deserialize_with = "deserialize_hamiltonian")]
+    hamiltonian: Vec<TermAndAmpl<T>>,
+    #[serde(deserialize_with = "deserialize_terms")]
+    jump_operators: Vec<TermAndAmpl<T>>,
+    density_matrices: Vec<DensPositions>,
+    #[serde(skip)]
+    stream: Option<Stream>,
+}
+
+/// Stochastic unraveling of the Lindblad master equation (Monte Carlo wave function method).
+/// Each trajectory is a pure state evolving with the effective non-Hermitian Hamiltonian
+/// H - i/2 sum_k rate_k L_k^dagger L_k, interrupted by quantum jumps that happen once the squared
+/// norm of the state drops below a uniformly distributed random number. Jump times are resolved
+/// by bisection of the time step, so several jumps may happen during one step. Jump operators are
+/// given in the same format as in `LindbladDynamics`. Reduced density matrices are averaged over
+/// trajectories, their statistical errors (standard errors of real and imaginary parts)
+/// are reported as well. Trajectories run in parallel, each one keeps a few state vectors in memory.
+/// As in `LindbladDynamics`, a time step is split into sub-steps whenever an upper bound of
+/// the norm of the effective Hamiltonian times `time_step_size` exceeds 1. Since every trajectory
+/// runs over all the time steps, averaged observables are streamed once all trajectories are done.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+    PartialOrd
+)]
+pub struct QuantumTrajectories<T>
+where
+    T: ComplexFloat
+{
+    #[serde(default)]
+    qubits_per_mode: Option<Vec<usize>>,
+    #[serde(default)]
+    max_occupation: Option<Vec<usize>>,
+    init_state: InitState,
+    total_time_steps_number: usize,
+    time_step_size: T::Real,
+    #[serde(deserialize_with = "deserialize_hamiltonian")]
+    hamiltonian: Vec<TermAndAmpl<T>>,
+    #[serde(deserialize_with = "deserialize_terms")]
+    jump_operators: Vec<TermAndAmpl<T>>,
+    density_matrices: Vec<DensPositions>,
+    trajectories_number: usize,
+    #[serde(default)]
+    seed: Option<u64>,
+    #[serde(skip)]
+    stream: Option<Stream>,
+}
+
+#[derive(
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+pub struct LindbladDynamicsResult<T>
+where
+    T: ComplexFloat,
+    T::Real: Serialize,
+{
+    pub(super) density_matrices: Vec<Vec<Vec<T>>>,
+    diagnostics: Diagnostics<T::Real>,
+}
+
+#[derive(
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+pub struct QuantumTrajectoriesResult<T>
+where
+    T: ComplexFloat,
+    T::Real: Serialize,
+{
+    density_matrices: Vec<Vec<Vec<T>>>,
+    density_matrices_errors: Vec<Vec<Vec<T>>>,
+    diagnostics: Diagnostics<T::Real>,
+    /// Numbers of quantum jumps of trajectories in the order of their seeds
+    jumps_numbers: Vec<usize>,
+}
+
+/// Two-time correlation functions <psi| A(t) B(0) |psi> = <psi(t)| A U(t) B |psi>, where |psi> is
+/// the Fock state `init_state`. Each correlator is given by a pair [A, B] of operators in the same
+/// format as Hamiltonian terms, `ampl` plays the role of a prefactor and `hc` adds the Hermitian
+/// conjugate of the term to the operator. Both |psi> and B|psi> are
+/// propagated with the same scheme as in `ChebyshevDynamics`, thus one extra state vector per
+/// correlator is kept in memory. The result is a time series (including t = 0) per correlator.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+    PartialOrd
+)]
+pub struct TwoTimeCorrelators<T>
+where
+    T: ComplexFloat
+{
+    #[serde(default)]
+    qubits_per_mode: Option<Vec<usize>>,
+    #[serde(default)]
+    max_occupation: Option<Vec<usize>>,
+    init_state: InitState,
+    total_time_steps_number: usize,
+    time_step_size: T::Real,
+    #[serde(deserialize_with = "deserialize_hamiltonian")]
+    hamiltonian: Vec<TermAndAmpl<T>>,
+    #[serde(deserialize_with = "deserialize_operator_pairs")]
+    correlators: Vec<OperatorPair<T>>,
+    #[serde(default)]
+    propagator: Propagator,
+    #[serde(default)]
+    spectral_bounds: SpectralBounds<T::Real>,
+    #[serde(skip)]
+    stream: Option<Stream>,
+}
+
+#[derive(
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+pub struct TwoTimeCorrelatorsResult<T>
+where
+    T: ComplexFloat,
+    T::Real: Serialize,
+{
+    correlators: Vec<Vec<T>>,
+    diagnostics: Diagnostics<T::Real>,
+}
+
+/// Kernel polynomial method. The density of states is restored from Chebyshev moments
+/// <r|T_n(H~)|r> averaged over `random_vectors_number` random-phase vectors |r>, where
+/// H~ = (H - b) / a is H mapped onto [-1, 1]. Local spectral functions A_i(w) = <psi|O_i^+ delta(w - H) O_i|psi>
+/// are restored from the moments of O_i|psi>, where |psi> is the Fock state `init_state` and O_i
+/// are `local_operators` given in the same format as Hamiltonian terms (e.g. [A+] at a site i),
+/// `hc` adds the Hermitian conjugate of the term to the operator.
+/// Spectral bounds of H are estimated by 64 Lanczos steps unless `spectral_bounds` is set.
+/// A time-dependent Hamiltonian is taken at t = 0.
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+    PartialOrd
+)]
+#[serde(bound(
+    serialize = "T::Real: Serialize",
+    deserialize = "T::Real: Deserialize<'de>",
+))]
+pub struct KernelPolynomial<T>
+where
+    T: ComplexFloat
+{
+    #[serde(default)]
+    qubits_per_mode: Option<Vec<usize>>,
+    #[serde(default)]
+    max_occupation: Option<Vec<usize>>,
+    init_state: InitState,
+    #[serde(deserialize_with = "deserialize_hamiltonian")]
+    hamiltonian: Vec<TermAndAmpl<T>>,
+    moments_number: usize,
+    random_vectors_number: usize,
+    energy_points_number: usize,
+    #[serde(default, deserialize_with = "deserialize_operators")]
+    local_operators: Vec<Vec<TermAndAmpl<T>>>,
+    #[serde(default)]
+    spectral_bounds: Option<SpectralBounds<T::Real>>,
+    #[serde(default)]
+    seed: Option<u64>,
+}
+
+#[derive(
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+pub struct KernelPolynomialResult<T>
+where
+    T: ComplexFloat,
+    T::Real: Serialize,
+{
+    energies: Vec<T::Real>,
+    density_of_states: Vec<T::Real>,
+    local_spectral_functions: Vec<Vec<T::Real>>,
+    moments: Vec<T::Real>,
+    local_moments: Vec<Vec<T::Real>>,
+    spectral_bounds: (T::Real, T::Real),
+}
+
+/// A state handed off from a task to the next one in a protocol, either a state vector
+/// or a density matrix of the whole space (vectorized as a state of the doubled system
+/// as in `LindbladDynamics`).
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+)]
+pub enum StageState<T> {
+    Pure(Vec<T>),
+    Mixed(Vec<T>),
+}
+
+/// Time series of requested density matrices and times of steps
+type TimeSeries<'a, T> = (&'a Vec<Vec<Vec<T>>>, Vec<<T as ComplexFloat>::Real>);
+
+/// Result of any task, serialized as the result itself
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum TaskResult<T>
+where
+    T: ComplexFloat,
+    T::Real: Serialize,
+{
+    ChebyshevDynamics(ChebyshevDynamicsResult<T>),
+    GroundState(GroundStateResult<T>),
+    ImaginaryTimeDynamics(ImaginaryTimeDynamicsResult<T>),
+    LindbladDynamics(LindbladDynamicsResult<T>),
+    QuantumTrajectories(QuantumTrajectoriesResult<T>),
+    TwoTimeCorrelators(TwoTimeCorrelatorsResult<T>),
+    KernelPolynomial(KernelPolynomialResult<T>),
+}
+
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    PartialEq,
+    PartialOrd,
+)]
+pub enum Task<T>
+where
+    T: ComplexFloat,
+    T::Real: Serialize + for<'a > Deserialize<'a> + Debug
+{
+    ChebyshevDynamics(ChebyshevDynamics<T>),
+    GroundState(GroundState<T>),
+    ImaginaryTimeDynamics(ImaginaryTimeDynamics<T>),
+    LindbladDynamics(LindbladDynamics<T>),
+    QuantumTrajectories(QuantumTrajectories<T>),
+    TwoTimeCorrelators(TwoTimeCorrelators<T>),
+    KernelPolynomial(KernelPolynomial<T>),
+}
+
+impl<T> Task<T>
+where
+    T: ComplexFloat + for<'a > Deserialize<'a>,
+    T::Real: Serialize + for<'a > Deserialize<'a> + Debug
+{
+    /// Parses a task config, a lattice is expanded into Hamiltonian terms and
+    /// matrices of named dense operators are substituted into terms beforehand
+    pub fn from_value(mut config: serde_yaml::Value) -> Result<Self, String>
+    {
+        expand_lattice(&mut config)?;
+        expand_dense_operators(&mut config)?;
+        let mut task: Self = serde_yaml::from_value(config).map_err(|err| err.to_string())?;
+        task.assign_fermionic_modes()?;
+        Ok(task)
+    }
+
+    /// Keys a config of the task consumes, i.e. the fields of the task and the sections
+    /// expanded before parsing
+    pub fn config_keys(&self) -> Vec<String>
+    where
+        T: Serialize,
+    {
+        let fields = match serde_yaml::to_value(self) {
+            Ok(serde_yaml::Value::Tagged(tagged)) => tagged.value,
+            _ => panic!("A task must be serialized as a tagged map"),
+        };
+        let fields = fields.as_mapping().expect("A task must be serialized as a tagged map").keys();
+        fields.filter_map(|key| key.as_str().map(str::to_owned))
+            .chain(["lattice", "dense_operators"].map(str::to_owned))
+            .collect()
+    }
+
+    /// All the terms of the task, i.e. terms of a Hamiltonian, jump operators and local operators
+    fn terms_mut(&mut self) -> Vec<&mut TermAndAmpl<T>>
+    {
+        match self {
+            Task::ChebyshevDynamics(task) => task.hamiltonian.iter_mut().collect(),
+            Task::GroundState(task) => task.hamiltonian.iter_mut().collect(),
+            Task::ImaginaryTimeDynamics(task) => task.hamiltonian.iter_mut().collect(),
+            Task::LindbladDynamics(task) => task.hamiltonian.iter_mut().chain(&mut task.jump_operators).collect(),
+            Task::QuantumTrajectories(task) => task.hamiltonian.iter_mut().chain(&mut task.jump_operators).collect(),
+            Task::TwoTimeCorrelators(task) => task.hamiltonian.iter_mut()
+                .chain(task.correlators.iter_mut().flat_map(|(lhs, rhs)| lhs.iter_mut().chain(rhs)))
+                .collect(),
+            Task::KernelPolynomial(task) => task.hamiltonian.iter_mut().chain(task.local_operators.iter_mut().flatten()).collect(),
+        }
+    }
+
+    /// Modes acted on by fermionic operators in any term of the task are fermionic,
+    /// the list of them is passed to every term for the Jordan–Wigner representation.
+    fn assign_fermionic_modes(&mut self) -> Result<(), String>
+    {
+        let mut terms = self.terms_mut();
+        let mut fermionic_modes: Vec<usize> = terms.iter()
+            .flat_map(|term| term.pos.iter().zip(&term.ops).filter(|(_, op)| op.is_fermionic()).map(|(pos, _)| *pos))
+            .collect();
+        fermionic_modes.sort();
+        fermionic_modes.dedup();
+        for term in terms.iter_mut() {
+            if term.dense.is_some() && term.pos.iter().any(|pos| fermionic_modes.contains(pos)) {
+                return Err(format!("The dense operator {} acts on fermionic modes {:?}", term.describe(), fermionic_modes));
+            }
+            let mixed = term.pos.iter().zip(&term.ops).find(|(pos, op)| {
+                fermionic_modes.contains(pos) && !(op.is_fermionic() || matches!(op, Op::N | Op::N2 | Op::Z))
+            });
+            if let Some((pos, op)) = mixed {
+                return Err(format!("The fermionic mode {} can not be acted on by {:?} in the term {}", pos, op, term.describe()));
+            }
+            term.fermionic_modes = fermionic_modes.clone();
+        }
+        Ok(())
+    }
+
+    fn init_state(&self) -> &InitState
+    {
+        match self {
+            Task::ChebyshevDynamics(task) => &task.init_state,
+            Task::GroundState(task) => &task.init_state,
+            Task::ImaginaryTimeDynamics(task) => &task.init_state,
+            Task::LindbladDynamics(task) => &task.init_state,
+            Task::QuantumTrajectories(task) => &task.init_state,
+            Task::TwoTimeCorrelators(task) => &task.init_state,
+            Task::KernelPolynomial(task) => &task.init_state,
+        }
+    }
+
+    /// Makes the task continue from the checkpoint `path` instead of starting anew
+    pub fn resume_from(&mut self, path: &str) -> Result<(), String>
+    {
+        match self {
+            Task::ChebyshevDynamics(task) => {
+                task.resume = Some(path.to_owned());
+                Ok(())
+            },
+            _ => Err("Only `ChebyshevDynamics` may be resumed from a checkpoint".to_owned()),
+        }
+    }
+
+    /// Makes the task stream observables of every time step, returns false
+    /// if the task has no time steps to stream
+    pub fn stream_to(&mut self, stream: Stream) -> bool
+    {
+        match self {
+            Task::ChebyshevDynamics(task) => task.stream = Some(stream),
+            Task::ImaginaryTimeDynamics(task) => task.stream = Some(stream),
+            Task::LindbladDynamics(task) => task.stream = Some(stream),
+            Task::QuantumTrajectories(task) => task.stream = Some(stream),
+            Task::TwoTimeCorrelators(task) => task.stream = Some(stream),
+            Task::GroundState(_) | Task::KernelPolynomial(_) => return false,
+        }
+        true
+    }
+
+    /// Local dimensions of modes of the task
+    pub fn local_dims(&self) -> Vec<usize>
+    {
+        match self {
+            Task::ChebyshevDynamics(task) => get_local_dims(&task.qubits_per_mode, &task.max_occupation),
+            Task::GroundState(task) => get_local_dims(&task.qubits_per_mode, &task.max_occupation),
+            Task::ImaginaryTimeDynamics(task) => get_local_dims(&task.qubits_per_mode, &task.max_occupation),
+            Task::LindbladDynamics(task) => get_local_dims(&task.qubits_per_mode, &task.max_occupation),
+            Task::QuantumTrajectories(task) => get_local_dims(&task.qubits_per_mode, &task.max_occupation),
+            Task::TwoTimeCorrelators(task) => get_local_dims(&task.qubits_per_mode, &task.max_occupation),
+            Task::KernelPolynomial(task) => get_local_dims(&task.qubits_per_mode, &task.max_occupation),
+        }
+    }
+
+    /// Whether the result of the task has density matrices to be written in the .npz format
+    pub fn npz_supported(&self) -> bool
+    {
+        !matches!(self, Task::TwoTimeCorrelators(_) | Task::KernelPolynomial(_))
+    }
+
+    pub fn name(&self) -> &'static str
+    {
+        match self {
+            Task::ChebyshevDynamics(_) => "ChebyshevDynamics",
+            Task::GroundState(_) => "GroundState",
+            Task::ImaginaryTimeDynamics(_) => "ImaginaryTimeDynamics",
+            Task::LindbladDynamics(_) => "LindbladDynamics",
+            Task::QuantumTrajectories(_) => "QuantumTrajectories",
+            Task::TwoTimeCorrelators(_) => "TwoTimeCorrelators",
+            Task::KernelPolynomial(_) => "KernelPolynomial",
+        }
+    }
+
+    /// Positions of modes of the requested density matrices
+    fn density_matrix_positions(&self) -> Vec<Vec<usize>>
+    {
+        let positions = match self {
+            Task::ChebyshevDynamics(task) => &task.density_matrices,
+            Task::GroundState(task) => &task.density_matrices,
+            Task::ImaginaryTimeDynamics(task) => &task.density_matrices,
+            Task::LindbladDynamics(task) => &task.density_matrices,
+            Task::QuantumTrajectories(task) => &task.density_matrices,
+            Task::TwoTimeCorrelators(_) | Task::KernelPolynomial(_) => return Vec::new(),
+        };
+        positions.iter().map(|DensPositions(positions)| positions.clone()).collect()
+    }
+
+    /// Time series of density matrices of the result and times of steps, None for tasks without time steps
+    fn time_series<'a>(&self, result: &'a TaskResult<T>) -> Option<TimeSeries<'a, T>>
+    {
+        let (density_matrices, steps, time_step_size) = match (self, result) {
+            (Task::ChebyshevDynamics(task), TaskResult::ChebyshevDynamics(result)) => {
+                (&result.density_matrices, task.total_time_steps_number + 1, task.time_step_size)
+            },
+            (Task::ImaginaryTimeDynamics(task), TaskResult::ImaginaryTimeDynamics(result)) => {
+                (&result.density_matrices, result.energies.len(), task.time_step_size)
+            },
+            (Task::LindbladDynamics(task), TaskResult::LindbladDynamics(result)) => {
+                (&result.density_matrices, task.total_time_steps_number + 1, task.time_step_size)
+            },
+            (Task::QuantumTrajectories(task), TaskResult::QuantumTrajectories(result)) => {
+                (&result.density_matrices, task.total_time_steps_number + 1, task.time_step_size)
+            },
+            _ => return None,
+        };
+        let times = (0..steps).map(|step| time_step_size * <T::Real as NumCast>::from(step).unwrap()).collect();
+        Some((density_matrices, times))
+    }
+}
+
+impl<T> Task<T>
+where
+    T: ComplexFloat + NpyElement + for<'a > Deserialize<'a>,
+    T::Real: NpyElement + Serialize + for<'a > Deserialize<'a> + Debug
+{
+    /// Arrays of the result of the task in the .npz format named with `prefix`: `density_matrix_i` of the shape
+    /// (steps, dim, dim) per requested density matrix and `times` of steps, or (dim, dim) ones for `GroundState`.
+    /// Panics if the task does not support the format, see `npz_supported`.
+    pub fn npz_arrays(&self, result: &TaskResult<T>, prefix: &str) -> Vec<(String, NpyArray)>
+    {
+        let square = |dens: &[T]| {
+            let dim = (dens.len() as f64).sqrt().round() as usize;
+            vec![dim, dim]
+        };
+        if let TaskResult::GroundState(result) = result {
+            return result.density_matrices.iter().enumerate().map(|(i, dens)| {
+                (format!("{}density_matrix_{}", prefix, i), NpyArray::new(square(dens), dens))
+            }).collect();
+        }
+        let (density_matrices, times) = self.time_series(result)
+            .expect("Results of `TwoTimeCorrelators` and `KernelPolynomial` can not be written in the .npz format");
+        let mut arrays = vec![(format!("{}times", prefix), NpyArray::new(vec![times.len()], &times))];
+        for (i, series) in density_matrices.iter().enumerate() {
+            let mut shape = series.first().map_or(vec![0, 0], |dens| square(dens));
+            shape.insert(0, series.len());
+            arrays.push((format!("{}density_matrix_{}", prefix, i), NpyArray::new(shape, series.iter().flatten())));
+        }
+        arrays
+    }
+}
+
+impl<T> Task<T>
+where
+    T: Value + TrueComplex + FromComplex64 + std::iter::Sum + Debug + for<'a > Deserialize<'a>,
+    T::Real: Value + Serialize + for<'a > Deserialize<'a>,
+{
+    /// Runs the task starting from the state handed off by the previous task if it is given
+    /// (`init_state` is ignored then), the state is loaded if `init_state` is a checkpoint, returns the result and the final state of the task
+    /// if there is any (quantum trajectories do not end with a single state).
+    pub fn run(&self, handoff: Option<&StageState<T>>, tolerance: f64, acc: T::Real) -> (TaskResult<T>, Option<StageState<T>>)
+    {
+        let loaded = match handoff {
+            Some(_) => None,
+            None => self.init_state().load(),
+        };
+        let handoff = handoff.or(loaded.as_ref());
+        match self {
+            Task::ChebyshevDynamics(task) => {
+                let (result, state) = task.run(handoff, tolerance, acc);
+                (TaskResult::ChebyshevDynamics(result), Some(state))
+            },
+            Task::GroundState(task) => {
+                let (result, state) = task.run(handoff, acc);
+                (TaskResult::GroundState(result), Some(state))
+            },
+            Task::ImaginaryTimeDynamics(task) => {
+                let (result, state) = task.run(handoff, tolerance, acc);
+                (TaskResult::ImaginaryTimeDynamics(result), Some(state))
+            },
+            Task::LindbladDynamics(task) => {
+                let (result, state) = task.run(handoff, tolerance, acc);
+                (TaskResult::LindbladDynamics(result), Some(state))
+            },
+            Task::QuantumTrajectories(task) => {
+                let result = task.run(handoff, tolerance, acc);
+                (TaskResult::QuantumTrajectories(result), None)
+            },
+            Task::TwoTimeCorrelators(task) => {
+                let (result, state) = task.run(handoff, tolerance);
+                (TaskResult::TwoTimeCorrelators(result), Some(state))
+            },
+            Task::KernelPolynomial(task) => {
+                let (result, state) = task.run(handoff);
+                (TaskResult::KernelPolynomial(result), Some(state))
+            },
+        }
+    }
+
+    /// Metadata of the task and its result, `stage` is the name of the stage of a protocol
+    pub fn metadata(&self, result: &TaskResult<T>, stage: Option<&str>) -> TaskMetadata
+    {
+        // density matrices at every step, the ground state ones make a single step
+        let (steps, times): (Vec<Vec<&Vec<T>>>, _) = match (result, self.time_series(result)) {
+            (TaskResult::GroundState(result), _) => (vec![result.density_matrices.iter().collect()], Vec::new()),
+            (_, Some((density_matrices, times))) => {
+                let steps = (0..times.len()).map(|step| density_matrices.iter().map(|series| &series[step]).collect()).collect();
+                (steps, times)
+            },
+            _ => (Vec::new(), Vec::new()),
+        };
+        let positions = self.density_matrix_positions();
+        let trace_deviations = if positions.is_empty() {
+            Vec::new()
+        } else {
+            steps.iter().map(|density_matrices| {
+                density_matrices.iter().map(|dens| (trace(dens) - T::one()).abs().to_f64().unwrap()).fold(0., f64::max)
+            }).collect()
+        };
+        TaskMetadata {
+            stage: stage.map(str::to_owned),
+            task: self.name(),
+            density_matrices: positions,
+            times: times.into_iter().map(|time| time.to_f64().unwrap()).collect(),
+            trace_deviations,
+        }
+    }
+}
+
+impl<T: Value> StageState<T> {
+
+    /// The state vector, panics if the state is mixed or does not match local dimensions of modes
+    fn pure(&self, local_dims: &[usize]) -> &[T]
+    {
+        let StageState::Pure(state) = self else {
+            panic!("A pure state is required, while the previous task ends with a density matrix");
+        };
+        assert_eq!(
+            state.len(), local_dims.iter().product::<usize>(),
+            "The handed off state does not match local dimensions of modes {:?}", local_dims,
+        );
+        state
+    }
+
+    /// Occupation numbers of the largest component of a state vector, that determine the sector
+    /// of the state, or `init_state` if there is no handed off state
+    fn occupations(handoff: Option<&Self>, init_state: &InitState, local_dims: &[usize]) -> Vec<usize>
+    {
+        let Some(handoff) = handoff else {
+            return init_state.fock().to_owned();
+        };
+        let state = handoff.pure(local_dims);
+        let mut index = (0..state.len())
+            .fold(0, |max, i| if state[i].abs() > state[max].abs() { i } else { max });
+        local_dims.iter().map(|dim| {
+            let occupation = index % dim;
+            index /= dim;
+            occupation
+        }).collect()
+    }
+}
+
+impl DensPositions {
+    /// Positions of modes, panics if some of them coincide
+    fn positions(&self) -> &[usize]
+    {
+        let mut positions = self.0.clone();
+        positions.sort();
+        positions.dedup();
+        assert_eq!(positions.len(), self.0.len(), "Positions of a density matrix must be distinct, got {:?}", self.0);
+        &self.0
+    }
+
+    fn get_mixed_density<T: Value>(&self, rho: &[T], local_dims: &[usize]) -> Vec<T>
+    {
+        get_mixed_density(rho, self.positions(), local_dims)
+    }
+
+    fn get_density<T: Value>(&self, state: &[T], basis: &Basis) -> Vec<T>
+    {
+        basis.get_density(state, self.positions())
+    }
+}
+
+/// Local dimensions of modes given either by `qubits_per_mode` (2^q levels per mode)
+/// or by `max_occupation` (n_max + 1 levels per mode).
+fn get_local_dims(qubits_per_mode: &Option<Vec<usize>>, max_occupation: &Option<Vec<usize>>) -> Vec<usize>
+{
+    match (qubits_per_mode, max_occupation) {
+        (Some(qubits_per_mode), None) => qubits_per_mode.iter().map(|q| 1 << q).collect(),
+        (None, Some(max_occupation)) => max_occupation.iter().map(|n| n + 1).collect(),
+        _ => panic!("Exactly one of qubits_per_mode and max_occupation must be specified"),
+    }
+}
+
+/// Basis of state vectors, either the whole product space of modes
+/// or the sector with a fixed total number of particles.
+enum Basis {
+    Full(Vec<usize>),
+    Sector(Sector),
+}
+
+impl Basis {
+    /// The sector of the Fock state `init_state` if `conserve_particle_number` is set,
+    /// the whole space otherwise
+    fn new(local_dims: &[usize], init_state: &[usize], conserve_particle_number: bool) -> Self
+    {
+        if conserve_particle_number {
+            let sector = Sector::new(local_dims, init_state.iter().sum());
+            info!("Size of the particle number sector: {}", sector.size());
+            Basis::Sector(sector)
+        } else {
+            Basis::Full(local_dims.to_owned())
+        }
+    }
+
+    fn local_dims(&self) -> &[usize]
+    {
+        match self {
+            Basis::Full(local_dims) => local_dims,
+            Basis::Sector(sector) => sector.local_dims(),
+        }
+    }
+
+    /// Dimension of the basis
+    fn dim(&self) -> usize
+    {
+        match self {
+            Basis::Full(local_dims) => get_size(local_dims),
+            Basis::Sector(sector) => sector.size(),
+        }
+    }
+
+    fn init_zero<T: Value>(&self) -> Vec<T>
+    {
+        match self {
+            Basis::Full(local_dims) => init_zero(local_dims),
+            Basis::Sector(sector) => init_zero_sized(sector.size()),
+        }
+    }
+
+    fn init_custom<T: Value>(&self, init_state: &[usize]) -> Vec<T>
+    {
+        match self {
+            Basis::Full(local_dims) => init_custom(init_state, local_dims),
+            Basis::Sector(sector) => init_custom_sector(init_state, sector),
+        }
+    }
+
+    /// The initial state of a task, i.e. the state handed off by the previous task
+    /// if it is given, the Fock state `init_state` otherwise
+    fn init_handoff<T: Value>(&self, init_state: &InitState, handoff: Option<&StageState<T>>) -> Vec<T>
+    {
+        let Some(handoff) = handoff else {
+            return self.init_custom(init_state.fock());
+        };
+        let state = handoff.pure(self.local_dims());
+        match self {
+            Basis::Full(_) => state.to_owned(),
+            Basis::Sector(sector) => {
+                let projected = project_to_sector(state, sector);
+                let lost_weight = (Float::powi(norm(state), 2) - Float::powi(norm(&projected), 2)).to_f64().unwrap();
+                assert!(
+                    lost_weight.abs() < Float::sqrt(T::Real::epsilon()).to_f64().unwrap(),
+                    "The handed off state does not have a fixed number of particles, the weight outside of the sector is {}",
+                    lost_weight,
+                );
+                projected
+            },
+        }
+    }
+
+    /// State vector of the whole space
+    fn to_full<T: Value>(&self, state: &[T]) -> Vec<T>
+    {
+        match self {
+            Basis::Full(_) => state.to_owned(),
+            Basis::Sector(sector) => embed_from_sector(state, sector),
+        }
+    }
+
+    /// Panics if some of the terms do not conserve the number of particles in a sector
+    fn check_terms<T>(&self, terms: &[TermAndAmpl<T>])
+    where
+        T: Value + TrueComplex,
+        T::Real: Value,
+    {
+        if let Basis::Sector(_) = self {
+            for term in terms {
+                assert!(
+                    term.conserves_particle_number(self.local_dims()),
+                    "Term {:?} does not conserve the number of particles", term,
+                );
+            }
+        }
+    }
+
+    fn apply_term<T: Value>(&self, dst: &mut [T], src: &[T], term: &Term, delta: T)
+    {
+        match self {
+            Basis::Full(local_dims) => apply_term(dst, src, term, local_dims, delta),
+            Basis::Sector(sector) => apply_term_sector(dst, src, term, sector, delta),
+        }
+    }
+
+    fn apply_dense<T: Value>(&self, dst: &mut [T], src: &[T], positions: &[usize], rows: &[Vec<(usize, T)>], delta: T)
+    {
+        match self {
+            Basis::Full(local_dims) => apply_dense(dst, src, positions, rows, local_dims, delta),
+            Basis::Sector(sector) => apply_dense_sector(dst, src, positions, rows, sector, delta),
+        }
+    }
+
+    fn get_density<T: Value>(&self, state: &[T], positions: &[usize]) -> Vec<T>
+    {
+        match self {
+            Basis::Full(local_dims) => get_density(state, positions, local_dims),
+            Basis::Sector(sector) => get_density_sector(state, positions, sector),
+        }
+    }
+
+    fn top_level_populations<T: Value>(&self, state: &[T]) -> Vec<f64>
+    {
+        match self {
+            Basis::Full(local_dims) => get_top_level_populations(state, local_dims),
+            Basis::Sector(sector) => get_top_level_populations_sector(state, sector),
+        }
+    }
+}
+
+impl<T: ComplexFloat> TermAndAmpl<T> {
+
+    fn amplitude(&self) -> &Amplitude<T::Real>
+    {
+        &self.ampl
+    }
+
+    pub(super) fn is_time_dependent(&self) -> bool
+    {
+        self.amplitude().is_time_dependent()
+    }
+
+    /// The operator string of the term with operators stably sorted by positions
+    fn term(&self) -> Term
+    {
+        Term::new(&self.pos, &self.ops)
+    }
+
+    /// Checks that the term is given either by a string of operators or by a dense operator
+    /// acting on distinct modes
+    fn check(&self) -> Result<(), String>
+    {
+        match &self.dense {
+            None if self.pos.len() != self.ops.len() || self.pos.is_empty() => Err(format!(
+                "A term must have equal non-zero numbers of positions and operators, got {:?} and {:?}",
+                self.pos, self.ops,
+            )),
+            None => Ok(()),
+            Some(dense) => {
+                if !self.ops.is_empty() {
+                    return Err(format!("A term with the dense operator \"{}\" must not contain `ops`", dense.name));
+                }
+                let mut positions = self.pos.clone();
+                positions.sort();
+                positions.dedup();
+                if positions.len() != self.pos.len() || positions.is_empty() {
+                    return Err(format!(
+                        "Positions of the dense operator \"{}\" must be distinct and not empty, got {:?}",
+                        dense.name, self.pos,
+                    ));
+                }
+                dense.check()
+            },
+        }
+    }
+
+    /// Whether two terms have equal operators, amplitudes are not compared
+    fn same_operator(&self, other: &Self) -> bool
+    {
+        match (&self.dense, &other.dense) {
+            (None, None) => self.term() == other.term(),
+            (Some(lhs), Some(rhs)) => self.pos == other.pos && lhs.is_close(rhs),
+            _ => false,
+        }
+    }
+
+    /// Human-readable description of the operator of the term
+    fn describe(&self) -> String
+    {
+        match &self.dense {
+            None => format!("{:?} at {:?}", self.ops, self.pos),
+            Some(dense) => format!("\"{}\" at {:?}", dense.name, self.pos),
+        }
+    }
+
+    /// The Jordan–Wigner representation of the string of operators
+    /// if it contains fermionic operators, the sorted string otherwise
+    fn operator_term(&self) -> Term
+    {
+        if self.ops.iter().any(|op| op.is_fermionic()) {
+            Term::jordan_wigner(&self.pos, &self.ops, &self.fermionic_modes)
+        } else {
+            self.term()
+        }
+    }
+
+    /// Amplitude of the term with operators stably sorted by positions,
+    /// i.e. taking into account the anticommutation of fermionic operators
+    fn sorted_amplitude(&self) -> Amplitude<T::Real>
+    {
+        if fermionic_sign(&self.pos, &self.ops) {
+            self.ampl.neg()
+        } else {
+            self.ampl.clone()
+        }
+    }
+
+    /// The transposed term with the same amplitude, a string of fermionic operators
+    /// is only reversed to keep track of their order.
+    pub(super) fn transposed(&self) -> Self
+    {
+        let mut transposed = self.clone();
+        transposed.hc = false;
+        match &self.dense {
+            None if self.ops.iter().any(|op| op.is_fermionic()) => {
+                transposed.pos.reverse();
+                transposed.ops = self.ops.iter().rev().map(|op| op.transposed()).collect();
+            },
+            None => {
+                let term = self.term().transpose();
+                transposed.pos = term.positions;
+                transposed.ops = term.op_types;
+            },
+            Some(dense) => transposed.dense = Some(dense.transposed()),
+        }
+        transposed
+    }
+
+    /// The term with the complex conjugate operator and the same amplitude.
+    pub(super) fn conjugated(&self) -> Self
+    {
+        let mut conjugate = self.clone();
+        conjugate.hc = false;
+        match &self.dense {
+            None => conjugate.ops = self.ops.iter().map(|op| op.conj()).collect(),
+            Some(dense) => conjugate.dense = Some(dense.conj()),
+        }
+        conjugate
+    }
+
+    /// The Hermitian conjugate term, i.e. the transposed complex conjugate operator
+    /// with the complex conjugate amplitude.
+    fn hermitian_conjugate(&self) -> Self
+    {
+        let mut conjugate = self.transposed().conjugated();
+        conjugate.ampl = conjugate.ampl.conj();
+        conjugate
+    }
+
+    fn hc(&self) -> bool
+    {
+        self.hc
+    }
+}
+
+/// Checks terms and adds Hermitian conjugates of the terms with `hc` set
+fn expand_terms<T: ComplexFloat>(terms: Vec<TermAndAmpl<T>>) -> Result<Vec<TermAndAmpl<T>>, String>
+{
+    let mut expanded = Vec::with_capacity(terms.len());
+    for term in terms {
+        term.check()?;
+        let conjugate = term.hc().then(|| term.hermitian_conjugate());
+        expanded.push(term);
+        expanded.extend(conjugate);
+    }
+    Ok(expanded)
+}
+
+/// Deserializes terms that are not required to form a Hermitian operator, e.g. jump operators,
+/// checks them and adds Hermitian conjugates of the terms with `hc` set.
+fn deserialize_terms<'de, D, T>(deserializer: D) -> Result<Vec<TermAndAmpl<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: ComplexFloat,
+    T::Real: Deserialize<'de>,
+{
+    expand_terms(Vec::<TermAndAmpl<T>>::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+/// Deserializes operators given by single terms, e.g. local operators, each term is checked
+/// and turned into an operator, the sum of the term and its Hermitian conjugate if `hc` is set.
+fn deserialize_operators<'de, D, T>(deserializer: D) -> Result<Vec<Vec<TermAndAmpl<T>>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: ComplexFloat,
+    T::Real: Deserialize<'de>,
+{
+    Vec::<TermAndAmpl<T>>::deserialize(deserializer)?
+        .into_iter()
+        .map(|term| expand_terms(vec![term]))
+        .collect::<Result<_, String>>()
+        .map_err(D::Error::custom)
+}
+
+/// A pair of operators, each given as a sum of terms
+type OperatorPair<T> = (Vec<TermAndAmpl<T>>, Vec<TermAndAmpl<T>>);
+
+/// Deserializes pairs of operators given by single terms, e.g. correlators, each term is checked
+/// and turned into an operator, the sum of the term and its Hermitian conjugate if `hc` is set.
+fn deserialize_operator_pairs<'de, D, T>(deserializer: D) -> Result<Vec<OperatorPair<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: ComplexFloat,
+    T::Real: Deserialize<'de>,
+{
+    Vec::<(TermAndAmpl<T>, TermAndAmpl<T>)>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(lhs, rhs)| Ok((expand_terms(vec![lhs])?, expand_terms(vec![rhs])?)))
+        .collect::<Result<_, String>>()
+        .map_err(D::Error::custom)
+}
+
+/// Deserializes Hamiltonian terms, adds Hermitian conjugates of the terms with `hc` set
+/// and rejects Hamiltonians which are not Hermitian.
+fn deserialize_hamiltonian<'de, D, T>(deserializer: D) -> Result<Vec<TermAndAmpl<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: ComplexFloat,
+    T::Real: Deserialize<'de>,
+{
+    let hamiltonian = deserialize_terms(deserializer)?;
+    check_hermiticity(&hamiltonian).map_err(D::Error::custom)?;
+    Ok(hamiltonian)
+}
+
+/// Checks that every term of a Hamiltonian is paired with its Hermitian conjugate.
+/// Constant amplitudes of equal operators are summed up beforehand, time-dependent
+/// amplitudes must match exactly.
+fn check_hermiticity<T: ComplexFloat>(hamiltonian: &[TermAndAmpl<T>]) -> Result<(), String>
+{
+    let mut terms: Vec<(&TermAndAmpl<T>, Amplitude<T::Real>)> = Vec::with_capacity(hamiltonian.len());
+    for term in hamiltonian {
+        let ampl = &term.sorted_amplitude();
+        let merged = terms.iter_mut().find(|(other, other_ampl)| {
+            other.same_operator(term) && !other_ampl.is_time_dependent()
+        });
+        match merged {
+            Some((_, acc)) if !ampl.is_time_dependent() => {
+                let value = acc.value(T::Real::zero()) + ampl.value(T::Real::zero());
+                *acc = Amplitude::Complex { re: value.re, im: value.im };
+            },
+            _ => terms.push((term, ampl.clone())),
+        }
+    }
+    let tolerance = Float::sqrt(T::Real::epsilon());
+    let is_close = |lhs: &Amplitude<T::Real>, rhs: &Amplitude<T::Real>| {
+        if lhs.is_time_dependent() || rhs.is_time_dependent() {
+            return lhs == rhs;
+        }
+        let (lhs, rhs) = (lhs.value(T::Real::zero()), rhs.value(T::Real::zero()));
+        (lhs - rhs).norm() <= tolerance * Float::max(lhs.norm(), T::Real::one())
+    };
+    let zero = Amplitude::Constant(T::Real::zero());
+    let mut paired = vec![false; terms.len()];
+    for (i, (term, ampl)) in terms.iter().enumerate() {
+        let conjugate = term.hermitian_conjugate();
+        // the amplitude of the sorted conjugate operator
+        let conjugate_ampl = if fermionic_sign(&term.pos, &term.ops) == fermionic_sign(&conjugate.pos, &conjugate.ops) {
+            ampl.conj()
+        } else {
+            ampl.conj().neg()
+        };
+        // self-adjoint operators only require real amplitudes
+        if paired[i] || is_close(ampl, &zero) || (conjugate.same_operator(term) && is_close(ampl, &conjugate_ampl)) {
+            continue;
+        }
+        let partner = terms.iter().enumerate().position(|(j, (other, other_ampl))| {
+            !paired[j] && j != i && other.same_operator(&conjugate) && is_close(&conjugate_ampl, other_ampl)
+        });
+        match partner {
+            Some(j) => {
+                paired[i] = true;
+                paired[j] = true;
+            },
+            None => {
+                let ampl = if ampl.is_time_dependent() {
+                    "time-dependent".to_owned()
+                } else {
+                    let value = ampl.value(T::Real::zero());
+                    format!("{}{:+}i", value.re.to_f64().unwrap(), value.im.to_f64().unwrap())
+                };
+                return Err(format!(
+                    "Hamiltonian is not Hermitian, the term {} with the amplitude {} has no Hermitian conjugate counterpart \
+                    (add it explicitly or set `hc: true`)",
+                    term.describe(), ampl,
+                ));
+            },
+        }
+    }
+    Ok(())
+}
+
+impl<T> TermAndAmpl<T>
+where
+    T: Value + TrueComplex,
+    T::Real: Value,
+{
+    /// Value of the amplitude at the given time
+    pub(super) fn ampl(&self, time: T::Real) -> T
+    {
+        let value = self.amplitude().value(time);
+        <T as TrueComplex>::new(value.re, value.im)
+    }
+
+    /// Value of the amplitude of a jump operator at the given time, i.e. its rate, which must be real
+    pub(super) fn rate(&self, time: T::Real) -> T::Real
+    {
+        let value = self.amplitude().value(time);
+        assert!(value.im == T::Real::zero(), "Rate of a jump operator must be real, got {:?}", value);
+        value.re
+    }
+
+    /// The same term with all the positions shifted by `shift`.
+    pub(super) fn shifted(&self, shift: usize) -> Self
+    {
+        let mut shifted = self.clone();
+        shifted.pos.iter_mut().for_each(|p| *p += shift);
+        shifted.fermionic_modes.iter_mut().for_each(|p| *p += shift);
+        shifted
+    }
+
+    /// Whether the term has equal numbers of rising and lowering operators (S+ and S- count as
+    /// rising and lowering ones, Pauli X and Y do not conserve the number of particles)
+    /// or, for a dense operator, does not change the number of particles in its modes
+    fn conserves_particle_number(&self, local_dims: &[usize]) -> bool
+    {
+        if let Some(dense) = &self.dense {
+            let target_dims: Vec<_> = self.pos.iter().map(|pos| local_dims[*pos]).collect();
+            return dense.conserves_particle_number(&target_dims);
+        }
+        if self.ops.iter().any(|op| matches!(op, Op::X | Op::Y | Op::MinusY)) {
+            return false;
+        }
+        let rising = self.ops.iter().filter(|op| matches!(op, Op::Rising | Op::SigmaPlus | Op::FermionCreation)).count();
+        let lowering = self.ops.iter().filter(|op| matches!(op, Op::Lowering | Op::SigmaMinus | Op::FermionAnnihilation)).count();
+        rising == lowering
+    }
+
+    /// Non-zero elements of rows of a dense operator, panics if its size
+    /// does not match the local dimensions of its modes
+    fn dense_rows(dense: &DenseOperator<T::Real>, positions: &[usize], local_dims: &[usize]) -> Vec<Vec<(usize, T)>>
+    {
+        let size: usize = positions.iter().map(|pos| local_dims[*pos]).product();
+        assert_eq!(
+            dense.size(), size,
+            "Size of the dense operator \"{}\" does not match the local dimensions of modes {:?}", dense.name, positions,
+        );
+        dense.rows().into_iter().map(|row| {
+            row.into_iter().map(|(k, value)| (k, <T as TrueComplex>::new(value.re, value.im))).collect()
+        }).collect()
+    }
+
+    /// Expansion of an operator string into strings without Pauli X and Y operators
+    fn expanded_terms(&self) -> Vec<(T, Term)>
+    {
+        self.operator_term().expand_paulis().into_iter().map(|(coeff, term)| {
+            let coeff = <T as TrueComplex>::new(
+                <T::Real as NumCast>::from(coeff.re).unwrap(),
+                <T::Real as NumCast>::from(coeff.im).unwrap(),
+            );
+            (coeff, term)
+        }).collect()
+    }
+
+    /// dst += delta * term * src, the amplitude is not taken into account
+    fn apply_operator(&self, dst: &mut [T], src: &[T], basis: &Basis, delta: T)
+    {
+        match &self.dense {
+            None => {
+                for (coeff, term) in self.expanded_terms() {
+                    basis.apply_term(dst, src, &term, delta * coeff);
+                }
+            },
+            Some(dense) => {
+                let rows = Self::dense_rows(dense, &self.pos, basis.local_dims());
+                basis.apply_dense(dst, src, &self.pos, &rows, delta);
+            },
+        }
+    }
+
+    /// dst += delta * term^dagger * term * src, the amplitude is not taken into account
+    pub(super) fn apply_hermitian_square(&self, dst: &mut [T], src: &[T], local_dims: &[usize], delta: T)
+    {
+        match &self.dense {
+            None if self.ops.iter().any(|op| matches!(op, Op::X | Op::Y | Op::MinusY)) => {
+                let basis = Basis::Full(local_dims.to_owned());
+                let mut aux = init_zero_sized(src.len());
+                self.apply_operator(&mut aux, src, &basis, T::one());
+                self.transposed().conjugated().apply_operator(dst, &aux, &basis, delta);
+            },
+            None => apply_hermitian_square(dst, src, &self.operator_term(), local_dims, delta),
+            Some(dense) => {
+                let rows = Self::dense_rows(&dense.hermitian_square(), &self.pos, local_dims);
+                apply_dense(dst, src, &self.pos, &rows, local_dims, delta);
+            },
+        }
+    }
+
+    /// Operator norm of the term without the amplitude,
+    /// for dense operators and Pauli X and Y operators an upper bound of the norm
+    fn operator_norm(&self, local_dims: &[usize]) -> f64
+    {
+        match &self.dense {
+            None => self.operator_term().expand_paulis().iter()
+                .map(|(coeff, term)| coeff.norm() * get_operator_norm(term, local_dims))
+                .sum(),
+            Some(dense) => dense.norm_bound(),
+        }
+    }
+
+    /// Operator norm of the term including the amplitude at the given time,
+    /// for dense operators and Pauli X and Y operators an upper bound of the norm
+    pub(super) fn norm(&self, local_dims: &[usize], time: T::Real) -> f64
+    {
+        self.ampl(time).abs().to_f64().unwrap() * self.operator_norm(local_dims)
+    }
+
+    /// dst += delta * ampl(time) * term * src
+    fn apply(&self, dst: &mut [T], src: &[T], basis: &Basis, time: T::Real, delta: T)
+    {
+        self.apply_operator(dst, src, basis, delta * self.ampl(time));
+    }
+}
+
+/// dst += delta * H(time) * src
+fn apply_hamiltonian<T>(
+    dst: &mut [T],
+    src: &[T],
+    hamiltonian: &[TermAndAmpl<T>],
+    basis: &Basis,
+    time: T::Real,
+    delta: T,
+)
+where
+    T: Value + TrueComplex,
+    T::Real: Value,
+{
+    for term in hamiltonian {
+        term.apply(dst, src, basis, time, delta);
+    }
+}
+
+/// Upper bound of the norm of the dissipator sum_k rate_k(time) L_k^dagger L_k
+fn dissipation_norm<T>(jump_operators: &[TermAndAmpl<T>], local_dims: &[usize], time: T::Real) -> f64
+where
+    T: Value + TrueComplex,
+    T::Real: Value,
+{
+    jump_operators.iter()
+        .map(|jump| Float::abs(jump.rate(time)).to_f64().unwrap() * jump.operator_norm(local_dims).powi(2))
+        .sum()
+}
+
+/// Number of sub-steps a time step is split into so that the norm of the generator of
+/// `cheb_exp` (an upper bound `norm_bound` times the sub-step size) does not exceed 1
+fn substeps_number(norm_bound: f64, time_step_size: f64) -> usize
+{
+    (norm_bound * time_step_size).ceil().max(1.) as usize
+}
+
+fn trace<T>(dens: &[T]) -> T
+where
+    T: Value + std::iter::Sum,
+{
+    let dim = (dens.len() as f64).sqrt() as usize;
+    dens.iter().enumerate().filter(|(i, _)| i % (dim + 1) == 0).map(|(_, x)| *x).sum::<T>()
+}
+
+fn check_trace<T>(dens: &[T], acc: T::Real)
+where
+    T: Value + std::iter::Sum,
+{
+    let trace = trace(dens);
+    if (trace - T::one()).abs() > acc {
+        error!("Trace of a density matrix sufficiently deviates from 1, trace value: {:?}", trace);
+    }
+}
+
+/// Modes acted on by Pauli or fermionic operators in any of the terms, they are two-level systems
+/// rather than truncated bosonic modes.
+fn two_level_modes<T: ComplexFloat>(terms: &[TermAndAmpl<T>]) -> Vec<usize>
+{
+    let mut modes: Vec<usize> = terms.iter()
+        .flat_map(|term| term.pos.iter().zip(&term.ops).filter(|(_, op)| op.is_pauli() || op.is_fermionic()).map(|(pos, _)| *pos))
+        .collect();
+    modes.sort();
+    modes.dedup();
+    modes
+}
+
+/// Checks populations of the highest allowed levels of modes against the policy except for
+/// `two_level_modes`, `warned` keeps track of modes that have already been reported.
+fn check_leakage<R>(populations: &[f64], policy: &LeakagePolicy<R>, two_level_modes: &[usize], warned: &mut [bool], time: R)
+where
+    R: Float + Debug,
+{
+    let threshold = policy.threshold.to_f64().unwrap();
+    for (mode, (population, warned)) in populations.iter().zip(warned).enumerate() {
+        if *population > threshold && !two_level_modes.contains(&mode) {
+            match policy.action {
+                LeakageAction::Warn => {
+                    if !*warned {
+                        warn!(
+                            "Population of the highest level of the mode {} exceeds {:?} at the time {:?}, population value: {}",
+                            mode, policy.threshold, time, population,
+                        );
+                        *warned = true;
+                    }
+                },
+                LeakageAction::Abort => panic!(
+                    "Population of the highest level of the mode {} exceeds {:?} at the time {:?}, population value: {}",
+                    mode, policy.threshold, time, population,
+                ),
+            }
+        }
+    }
+}
+
+/// The last entries of time series of observables
+fn latest<X>(series: &[Vec<X>]) -> impl Iterator<Item = &X>
+{
+    series.iter().map(|entries| entries.last().expect("Empty time series"))
+}
+
+/// A state with random phases e^{i phi} of all the amplitudes, it overlaps with all symmetry sectors.
+fn random_phase_state<T>(basis: &Basis, rng: &mut StdRng) -> Vec<T>
+where
+    T: Value + TrueComplex,
+{
+    let mut state = basis.init_zero::<T>();
+    for elem in &mut state {
+        let phase = <T::Real as NumCast>::from(2. * std::f64::consts::PI * rng.gen::<f64>()).unwrap();
+        *elem = <T as TrueComplex>::new(Float::cos(phase), Float::sin(phase));
+    }
+    state
+}
+
+/// Unitary propagation of state vectors, exp(-iHt), with the Chebyshev expansion of the propagator.
+/// Every exponent is shifted and rescaled onto [-1, 1] with `spectral_bounds` (must be resolved),
+/// the order of the expansion is chosen per exponential to reach `tolerance`.
+struct Evolution<'a, T: ComplexFloat> {
+    hamiltonian: &'a [TermAndAmpl<T>],
+    basis: &'a Basis,
+    propagator: Propagator,
+    time_step_size: T::Real,
+    spectral_bounds: SpectralBounds<T::Real>,
+    tolerance: f64,
+}
+
+impl<'a, T> Evolution<'a, T>
+where
+    T: Value + TrueComplex + FromComplex64,
+    T::Real: Value,
+{
+    /// Propagates the state over the time step [time, time + time_step_size], returns the largest
+    /// order of expansions, exp and aux are auxiliary buffers that must be filled with zeros.
+    fn step(&self, state: &mut Vec<T>, exp: &mut Vec<T>, aux: &mut Vec<T>, time: T::Real) -> usize
+    {
+        let mut max_order = 0;
+        let time_dependent = self.hamiltonian.iter().any(|term| term.is_time_dependent());
+        for exponent in self.propagator.exponents(time, self.time_step_size, time_dependent) {
+            // bounds of the exponent sum_k weight_k H(time_k), the propagator is
+            // exp(-i time_step_size center) exp(i tau A), A = -(sum_k weight_k H(time_k) - center) / radius
+            let (lower, upper) = exponent.iter().fold((0., 0.), |(lower, upper), (time, weight)| {
+                let (l, u) = self.spectral_bounds.at(self.hamiltonian, self.basis, *time);
+                let weight = weight.to_f64().unwrap();
+                if weight >= 0. {
+                    (lower + weight * l, upper + weight * u)
+                } else {
+                    (lower + weight * u, upper + weight * l)
+                }
+            });
+            let center = (upper + lower) / 2.;
+            let radius = ((upper - lower) / 2.).max(f64::MIN_POSITIVE);
+            let time_step_size = self.time_step_size.to_f64().unwrap();
+            let tau = time_step_size * radius;
+            let update_fn = |dst: &mut Vec<T>, src: &Vec<T>, delta: T| {
+                for (time, weight) in &exponent {
+                    apply_hamiltonian(
+                        dst, src,
+                        self.hamiltonian,
+                        self.basis,
+                        *time,
+                        -delta * T::from(weight.to_f64().unwrap() / radius).unwrap(),
+                    );
+                }
+                add_inplace(dst, src, delta * T::from(center / radius).unwrap());
+            };
+            let state_norm = norm(state);
+            let order = unitary_order(tau, self.tolerance);
+            max_order = max_order.max(order);
+            cheb_exp_unitary::<Vec<T>, T>(
+                exp,
+                state,
+                aux,
+                update_fn,
+                |dst, src, delta| add_inplace(dst.as_mut_slice(), src.as_slice(), delta),
+                tau,
+                order,
+            );
+            let phase = <T::Real as NumCast>::from(-time_step_size * center).unwrap();
+            scale_inplace(exp, <T as TrueComplex>::new(Float::cos(phase), Float::sin(phase)));
+            let norm_change = (norm(exp) / state_norm).to_f64().unwrap() - 1.;
+            if norm_change.abs() > self.tolerance.sqrt() {
+                panic!(
+                    "Chebyshev expansion diverges, the norm of a state has changed by {} during a time step, \
+                    the spectrum of H lies outside of the spectral bounds [{}, {}]",
+                    norm_change, lower, upper,
+                );
+            }
+            std::mem::swap(exp, state);
+            set2zero(exp);
+            set2zero(aux);
+        }
+        max_order
+    }
+}
+
+impl<T> ChebyshevDynamics<T>
+where
+    T: Value + TrueComplex + FromComplex64 + std::iter::Sum + Debug,
+    T::Real: Value,
+{
+    /// Hash of the config without the checkpoint policy and the number of time steps,
+    /// thus a finished run may be extended as well
+    fn config_hash(&self) -> String
+    {
+        config_hash(&ChebyshevDynamics { checkpoint: None, total_time_steps_number: 0, ..self.clone() })
+    }
+
+    /// The checkpoint to resume the run from if it is set, its config must coincide with the task one
+    fn load_resumed(&self) -> Option<Checkpoint<T, ChebyshevDynamicsResult<T>>>
+    {
+        let path = self.resume.as_ref()?;
+        let checkpoint: Checkpoint<T, ChebyshevDynamicsResult<T>> = Checkpoint::load(path).expect("Unable to resume a run");
+        assert_eq!(checkpoint.config_hash, self.config_hash(), "The checkpoint {} was made for a different config", path);
+        assert!(checkpoint.step <= self.total_time_steps_number, "The checkpoint {} is made after the last time step", path);
+        info!("The run is resumed from the checkpoint {} after {} time steps", path, checkpoint.step);
+        Some(checkpoint)
+    }
+
+    pub fn run(&self, handoff: Option<&StageState<T>>, tolerance: f64, acc: T::Real) -> (ChebyshevDynamicsResult<T>, StageState<T>)
+    {
+        let local_dims = get_local_dims(&self.qubits_per_mode, &self.max_occupation);
+        let resumed = self.load_resumed();
+        let resumed_state = resumed.as_ref().map(|checkpoint| StageState::Pure(checkpoint.state.clone()));
+        let handoff = resumed_state.as_ref().or(handoff);
+        let occupations = StageState::occupations(handoff, &self.init_state, &local_dims);
+        let basis = Basis::new(&local_dims, &occupations, self.conserve_particle_number);
+        basis.check_terms(&self.hamiltonian);
+        let mut state = basis.init_handoff::<T>(&self.init_state, handoff);
+        let mut exp = basis.init_zero::<T>();
+        let mut aux = basis.init_zero::<T>();
+        let mut warned = vec![false; local_dims.len()];
+        let two_level_modes = two_level_modes(&self.hamiltonian);
+        let stream_step = |step: usize, density_matrices: &[Vec<Vec<T>>], top_level_populations: &[Vec<T::Real>]| {
+            if let Some(stream) = &self.stream {
+                stream.append(step, StepObservables {
+                    time: self.time_step_size * <T::Real as NumCast>::from(step).unwrap(),
+                    density_matrices: latest(density_matrices).collect(),
+                    top_level_populations: latest(top_level_populations).copied().collect(),
+                    energy: None,
+                    correlators: Vec::new(),
+                });
+            }
+        };
+        let (first_step, mut density_matrices, mut top_level_populations, mut diagnostics) = match resumed {
+            Some(checkpoint) => (
+                checkpoint.step,
+                checkpoint.observables.density_matrices,
+                checkpoint.observables.top_level_populations,
+                checkpoint.observables.diagnostics,
+            ),
+            None => {
+                let density_matrices: Vec<_> = self.density_matrices.iter()
+                    .map(|dens| vec![dens.get_density(&state, &basis)])
+                    .collect();
+                let top_level_populations: Vec<_> = basis.top_level_populations(&state).into_iter()
+                    .map(|population| vec![<T::Real as NumCast>::from(population).unwrap()])
+                    .collect();
+                stream_step(0, &density_matrices, &top_level_populations);
+                let diagnostics = Diagnostics { norms: vec![norm(&state)], chebyshev_order: 0 };
+                (0, density_matrices, top_level_populations, diagnostics)
+            },
+        };
+        let evolution = Evolution {
+            hamiltonian: &self.hamiltonian,
+            basis: &basis,
+            propagator: self.propagator,
+            time_step_size: self.time_step_size,
+            spectral_bounds: self.spectral_bounds.resolve(&self.hamiltonian, &basis),
+            tolerance,
+        };
+        for step in (first_step..self.total_time_steps_number).progress() {
+            let time = self.time_step_size * <T::Real as NumCast>::from(step).unwrap();
+            let order = evolution.step(&mut state, &mut exp, &mut aux, time);
+            diagnostics.chebyshev_order = diagnostics.chebyshev_order.max(order);
+            diagnostics.norms.push(norm(&state));
+            for (dens, dst) in self.density_matrices.iter().zip(&mut density_matrices)
+            {
+                let dens = dens.get_density(&state, &basis);
+                check_trace(&dens, acc);
+                dst.push(dens);
+            }
+            let populations = basis.top_level_populations(&state);
+            if let Some(policy) = &self.leakage_policy {
+                check_leakage(&populations, policy, &two_level_modes, &mut warned, time + self.time_step_size);
+            }
+            for (population, dst) in populations.into_iter().zip(&mut top_level_populations)
+            {
+                dst.push(<T::Real as NumCast>::from(population).unwrap());
+            }
+            stream_step(step + 1, &density_matrices, &top_level_populations);
+            if let Some(policy) = &self.checkpoint {
+                if (step + 1) % policy.every == 0 || step + 1 == self.total_time_steps_number {
+                    let checkpoint = Checkpoint {
+                        config_hash: self.config_hash(),
+                        step: step + 1,
+                        state: basis.to_full(&state),
+                        observables: ChebyshevDynamicsResult {
+                            density_matrices: density_matrices.clone(),
+                            top_level_populations: top_level_populations.clone(),
+                            diagnostics: diagnostics.clone(),
+                        },
+                    };
+                    checkpoint.save(&policy.path).expect("Unable to save a checkpoint");
+                }
+            }
+        }
+        let result = ChebyshevDynamicsResult {
+            density_matrices,
+            top_level_populations,
+            diagnostics,
+        };
+        (result, StageState::Pure(basis.to_full(&state)))
+    }
+}
+
+impl<T> TwoTimeCorrelators<T>
+where
+    T: Value + TrueComplex + FromComplex64 + std::iter::Sum + Debug,
+    T::Real: Value,
+{
+    pub fn run(&self, handoff: Option<&StageState<T>>, tolerance: f64) -> (TwoTimeCorrelatorsResult<T>, StageState<T>)
+    {
+        let local_dims = get_local_dims(&self.qubits_per_mode, &self.max_occupation);
+        let basis = Basis::Full(local_dims);
+        let mut bra = basis.init_handoff::<T>(&self.init_state, handoff);
+        let mut kets: Vec<_> = self.correlators.iter().map(|(_, b)| {
+            let mut ket = basis.init_zero::<T>();
+            apply_hamiltonian(&mut ket, &bra, b, &basis, T::Real::zero(), T::one());
+            ket
+        }).collect();
+        let mut exp = basis.init_zero::<T>();
+        let mut aux = basis.init_zero::<T>();
+        let mut correlators = vec![
+            Vec::with_capacity(self.total_time_steps_number + 1); self.correlators.len()
+        ];
+        let evolution = Evolution {
+            hamiltonian: &self.hamiltonian,
+            basis: &basis,
+            propagator: self.propagator,
+            time_step_size: self.time_step_size,
+            spectral_bounds: self.spectral_bounds.resolve(&self.hamiltonian, &basis),
+            tolerance,
+        };
+        let mut measure = |bra: &[T], kets: &[Vec<T>], aux: &mut Vec<T>, step: usize| {
+            let time = self.time_step_size * <T::Real as NumCast>::from(step).unwrap();
+            for (((a, _), ket), dst) in self.correlators.iter().zip(kets).zip(&mut correlators) {
+                apply_hamiltonian(aux, ket, a, &basis, time, T::one());
+                dst.push(dot(bra, aux));
+                set2zero(aux);
+            }
+            if let Some(stream) = &self.stream {
+                stream.append(step, StepObservables {
+                    time,
+                    density_matrices: Vec::new(),
+                    top_level_populations: Vec::new(),
+                    energy: None,
+                    correlators: latest(&correlators).copied().collect(),
+                });
+            }
+        };
+        measure(&bra, &kets, &mut aux, 0);
+        let mut diagnostics = Diagnostics { norms: vec![norm(&bra)], chebyshev_order: 0 };
+        for step in (0..self.total_time_steps_number).progress() {
+            let time = self.time_step_size * <T::Real as NumCast>::from(step).unwrap();
+            let mut order = evolution.step(&mut bra, &mut exp, &mut aux, time);
+            for ket in &mut kets {
+                order = order.max(evolution.step(ket, &mut exp, &mut aux, time));
+            }
+            diagnostics.chebyshev_order = diagnostics.chebyshev_order.max(order);
+            diagnostics.norms.push(norm(&bra));
+            measure(&bra, &kets, &mut aux, step + 1);
+        }
+        (TwoTimeCorrelatorsResult { correlators, diagnostics }, StageState::Pure(bra))
+    }
+}
+
+impl<T> GroundState<T>
+where
+    T: Value + TrueComplex + std::iter::Sum + Debug,
+    T::Real: Value,
+{
+    pub fn run(&self, handoff: Option<&StageState<T>>, acc: T::Real) -> (GroundStateResult<T>, StageState<T>)
+    {
+        let local_dims = get_local_dims(&self.qubits_per_mode, &self.max_occupation);
+        let occupations = StageState::occupations(handoff, &self.init_state, &local_dims);
+        let basis = Basis::new(&local_dims, &occupations, self.conserve_particle_number);
+        basis.check_terms(&self.hamiltonian);
+        let init_state = basis.init_handoff::<T>(&self.init_state, handoff);
+        let result = lanczos(
+            &init_state,
+            |dst, src| apply_hamiltonian(dst, src, &self.hamiltonian, &basis, T::Real::zero(), T::one()),
+            self.krylov_dim,
+            self.eigenvalues_number,
+            self.tolerance,
+            self.max_restarts,
+        );
+        info!(
+            "Ground state energy: {:?}, residual: {:?}",
+            result.eigenvalues.first(),
+            result.residual,
+        );
+        let density_matrices = self.density_matrices.iter().map(|dens| {
+            let dens = dens.get_density(&result.ground_state, &basis);
+            check_trace(&dens, acc);
+            dens
+        }).collect();
+        let state = StageState::Pure(basis.to_full(&result.ground_state));
+        let result = GroundStateResult {
+            eigenvalues: result.eigenvalues,
+            state: result.ground_state,
+            density_matrices,
+        };
+        (result, state)
+    }
+}
+
+impl<T> ImaginaryTimeDynamics<T>
+where
+    T: Value + TrueComplex + FromComplex64 + std::iter::Sum + Debug,
+    T::Real: Value,
+{
+    fn energy(&self, state: &[T], basis: &Basis, aux: &mut [T]) -> T::Real
+    {
+        set2zero(aux);
+        apply_hamiltonian(aux, state, &self.hamiltonian, basis, T::Real::zero(), T::one());
+        dot(state, aux).re()
+    }
+
+    pub fn run(&self, handoff: Option<&StageState<T>>, tolerance: f64, acc: T::Real) -> (ImaginaryTimeDynamicsResult<T>, StageState<T>)
+    {
+        let local_dims = get_local_dims(&self.qubits_per_mode, &self.max_occupation);
+        let occupations = StageState::occupations(handoff, &self.init_state, &local_dims);
+        let basis = Basis::new(&local_dims, &occupations, self.conserve_particle_number);
+        basis.check_terms(&self.hamiltonian);
+        let (lower, upper) = self.spectral_bounds
+            .resolve(&self.hamiltonian, &basis)
+            .at(&self.hamiltonian, &basis, T::Real::zero());
+        // exp(-time_step_size * H) is proportional to exp(-tau A), A = (H - center) / radius
+        let center = (upper + lower) / 2.;
+        let radius = ((upper - lower) / 2.).max(f64::MIN_POSITIVE);
+        let tau = self.time_step_size.to_f64().unwrap() * radius;
+        let order = real_order(tau, tolerance);
+        let mut state = basis.init_handoff::<T>(&self.init_state, handoff);
+        let mut exp = basis.init_zero::<T>();
+        let mut aux = basis.init_zero::<T>();
+        let mut energies = Vec::with_capacity(self.total_time_steps_number + 1);
+        let mut density_matrices = vec![
+            Vec::with_capacity(self.total_time_steps_number + 1); self.density_matrices.len()
+        ];
+        let stream_step = |step: usize, energies: &[T::Real], density_matrices: &[Vec<Vec<T>>]| {
+            if let Some(stream) = &self.stream {
+                stream.append(step, StepObservables {
+                    time: self.time_step_size * <T::Real as NumCast>::from(step).unwrap(),
+                    density_matrices: latest(density_matrices).collect(),
+                    top_level_populations: Vec::new(),
+                    energy: energies.last().copied(),
+                    correlators: Vec::new(),
+                });
+            }
+        };
+        energies.push(self.energy(&state, &basis, &mut aux));
+        for (dens, dst) in self.density_matrices.iter().zip(&mut density_matrices)
+        {
+            dst.push(dens.get_density(&state, &basis));
+        }
+        let mut diagnostics = Diagnostics { norms: vec![norm(&state)], chebyshev_order: order };
+        stream_step(0, &energies, &density_matrices);
+        for step in (0..self.total_time_steps_number).progress() {
+            let update_fn = |dst: &mut Vec<T>, src: &Vec<T>, delta: T| {
+                apply_hamiltonian(
+                    dst, src,
+                    &self.hamiltonian,
+                    &basis,
+                    T::Real::zero(),
+                    delta * T::from(1. / radius).unwrap(),
+                );
+                add_inplace(dst, src, -delta * T::from(center / radius).unwrap());
+            };
+            set2zero(&mut aux);
+            cheb_exp_real::<Vec<T>, T>(
+                &mut exp,
+                &mut state,
+                &mut aux,
+                update_fn,
+                |dst, src, delta| add_inplace(dst.as_mut_slice(), src.as_slice(), delta),
+                tau,
+                order
+            );
+            std::mem::swap(&mut exp, &mut state);
+            set2zero(&mut exp);
+            let state_norm = norm(&state);
+            diagnostics.norms.push(state_norm);
+            scale_inplace(&mut state, <T as TrueComplex>::new(T::Real::one() / state_norm, T::Real::zero()));
+            let energy = self.energy(&state, &basis, &mut aux);
+            let energy_change = Float::abs(energy - *energies.last().unwrap());
+            info!("Imaginary time step {}: energy {:?}, energy change {:?}", step + 1, energy, energy_change);
+            energies.push(energy);
+            for (dens, dst) in self.density_matrices.iter().zip(&mut density_matrices)
+            {
+                let dens = dens.get_density(&state, &basis);
+                check_trace(&dens, acc);
+                dst.push(dens);
+            }
+            stream_step(step + 1, &energies, &density_matrices);
+            if let Some(energy_tolerance) = self.energy_tolerance {
+                if energy_change < energy_tolerance {
+                    info!("Energy has converged after {} imaginary time steps", step + 1);
+                    break;
+                }
+            }
+        }
+        let full_state = StageState::Pure(basis.to_full(&state));
+        let result = ImaginaryTimeDynamicsResult {
+            energies,
+            state,
+            density_matrices,
+            diagnostics,
+        };
+        (result, full_state)
+    }
+}
+
+impl<T> LindbladDynamics<T>
+where
+    T: Value + TrueComplex + FromComplex64 + std::iter::Sum + Debug,
+    T::Real: Value,
+{
+    /// dst += delta * L * src, where L is the Liouvillian acting on the vectorized density matrix
+    fn apply_liouvillian(&self, dst: &mut [T], src: &[T], aux: &mut [T], basis: &Basis, time: T::Real, delta: T)
+    {
+        let modes_number = basis.local_dims().len() / 2;
+        let imag = <T as TrueComplex>::new(T::Real::zero(), T::Real::one());
+        let half = T::from(0.5).unwrap();
+        for term in &self.hamiltonian {
+            term.apply(dst, src, basis, time, -delta * imag);
+            term.transposed().shifted(modes_number).apply(dst, src, basis, time, delta * imag);
+        }
+        for jump in &self.jump_operators {
+            let rate = <T as TrueComplex>::new(jump.rate(time), T::Real::zero());
+            set2zero(aux);
+            // the bra side is acted on by the complex conjugate operators
+            jump.conjugated().shifted(modes_number).apply_operator(aux, src, basis, T::one());
+            jump.apply_operator(dst, aux, basis, delta * rate);
+            jump.apply_hermitian_square(dst, src, basis.local_dims(), -delta * rate * half);
+            jump.conjugated().shifted(modes_number).apply_hermitian_square(dst, src, basis.local_dims(), -delta * rate * half);
+        }
+    }
+
+    /// The initial density matrix, either the handed off one, the projector onto the handed off
+    /// state vector or the projector onto the Fock state `init_state`
+    fn init_rho(&self, handoff: Option<&StageState<T>>, local_dims: &[usize]) -> Vec<T>
+    {
+        let doubled_local_dims = [local_dims, local_dims].concat();
+        match handoff {
+            None => {
+                let init_state = [self.init_state.fock(), self.init_state.fock()].concat();
+                init_custom::<T>(&init_state, &doubled_local_dims)
+            },
+            Some(StageState::Mixed(rho)) => {
+                assert_eq!(
+                    rho.len(), doubled_local_dims.iter().product::<usize>(),
+                    "The handed off density matrix does not match local dimensions of modes {:?}", local_dims,
+                );
+                rho.clone()
+            },
+            Some(handoff) => {
+                let state = handoff.pure(local_dims);
+                state.iter().flat_map(|bra| state.iter().map(move |ket| *ket * bra.conj())).collect()
+            },
+        }
+    }
+
+    pub fn run(&self, handoff: Option<&StageState<T>>, tolerance: f64, acc: T::Real) -> (LindbladDynamicsResult<T>, StageState<T>)
+    {
+        let order = exp_order(tolerance);
+        let local_dims = get_local_dims(&self.qubits_per_mode, &self.max_occupation);
+        let doubled_local_dims = [&local_dims[..], &local_dims[..]].concat();
+        let mut rho = self.init_rho(handoff, &local_dims);
+        let mut exp = init_zero::<T>(&doubled_local_dims);
+        let mut aux = init_zero::<T>(&doubled_local_dims);
+        let jump_aux = RefCell::new(init_zero::<T>(&doubled_local_dims));
+        let basis = Basis::Full(doubled_local_dims);
+        let mut density_matrices = vec![
+            Vec::with_capacity(self.total_time_steps_number + 1); self.density_matrices.len()
+        ];
+        let stream_step = |step: usize, density_matrices: &[Vec<Vec<T>>]| {
+            if let Some(stream) = &self.stream {
+                stream.append(step, StepObservables {
+                    time: self.time_step_size * <T::Real as NumCast>::from(step).unwrap(),
+                    density_matrices: latest(density_matrices).collect(),
+                    top_level_populations: Vec::new(),
+                    energy: None,
+                    correlators: Vec::new(),
+                });
+            }
+        };
+        let size = local_dims.iter().product::<usize>();
+        let trace = |rho: &[T]| (0..size).map(|i| rho[i + size * i]).sum::<T>().re();
+        let mut diagnostics = Diagnostics { norms: vec![trace(&rho)], chebyshev_order: order };
+        for (dens, dst) in self.density_matrices.iter().zip(&mut density_matrices)
+        {
+            dst.push(dens.get_mixed_density(&rho, &local_dims));
+        }
+        stream_step(0, &density_matrices);
+        for step in (0..self.total_time_steps_number).progress() {
+            // ||L|| <= 2 ||H|| + 2 sum_k rate_k ||L_k||^2, the bound is taken in the middle of the time step
+            let time = self.time_step_size * (<T::Real as NumCast>::from(step).unwrap() + <T::Real as NumCast>::from(0.5).unwrap());
+            let (lower, upper) = SpectralBounds::TermNorms.at(&self.hamiltonian, &basis, time);
+            let norm_bound = 2. * (lower.abs().max(upper.abs()) + dissipation_norm(&self.jump_operators, &local_dims, time));
+            let substeps = substeps_number(norm_bound, self.time_step_size.to_f64().unwrap());
+            let substep_size = self.time_step_size / <T::Real as NumCast>::from(substeps).unwrap();
+            for substep in 0..substeps {
+                let time = self.time_step_size * <T::Real as NumCast>::from(step).unwrap()
+                    + substep_size * (<T::Real as NumCast>::from(substep).unwrap() + <T::Real as NumCast>::from(0.5).unwrap());
+                let update_fn = |dst: &mut Vec<T>, src: &Vec<T>, delta| {
+                    self.apply_liouvillian(
+                        dst, src,
+                        &mut jump_aux.borrow_mut(),
+                        &basis,
+                        time,
+                        delta * <T as TrueComplex>::new(substep_size, T::Real::zero()),
+                    );
+                };
+                let rho_trace = trace(&rho);
+                cheb_exp::<Vec<T>, T>(
+                    &mut exp,
+                    &mut rho,
+                    &mut aux,
+                    update_fn,
+                    |dst, src, delta| add_inplace(dst.as_mut_slice(), src.as_slice(), delta),
+                    order
+                );
+                let trace_change = (trace(&exp) - rho_trace).to_f64().unwrap();
+                if trace_change.abs() > tolerance.sqrt() {
+                    panic!(
+                        "Chebyshev expansion diverges, the trace of a density matrix has changed by {} during a time step, \
+                        the norm of the Liouvillian exceeds the bound {}",
+                        trace_change, norm_bound,
+                    );
+                }
+                std::mem::swap(&mut exp, &mut rho);
+                set2zero(&mut exp);
+                set2zero(&mut aux);
+            }
+            for (dens, dst) in self.density_matrices.iter().zip(&mut density_matrices)
+            {
+                let dens = dens.get_mixed_density(&rho, &local_dims);
+                check_trace(&dens, acc);
+                dst.push(dens);
+            }
+            diagnostics.norms.push(trace(&rho));
+            stream_step(step + 1, &density_matrices);
+        }
+        (LindbladDynamicsResult { density_matrices, diagnostics }, StageState::Mixed(rho))
+    }
+}
+
+/// Number of bisections of a (sub-)step resolving the time of a quantum jump
+const JUMP_TIME_BISECTIONS: usize = 16;
+
+/// Observables of a single trajectory: reduced density matrices and norms of the state before
+/// renormalization at every time step, and the number of quantum jumps.
+struct Trajectory<T: ComplexFloat> {
+    density_matrices: Vec<Vec<Vec<T>>>,
+    norms: Vec<T::Real>,
+    jumps_number: usize,
+}
+
+/// Sums and sums of squares of real and imaginary parts of observables over trajectories,
+/// sums of norms and numbers of jumps labeled by indices of trajectories.
+struct TrajectoriesAccumulator<T: ComplexFloat> {
+    sum: Vec<Vec<Vec<T>>>,
+    sum_sq: Vec<Vec<Vec<T>>>,
+    norms: Vec<T::Real>,
+    jumps_numbers: Vec<(usize, usize)>,
+    count: usize,
+}
+
+impl<T> TrajectoriesAccumulator<T>
+where
+    T: Value + TrueComplex,
+    T::Real: Value,
+{
+    fn new() -> Self
+    {
+        TrajectoriesAccumulator { sum: Vec::new(), sum_sq: Vec::new(), norms: Vec::new(), jumps_numbers: Vec::new(), count: 0 }
+    }
+
+    fn merge(mut self, other: Self) -> Self
+    {
+        if self.count == 0 {
+            return other;
+        }
+        if other.count == 0 {
+            return self;
+        }
+        for (dst, src) in self.sum.iter_mut().flatten().flatten().zip(other.sum.into_iter().flatten().flatten()) {
+            *dst = *dst + src;
+        }
+        for (dst, src) in self.sum_sq.iter_mut().flatten().flatten().zip(other.sum_sq.into_iter().flatten().flatten()) {
+            *dst = *dst + src;
+        }
+        for (dst, src) in self.norms.iter_mut().zip(other.norms) {
+            *dst = *dst + src;
+        }
+        self.jumps_numbers.extend(other.jumps_numbers);
+        self.count += other.count;
+        self
+    }
+
+    /// Adds the trajectory with the index `i`
+    fn add(self, i: usize, trajectory: Trajectory<T>) -> Self
+    {
+        let sum_sq = trajectory.density_matrices.iter().map(|dens_per_step| {
+            dens_per_step.iter().map(|dens| {
+                dens.iter().map(|x| <T as TrueComplex>::new(x.re() * x.re(), x.im() * x.im())).collect()
+            }).collect()
+        }).collect();
+        self.merge(TrajectoriesAccumulator {
+            sum: trajectory.density_matrices,
+            sum_sq,
+            norms: trajectory.norms,
+            jumps_numbers: vec![(i, trajectory.jumps_number)],
+            count: 1,
+        })
+    }
+
+    /// Means and standard errors of the mean, means of norms and numbers of jumps in the order of trajectories
+    fn finalize(mut self, chebyshev_order: usize) -> QuantumTrajectoriesResult<T>
+    {
+        let count = <T::Real as NumCast>::from(self.count).unwrap();
+        let dof = <T::Real as NumCast>::from(std::cmp::max(self.count, 2) - 1).unwrap();
+        let mean: Vec<Vec<Vec<T>>> = self.sum.iter().map(|dens_per_step| {
+            dens_per_step.iter().map(|dens| {
+                dens.iter().map(|x| <T as TrueComplex>::new(x.re() / count, x.im() / count)).collect()
+            }).collect()
+        }).collect();
+        let std_err = |mean: T::Real, sq: T::Real| {
+            let var = (sq / count - mean * mean) * count / dof;
+            Float::sqrt(Float::max(var, T::Real::zero()) / count)
+        };
+        let errors = mean.iter().zip(&self.sum_sq).map(|(mean_per_step, sq_per_step)| {
+            mean_per_step.iter().zip(sq_per_step).map(|(mean, sq)| {
+                mean.iter().zip(sq).map(|(m, s)| {
+                    <T as TrueComplex>::new(std_err(m.re(), s.re()), std_err(m.im(), s.im()))
+                }).collect()
+            }).collect()
+        }).collect();
+        self.jumps_numbers.sort();
+        QuantumTrajectoriesResult {
+            density_matrices: mean,
+            density_matrices_errors: errors,
+            diagnostics: Diagnostics {
+                norms: self.norms.into_iter().map(|norm| norm / count).collect(),
+                chebyshev_order,
+            },
+            jumps_numbers: self.jumps_numbers.into_iter().map(|(_, jumps_number)| jumps_number).collect(),
+        }
+    }
+}
+
+impl<T> QuantumTrajectories<T>
+where
+    T: Value + TrueComplex + FromComplex64 + std::iter::Sum + Debug,
+    T::Real: Value,
+{
+    /// dst += delta * (-i) * H_eff * src, where H_eff = H - i/2 sum_k rate_k L_k^dagger L_k
+    fn apply_effective_hamiltonian(&self, dst: &mut [T], src: &[T], basis: &Basis, time: T::Real, delta: T)
+    {
+        let imag = <T as TrueComplex>::new(T::Real::zero(), T::Real::one());
+        let half = T::from(0.5).unwrap();
+        apply_hamiltonian(dst, src, &self.hamiltonian, basis, time, -delta * imag);
+        for jump in &self.jump_operators {
+            let rate = <T as TrueComplex>::new(jump.rate(time), T::Real::zero());
+            jump.apply_hermitian_square(dst, src, basis.local_dims(), -delta * rate * half);
+        }
+    }
+
+    /// Applies a randomly chosen jump operator to the state and normalizes it, returns false
+    /// leaving the state as is if no jump is possible at the given time.
+    fn jump(&self, state: &mut Vec<T>, aux: &mut Vec<T>, basis: &Basis, time: T::Real, rng: &mut StdRng) -> bool
+    {
+        let weights: Vec<_> = self.jump_operators.iter().map(|jump| {
+            set2zero(aux);
+            jump.apply_operator(aux, state, basis, T::one());
+            jump.rate(time) * Float::powi(norm(aux), 2)
+        }).collect();
+        let total = weights.iter().fold(T::Real::zero(), |acc, w| acc + *w);
+        if total == T::Real::zero() {
+            return false;
+        }
+        let threshold = total * <T::Real as NumCast>::from(rng.gen::<f64>()).unwrap();
+        let mut cumulative = T::Real::zero();
+        let mut chosen = weights.len() - 1;
+        for (i, w) in weights.iter().enumerate() {
+            cumulative = cumulative + *w;
+            if threshold < cumulative {
+                chosen = i;
+                break;
+            }
+        }
+        set2zero(aux);
+        self.jump_operators[chosen].apply_operator(aux, state, basis, T::one());
+        let aux_norm = norm(aux);
+        scale_inplace(aux, <T as TrueComplex>::new(T::Real::one() / aux_norm, T::Real::zero()));
+        std::mem::swap(state, aux);
+        true
+    }
+
+    fn run_trajectory(&self, init_state: &[T], seed: u64, tolerance: f64, order: usize) -> Trajectory<T>
+    {
+        let local_dims = get_local_dims(&self.qubits_per_mode, &self.max_occupation);
+        let basis = Basis::Full(local_dims);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut state = init_state.to_owned();
+        let mut exp = basis.init_zero::<T>();
+        let mut aux = basis.init_zero::<T>();
+        let mut threshold = <T::Real as NumCast>::from(rng.gen::<f64>()).unwrap();
+        let mut density_matrices = vec![
+            Vec::with_capacity(self.total_time_steps_number + 1); self.density_matrices.len()
+        ];
+        for (dens, dst) in self.density_matrices.iter().zip(&mut density_matrices)
+        {
+            dst.push(dens.get_density(&state, &basis));
+        }
+        let mut norms = Vec::with_capacity(self.total_time_steps_number + 1);
+        norms.push(norm(&state));
+        let mut jumps_number = 0;
+        let mut start_state = basis.init_zero::<T>();
+        let half = <T::Real as NumCast>::from(0.5).unwrap();
+        for step in 0..self.total_time_steps_number {
+            // ||H_eff|| <= ||H|| + sum_k rate_k ||L_k||^2 / 2, the bound is taken in the middle of the time step
+            let step_start = self.time_step_size * <T::Real as NumCast>::from(step).unwrap();
+            let time = step_start + self.time_step_size * half;
+            let (lower, upper) = SpectralBounds::TermNorms.at(&self.hamiltonian, &basis, time);
+            let norm_bound = lower.abs().max(upper.abs()) + 0.5 * dissipation_norm(&self.jump_operators, basis.local_dims(), time);
+            let substeps = substeps_number(norm_bound, self.time_step_size.to_f64().unwrap());
+            let substep_size = self.time_step_size / <T::Real as NumCast>::from(substeps).unwrap();
+            // propagates the state from `start` by `duration` not exceeding the sub-step size
+            let propagate = |state: &mut Vec<T>, exp: &mut Vec<T>, aux: &mut Vec<T>, start: T::Real, duration: T::Real| {
+                let time = start + duration * half;
+                let update_fn = |dst: &mut Vec<T>, src: &Vec<T>, delta| {
+                    self.apply_effective_hamiltonian(
+                        dst, src,
+                        &basis,
+                        time,
+                        delta * <T as TrueComplex>::new(duration, T::Real::zero()),
+                    );
+                };
+                let state_norm = norm(state);
+                cheb_exp::<Vec<T>, T>(
+                    exp,
+                    state,
+                    aux,
+                    update_fn,
+                    |dst, src, delta| add_inplace(dst.as_mut_slice(), src.as_slice(), delta),
+                    order
+                );
+                // the effective Hamiltonian does not increase the norm
+                let norm_change = (norm(exp) / state_norm).to_f64().unwrap() - 1.;
+                if norm_change > tolerance.sqrt() {
+                    panic!(
+                        "Chebyshev expansion diverges, the norm of a state has grown by {} during a time step, \
+                        the norm of the effective Hamiltonian exceeds the bound {}",
+                        norm_change, norm_bound,
+                    );
+                }
+                std::mem::swap(exp, state);
+                set2zero(exp);
+                set2zero(aux);
+            };
+            for substep in 0..substeps {
+                let mut start = step_start + substep_size * <T::Real as NumCast>::from(substep).unwrap();
+                let mut duration = substep_size;
+                start_state.clone_from(&state);
+                propagate(&mut state, &mut exp, &mut aux, start, duration);
+                while Float::powi(norm(&state), 2) < threshold {
+                    // the squared norm decreases monotonically, the jump time is found by bisection
+                    let (mut lower, mut upper) = (T::Real::zero(), duration);
+                    for _ in 0..JUMP_TIME_BISECTIONS {
+                        let middle = (lower + upper) * half;
+                        state.clone_from(&start_state);
+                        propagate(&mut state, &mut exp, &mut aux, start, middle);
+                        if Float::powi(norm(&state), 2) < threshold {
+                            upper = middle;
+                        } else {
+                            lower = middle;
+                        }
+                    }
+                    state.clone_from(&start_state);
+                    propagate(&mut state, &mut exp, &mut aux, start, upper);
+                    let jumped = self.jump(&mut state, &mut aux, &basis, start + upper, &mut rng);
+                    set2zero(&mut aux);
+                    if !jumped {
+                        break;
+                    }
+                    jumps_number += 1;
+                    threshold = <T::Real as NumCast>::from(rng.gen::<f64>()).unwrap();
+                    start = start + upper;
+                    duration = duration - upper;
+                    start_state.clone_from(&state);
+                    propagate(&mut state, &mut exp, &mut aux, start, duration);
+                }
+            }
+            let state_norm = norm(&state);
+            norms.push(state_norm);
+            let norm_sq = Float::powi(state_norm, 2);
+            let scale = <T as TrueComplex>::new(T::Real::one() / norm_sq, T::Real::zero());
+            for (dens, dst) in self.density_matrices.iter().zip(&mut density_matrices)
+            {
+                let dens = dens.get_density(&state, &basis);
+                dst.push(dens.into_iter().map(|x| x * scale).collect());
+            }
+        }
+        Trajectory { density_matrices, norms, jumps_number }
+    }
+
+    pub fn run(&self, handoff: Option<&StageState<T>>, tolerance: f64, acc: T::Real) -> QuantumTrajectoriesResult<T>
+    {
+        let order = exp_order(tolerance);
+        let local_dims = get_local_dims(&self.qubits_per_mode, &self.max_occupation);
+        let init_state = Basis::Full(local_dims).init_handoff::<T>(&self.init_state, handoff);
+        let seed = self.seed.unwrap_or_else(rand::random);
+        info!("Quantum trajectories seed: {}", seed);
+        let progress_bar = indicatif::ProgressBar::new(self.trajectories_number as u64);
+        // trajectories are distributed over OS threads, the state vector subroutines of
+        // each trajectory use the global rayon thread pool
+        let threads_num = std::cmp::max(std::cmp::min(get_physical(), self.trajectories_number), 1);
+        let next_trajectory = AtomicUsize::new(0);
+        let accumulator = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..threads_num).map(|_| {
+                s.spawn(|| {
+                    let mut accumulator = TrajectoriesAccumulator::new();
+                    loop {
+                        let i = next_trajectory.fetch_add(1, Ordering::Relaxed);
+                        if i >= self.trajectories_number {
+                            break;
+                        }
+                        accumulator = accumulator.add(i, self.run_trajectory(&init_state, seed.wrapping_add(i as u64), tolerance, order));
+                        progress_bar.inc(1);
+                    }
+                    accumulator
+                })
+            }).collect();
+            handles.into_iter()
+                .map(|handle| handle.join().expect("A trajectory thread has panicked"))
+                .fold(TrajectoriesAccumulator::new(), |lhs, rhs| lhs.merge(rhs))
+        });
+        progress_bar.finish();
+        let result = accumulator.finalize(order);
+        for dens in result.density_matrices.iter().flatten() {
+            check_trace(dens, acc);
+        }
+        if let Some(stream) = &self.stream {
+            for step in 0..=self.total_time_steps_number {
+                stream.append(step, StepObservables {
+                    time: self.time_step_size * <T::Real as NumCast>::from(step).unwrap(),
+                    density_matrices: result.density_matrices.iter().map(|series| &series[step]).collect(),
+                    top_level_populations: Vec::new(),
+                    energy: None,
+                    correlators: Vec::<T>::new(),
+                });
+            }
+        }
+        result
+    }
+}
+
+impl<T> KernelPolynomial<T>
+where
+    T: Value + TrueComplex + FromComplex64 + std::iter::Sum + Debug,
+    T::Real: Value,
+{
+    pub fn run(&self, handoff: Option<&StageState<T>>) -> (KernelPolynomialResult<T>, StageState<T>)
+    {
+        let local_dims = get_local_dims(&self.qubits_per_mode, &self.max_occupation);
+        let basis = Basis::Full(local_dims);
+        let seed = self.seed.unwrap_or_else(rand::random);
+        info!("Kernel polynomial method seed: {}", seed);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let (lower, upper) = self.spectral_bounds
+            .unwrap_or(SpectralBounds::Lanczos(64))
+            .resolve(&self.hamiltonian, &basis)
+            .at(&self.hamiltonian, &basis, T::Real::zero());
+        assert!(upper > lower, "Invalid spectral bounds [{}, {}]", lower, upper);
+        let scale = (upper - lower) / (2. - KPM_EPSILON);
+        let center = (upper + lower) / 2.;
+        // dst += delta * (H - center) / scale * src
+        let update_fn = |dst: &mut Vec<T>, src: &Vec<T>, delta: T| {
+            let delta = delta * T::from(1. / scale).unwrap();
+            apply_hamiltonian(dst, src, &self.hamiltonian, &basis, T::Real::zero(), delta);
+            add_inplace(dst, src, -delta * T::from(center).unwrap());
+        };
+        let dim = basis.dim() as f64;
+        let mut moments = vec![0f64; self.moments_number];
+        for _ in (0..self.random_vectors_number).progress() {
+            let state = random_phase_state(&basis, &mut rng);
+            let sample = chebyshev_moments(&state, update_fn, self.moments_number);
+            for (mu, sample) in moments.iter_mut().zip(sample) {
+                *mu += sample / (dim * self.random_vectors_number as f64);
+            }
+        }
+        let init_state = basis.init_handoff::<T>(&self.init_state, handoff);
+        let local_moments: Vec<_> = self.local_operators.iter().map(|op| {
+            let mut state = basis.init_zero::<T>();
+            apply_hamiltonian(&mut state, &init_state, op, &basis, T::Real::zero(), T::one());
+            chebyshev_moments(&state, update_fn, self.moments_number)
+        }).collect();
+        let points = chebyshev_nodes(self.energy_points_number);
+        // densities of x = (E - center) / scale are rescaled to densities of E
+        let to_real = |values: Vec<f64>| -> Vec<T::Real> {
+            values.into_iter().map(|x| <T::Real as NumCast>::from(x).unwrap()).collect()
+        };
+        let density_of_states = spectral_density(&moments, &points).into_iter().map(|x| x / scale).collect();
+        let local_spectral_functions = local_moments.iter().map(|moments| {
+            to_real(spectral_density(moments, &points).into_iter().map(|x| x / scale).collect())
+        }).collect();
+        let result = KernelPolynomialResult {
+            energies: to_real(points.iter().map(|x| scale * x + center).collect()),
+            density_of_states: to_real(density_of_states),
+            local_spectral_functions,
+            moments: to_real(moments),
+            local_moments: local_moments.into_iter().map(to_real).collect(),
+            spectral_bounds: (
+                <T::Real as NumCast>::from(lower).unwrap(),
+                <T::Real as NumCast>::from(upper).unwrap(),
+            ),
+        };
+        // the method does not change the state, thus it is handed off as is
+        (result, StageState::Pure(init_state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_complex::Complex64;
+    use super::*;
+
+    /// Parses a task config
+    fn parse(config: &str) -> Result<Task<Complex64>, String>
+    {
+        Task::from_value(serde_yaml::from_str(config).unwrap())
+    }
+
+    /// Parses a config of the task `$variant`, e.g. `parse_task!(GroundState, config)`
+    macro_rules! parse_task {
+        ($variant:ident, $config:expr) => {
+            match parse(&$config).unwrap() {
+                Task::$variant(task) => task,
+                _ => panic!("Unexpected task"),
+            }
+        };
+    }
+
+    /// A config of a ground state task of two qubit modes with the given Hamiltonian terms
+    fn ground_state(terms: &str) -> String
+    {
+        format!(
+            "!GroundState {{qubits_per_mode: [1, 1], init_state: [1, 0], density_matrices: [], \
+            krylov_dim: 2, eigenvalues_number: 1, tolerance: 1e-6, max_restarts: 1, hamiltonian: {}}}",
+            terms,
+        )
+    }
+
+    /// A ground state task of two qubit modes with the given Hamiltonian terms
+    fn hamiltonian(terms: &str) -> Result<Task<Complex64>, String>
+    {
+        parse(&ground_state(terms))
+    }
+
+    #[test]
+    fn test_hermiticity()
+    {
+        let task = parse_task!(GroundState, ground_state("[{ampl: -1, pos: [0, 1], ops: [A+, A-], hc: true}, {ampl: 0.5, pos: [0], ops: [N1]}]"));
+        assert_eq!(task.hamiltonian.len(), 3);
+        assert_eq!(task.hamiltonian[1].term(), Term::new(&[0, 1], &[Op::Lowering, Op::Rising]));
+        assert!(hamiltonian("[{ampl: -1, pos: [0, 1], ops: [A+, A-]}, {ampl: -1, pos: [0, 1], ops: [A-, A+]}]").is_ok());
+        assert!(hamiltonian("[{ampl: -1, pos: [0, 1], ops: [A+, A-]}, {ampl: -0.5, pos: [0, 1], ops: [A-, A+]}, \
+            {ampl: -0.5, pos: [0, 1], ops: [A-, A+]}]").is_ok());
+        assert!(hamiltonian("[{ampl: -1, pos: [0, 1], ops: [A+, A-]}]").is_err());
+        assert!(hamiltonian("[{ampl: -1, pos: [0, 1], ops: [A+, A-]}, {ampl: -2, pos: [0, 1], ops: [A-, A+]}]").is_err());
+        assert!(hamiltonian("[{ampl: \"t\", pos: [0, 1], ops: [A+, A-], hc: true}]").is_ok());
+        assert!(hamiltonian("[{ampl: -1, pos: [0, 1], ops: [A+, A-], hc: true}, {ampl: -1, pos: [0, 1], ops: [A-, A+]}]").is_err());
+    }
+
+    #[test]
+    fn test_complex_amplitudes()
+    {
+        let task = parse_task!(GroundState, ground_state("[{ampl: {abs: 1, phase: 0.3}, pos: [0, 1], ops: [A+, A-], hc: true}]"));
+        assert!((task.hamiltonian[1].ampl(0.) - Complex64::from_polar(1., -0.3)).norm() < 1e-12);
+        assert!(hamiltonian("[{ampl: {re: 1, im: 2}, pos: [0, 1], ops: [A+, A-]}, {ampl: {re: 1, im: -2}, pos: [0, 1], ops: [A-, A+]}]").is_ok());
+        assert!(hamiltonian("[{ampl: {abs: 1, phase: 0.3}, pos: [0, 1], ops: [A+, A-]}, \
+            {ampl: {abs: 1, phase: 0.3}, pos: [0, 1], ops: [A-, A+]}]").is_err());
+        assert!(hamiltonian("[{ampl: {re: 1, im: 1}, pos: [0], ops: [N1]}]").is_err());
+        assert!(hamiltonian("[{ampl: {re: 1, im: 1}, pos: [0], ops: [N1]}, {ampl: {re: 1, im: -1}, pos: [0], ops: [N1]}]").is_ok());
+    }
+
+    #[test]
+    fn test_operator_strings()
+    {
+        // operator strings of any length with repeated positions
+        assert!(hamiltonian("[{ampl: 0.5, pos: [1, 1, 1, 1], ops: [A+, A+, A-, A-]}]").is_ok());
+        assert!(hamiltonian("[{ampl: 1, pos: [1, 1, 0], ops: [A+, A+, A-]}]").is_err());
+        assert!(hamiltonian("[{ampl: 1, pos: [1, 1, 0], ops: [A+, A+, A-], hc: true}]").is_ok());
+        assert!(hamiltonian("[{ampl: 1, pos: [1, 0, 1, 0, 1], ops: [A+, A-, A+, A-, N1]}, \
+            {ampl: 1, pos: [0, 0, 1, 1, 1], ops: [A+, A+, N1, A-, A-]}]").is_ok());
+        assert!(hamiltonian("[{ampl: 1, pos: [0, 1], ops: [A+]}]").is_err());
+    }
+
+    #[test]
+    fn test_pauli_terms()
+    {
+        assert!(hamiltonian("[{ampl: 1, pos: [0], ops: [Y]}, {ampl: 0.5, pos: [0, 1], ops: [X, Z]}]").is_ok());
+        assert!(hamiltonian("[{ampl: 1, pos: [0, 1], ops: [Y, Y]}, {ampl: 1, pos: [0, 1], ops: [S+, S-], hc: true}]").is_ok());
+        assert!(hamiltonian("[{ampl: {re: 0, im: 1}, pos: [0], ops: [Y]}]").is_err());
+        assert!(hamiltonian("[{ampl: 1, pos: [0, 0], ops: [Y, Z]}]").is_err());
+        assert!(hamiltonian("[{ampl: 1, pos: [0, 0], ops: [Y, Z], hc: true}]").is_ok());
+        assert!(hamiltonian("[{ampl: 1, pos: [0], ops: [σ+]}, {ampl: 1, pos: [0], ops: [S-]}]").is_ok());
+        assert!(hamiltonian("[{ampl: 1, pos: [0], ops: [-Y]}]").is_err());
+        let task = parse_task!(GroundState, ground_state("[{ampl: 1, pos: [0, 1], ops: [X, Y]}]"));
+        assert!((task.hamiltonian[0].norm(&[2, 2], 0.) - 4.).abs() < 1e-12);
+        assert!(!task.hamiltonian[0].conserves_particle_number(&[2, 2]));
+    }
+
+    #[test]
+    fn test_fermionic_terms()
+    {
+        // fermionic operators anticommute on different modes
+        assert!(hamiltonian("[{ampl: -1, pos: [0, 1], ops: [F+, F-], hc: true}]").is_ok());
+        assert!(hamiltonian("[{ampl: -1, pos: [0, 1], ops: [F+, F-]}, {ampl: -1, pos: [1, 0], ops: [F+, F-]}]").is_ok());
+        assert!(hamiltonian("[{ampl: -1, pos: [0, 1], ops: [F+, F-]}, {ampl: 1, pos: [0, 1], ops: [F-, F+]}]").is_ok());
+        assert!(hamiltonian("[{ampl: -1, pos: [0, 1], ops: [F+, F-]}, {ampl: -1, pos: [0, 1], ops: [F-, F+]}]").is_err());
+        assert!(hamiltonian("[{ampl: 1, pos: [1, 0, 0, 1], ops: [F+, F+, F-, F-]}]").is_ok());
+        assert!(hamiltonian("[{ampl: 1, pos: [0, 1], ops: [F+, F-], hc: true}, {ampl: 1, pos: [0], ops: [A+], hc: true}]").is_err());
+        let task = parse_task!(GroundState, ground_state("[{ampl: 1, pos: [1, 0], ops: [F+, F-], hc: true}, {ampl: 1, pos: [0], ops: [N1]}]"));
+        assert_eq!(task.hamiltonian[1].fermionic_modes, [0, 1]);
+        assert_eq!(task.hamiltonian[1].operator_term(), Term::new(&[0, 0, 1], &[Op::SigmaPlus, Op::Parity, Op::SigmaMinus]));
+    }
+
+    #[test]
+    fn test_evolution_direction()
+    {
+        // states evolve as exp(-iHt), thus <a(t) a^dagger(0)> = exp(-i omega t) for H = omega n
+        let config = "!TwoTimeCorrelators {qubits_per_mode: [2], init_state: [0], total_time_steps_number: 10, \
+            time_step_size: 0.1, hamiltonian: [{ampl: 1.5, pos: [0], ops: [N1]}], \
+            correlators: [[{ampl: 1, pos: [0], ops: [A-]}, {ampl: 1, pos: [0], ops: [A+]}]]}";
+        let (TwoTimeCorrelatorsResult { correlators, .. }, _) = parse_task!(TwoTimeCorrelators, config).run(None, 1e-14);
+        for (step, correlator) in correlators[0].iter().enumerate() {
+            assert!((correlator - Complex64::from_polar(1., -0.15 * step as f64)).norm() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn test_two_time_correlators()
+    {
+        // a single mode H = omega n with one photon, <a(t) a^dagger(0)> = 2 exp(-i omega t),
+        // <a^dagger(t) a(0)> = exp(i omega t) and <n(t) n(0)> = 1
+        let single_mode = parse_task!(TwoTimeCorrelators, "!TwoTimeCorrelators {max_occupation: [3], init_state: [1], total_time_steps_number: 12, \
+            time_step_size: 0.25, hamiltonian: [{ampl: 0.8, pos: [0], ops: [N1]}], \
+            correlators: [[{ampl: 1, pos: [0], ops: [A-]}, {ampl: 1, pos: [0], ops: [A+]}], \
+            [{ampl: 1, pos: [0], ops: [A+]}, {ampl: 1, pos: [0], ops: [A-]}], \
+            [{ampl: 1, pos: [0], ops: [N1]}, {ampl: 1, pos: [0], ops: [N1]}]]}");
+        let (TwoTimeCorrelatorsResult { correlators, diagnostics }, _) = single_mode.run(None, 1e-14);
+        assert_eq!(diagnostics.norms.len(), 13);
+        assert!(diagnostics.norms.iter().all(|norm| (norm - 1.).abs() < 1e-10));
+        assert!(diagnostics.chebyshev_order > 0);
+        assert_eq!(correlators.len(), 3);
+        assert_eq!(correlators[0].len(), 13);
+        for (step, ((lowering, rising), number)) in correlators[0].iter().zip(&correlators[1]).zip(&correlators[2]).enumerate() {
+            let time = 0.25 * step as f64;
+            assert!((lowering - Complex64::from_polar(2., -0.8 * time)).norm() < 1e-10);
+            assert!((rising - Complex64::from_polar(1., 0.8 * time)).norm() < 1e-10);
+            assert!((number - 1.).norm() < 1e-10);
+        }
+        // a hopping dimer H = -J (a_0^dagger a_1 + h.c.) in the vacuum,
+        // <a_0(t) a_0^dagger(0)> = cos(J t) and <a_1(t) a_0^dagger(0)> = i sin(J t)
+        let dimer = parse_task!(TwoTimeCorrelators, "!TwoTimeCorrelators {max_occupation: [1, 1], init_state: [0, 0], total_time_steps_number: 12, \
+            time_step_size: 0.25, hamiltonian: [{ampl: -1.3, pos: [0, 1], ops: [A+, A-], hc: true}], \
+            correlators: [[{ampl: 1, pos: [0], ops: [A-]}, {ampl: 1, pos: [0], ops: [A+]}], \
+            [{ampl: 1, pos: [1], ops: [A-]}, {ampl: 1, pos: [0], ops: [A+]}]]}");
+        let (TwoTimeCorrelatorsResult { correlators, .. }, _) = dimer.run(None, 1e-14);
+        assert_eq!(correlators[0].len(), 13);
+        for (step, (local, hopped)) in correlators[0].iter().zip(&correlators[1]).enumerate() {
+            let time = 0.25 * step as f64;
+            assert!((local - Complex64::new((1.3 * time).cos(), 0.)).norm() < 1e-10);
+            assert!((hopped - Complex64::new(0., (1.3 * time).sin())).norm() < 1e-10);
+        }
+        // `hc` turns a term into a quadrature, <x(t) x(0)> = exp(-i omega t) in the vacuum with x = a + a^dagger
+        let quadrature = |correlators: &str| format!(
+            "!TwoTimeCorrelators {{max_occupation: [2], init_state: [0], total_time_steps_number: 4, \
+            time_step_size: 0.25, hamiltonian: [{{ampl: 0.8, pos: [0], ops: [N1]}}], correlators: {}}}",
+            correlators,
+        );
+        let task = parse_task!(TwoTimeCorrelators, quadrature("[[{ampl: 1, pos: [0], ops: [A-], hc: true}, {ampl: 1, pos: [0], ops: [A-], hc: true}]]"));
+        assert_eq!(task.correlators[0].0.len(), 2);
+        let (TwoTimeCorrelatorsResult { correlators, .. }, _) = task.run(None, 1e-14);
+        for (step, value) in correlators[0].iter().enumerate() {
+            assert!((value - Complex64::from_polar(1., -0.8 * 0.25 * step as f64)).norm() < 1e-10);
+        }
+        // both operators of a pair are checked when parsing
+        assert!(parse(&quadrature("[[{ampl: 1, pos: [0], ops: [A-]}, {ampl: 1, pos: [0, 1], ops: [A+]}]]"))
+            .unwrap_err()
+            .contains("equal non-zero numbers"));
+    }
+
+    #[test]
+    fn test_imaginary_time_dynamics()
+    {
+        // a Fock state is an eigenstate of H = omega n, a step multiplies it by exp(-dt (omega - c))
+        let task = parse_task!(ImaginaryTimeDynamics, "!ImaginaryTimeDynamics {max_occupation: [2], init_state: [1], \
+            total_time_steps_number: 3, time_step_size: 0.5, hamiltonian: [{ampl: 0.8, pos: [0], ops: [N1]}], \
+            density_matrices: [[0]], spectral_bounds: !Fixed [0, 2]}");
+        let (result, _) = task.run(None, 1e-14, 1e-8);
+        assert_eq!(result.diagnostics.norms.len(), 4);
+        assert!((result.diagnostics.norms[0] - 1.).abs() < 1e-10);
+        assert!(result.diagnostics.norms[1..].iter().all(|norm| (norm - 0.1f64.exp()).abs() < 1e-10));
+        assert!(result.diagnostics.chebyshev_order > 0);
+    }
+
+    #[test]
+    fn test_kernel_polynomial()
+    {
+        let kpm = |local_operators: &str| format!(
+            "!KernelPolynomial {{max_occupation: [3], init_state: [1], moments_number: 8, random_vectors_number: 2, \
+            energy_points_number: 16, hamiltonian: [{{ampl: 0.8, pos: [0], ops: [N1]}}], seed: 3, local_operators: {}}}",
+            local_operators,
+        );
+        // the zeroth moments are normalized by the dimension and by |O|psi>|^2, where x|1> = |0> + sqrt(2)|2>
+        // for the quadrature x = a + a^dagger
+        let task = parse_task!(KernelPolynomial, kpm("[{ampl: 1, pos: [0], ops: [A-]}, {ampl: 1, pos: [0], ops: [A-], hc: true}]"));
+        let (result, _) = task.run(None);
+        assert!((result.moments[0] - 1.).abs() < 1e-10);
+        assert!((result.local_moments[0][0] - 1.).abs() < 1e-10);
+        assert!((result.local_moments[1][0] - 3.).abs() < 1e-10);
+        // local operators are checked when parsing
+        assert!(parse(&kpm("[{ampl: 1, pos: [0], ops: [A-, A+]}]")).unwrap_err().contains("equal non-zero numbers"));
+    }
+
+    #[test]
+    fn test_lindblad_dynamics()
+    {
+        // photon loss, <n>(t) = n0 exp(-gamma t), gamma dt = 2 requires sub-steps
+        let loss = parse_task!(LindbladDynamics, "!LindbladDynamics {max_occupation: [3], init_state: [3], total_time_steps_number: 8, \
+            time_step_size: 0.5, hamiltonian: [{ampl: 0.7, pos: [0], ops: [N1]}], \
+            jump_operators: [{ampl: 4, pos: [0], ops: [A-]}], density_matrices: [[0]]}");
+        let (result, _) = loss.run(None, 1e-14, 1e-8);
+        for (step, dens) in result.density_matrices[0].iter().enumerate() {
+            let occupation = (0..4).map(|n| n as f64 * dens[5 * n].re).sum::<f64>();
+            assert!((occupation - 3. * (-2. * step as f64).exp()).abs() < 1e-10);
+        }
+        assert!(result.diagnostics.norms.iter().all(|norm| (norm - 1.).abs() < 1e-10));
+        // dephasing of a superposition, |rho_01|(t) = exp(-gamma t / 2) / 2 while populations are conserved
+        let dephasing = parse_task!(LindbladDynamics, "!LindbladDynamics {qubits_per_mode: [1], init_state: [0], total_time_steps_number: 10, \
+            time_step_size: 0.3, hamiltonian: [{ampl: 1.5, pos: [0], ops: [N1]}], \
+            jump_operators: [{ampl: 0.8, pos: [0], ops: [N1]}], density_matrices: [[0]]}");
+        let state = vec![Complex64::new(0.5f64.sqrt(), 0.); 2];
+        let (result, _) = dephasing.run(Some(&StageState::Pure(state)), 1e-14, 1e-8);
+        for (step, dens) in result.density_matrices[0].iter().enumerate() {
+            assert!((dens[0].re - 0.5).abs() < 1e-10);
+            assert!((dens[3].re - 0.5).abs() < 1e-10);
+            assert!((dens[1].norm() - 0.5 * (-0.4 * 0.3 * step as f64).exp()).abs() < 1e-10);
+        }
+        // jump operators are checked and expanded by `hc` as Hamiltonian terms, but need not be Hermitian
+        let jumps = |jump_operators: &str| format!(
+            "!LindbladDynamics {{max_occupation: [1, 1], init_state: [1, 0], total_time_steps_number: 1, \
+            time_step_size: 0.1, hamiltonian: [], density_matrices: [], jump_operators: {}}}",
+            jump_operators,
+        );
+        let task = parse_task!(LindbladDynamics, jumps("[{ampl: 0.5, pos: [0, 1], ops: [A+, A-], hc: true}]"));
+        assert_eq!(task.jump_operators.len(), 2);
+        assert_eq!(task.jump_operators[1].term(), Term::new(&[0, 1], &[Op::Lowering, Op::Rising]));
+        assert!(parse(&jumps("[{ampl: 0.5, pos: [0, 1], ops: [A-]}]")).unwrap_err().contains("equal non-zero numbers"));
+    }
+
+    #[test]
+    fn test_quantum_trajectories()
+    {
+        let config = |task: &str, extra: &str| format!(
+            "!{} {{max_occupation: [1, 1], init_state: [1, 0], total_time_steps_number: 4, time_step_size: 0.25, \
+            hamiltonian: [{{ampl: -1, pos: [0, 1], ops: [A+, A-], hc: true}}], \
+            jump_operators: [{{ampl: 0.3, pos: [0], ops: [A-]}}, {{ampl: 3, pos: [1], ops: [N1]}}], \
+            density_matrices: [[0], [1]]{}}}",
+            task, extra,
+        );
+        let (expected, _) = parse_task!(LindbladDynamics, config("LindbladDynamics", "")).run(None, 1e-14, 1e-8);
+        let mut trajectories = parse_task!(QuantumTrajectories, config("QuantumTrajectories", ", trajectories_number: 200, seed: 7"));
+        let stream = std::env::temp_dir().join(format!("trajectories_stream_test_{}.pkl", std::process::id()));
+        let stream = stream.to_str().unwrap();
+        Stream::create(stream).unwrap();
+        trajectories.stream = Some(Stream::new(stream, None));
+        let result = trajectories.run(None, 1e-14, 1e-8);
+        assert_eq!(result.jumps_numbers.len(), 200);
+        assert_eq!(result.diagnostics.norms.len(), 5);
+        assert!(result.diagnostics.norms.iter().all(|norm| *norm > 0. && *norm < 1. + 1e-10));
+        assert!(result.jumps_numbers.iter().sum::<usize>() > 0);
+        // averaged observables of every time step are streamed
+        let file = std::fs::File::open(stream).unwrap();
+        let mut deserializer = serde_pickle::Deserializer::new(file, Default::default());
+        let records: Vec<serde_pickle::Value> = (0..5).map(|_| Deserialize::deserialize(&mut deserializer).unwrap()).collect();
+        deserializer.end().unwrap();
+        let serde_pickle::Value::Dict(last) = &records[4] else { panic!("Unexpected record") };
+        assert_eq!(last.get(&serde_pickle::HashableValue::String("time".to_owned())), Some(&serde_pickle::Value::F64(1.)));
+        std::fs::remove_file(stream).unwrap();
+        let values = result.density_matrices.iter().flatten().flatten();
+        let errors = result.density_matrices_errors.iter().flatten().flatten();
+        let expected = expected.density_matrices.iter().flatten().flatten();
+        for ((value, error), expected) in values.zip(errors).zip(expected) {
+            assert!((value.re - expected.re).abs() < 4. * error.re + 1e-10);
+            assert!((value.im - expected.im).abs() < 4. * error.im + 1e-10);
+        }
+        // jump times do not depend on the time step, thus a trajectory of a driven lossy mode
+        // does not change with the time step
+        let driven = |steps: usize, time_step_size: f64| parse_task!(QuantumTrajectories, format!(
+            "!QuantumTrajectories {{max_occupation: [1], init_state: [1], total_time_steps_number: {}, time_step_size: {}, \
+            hamiltonian: [{{ampl: 1, pos: [0], ops: [A+], hc: true}}], jump_operators: [{{ampl: 1, pos: [0], ops: [A-]}}], \
+            density_matrices: [[0]], trajectories_number: 1}}",
+            steps, time_step_size,
+        ));
+        let init_state = [Complex64::new(0., 0.), Complex64::new(1., 0.)];
+        for seed in [1, 2, 3] {
+            let coarse = driven(8, 0.5).run_trajectory(&init_state, seed, 1e-14, exp_order(1e-14));
+            let fine = driven(40, 0.1).run_trajectory(&init_state, seed, 1e-14, exp_order(1e-14));
+            assert_eq!(coarse.jumps_number, fine.jumps_number);
+            for (lhs, rhs) in coarse.density_matrices[0].iter().zip(fine.density_matrices[0].iter().step_by(5)) {
+                assert!((lhs[3] - rhs[3]).norm() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_leakage_policy()
+    {
+        // a qubit in the excited state is not leakage, a bosonic mode in its highest level is
+        let task = |init_state: &str| parse_task!(ChebyshevDynamics, format!(
+            "!ChebyshevDynamics {{max_occupation: [1, 1], init_state: {}, total_time_steps_number: 2, time_step_size: 0.1, \
+            density_matrices: [], leakage_policy: {{threshold: 0.5, action: Abort}}, \
+            hamiltonian: [{{ampl: 0.5, pos: [0], ops: [N1]}}, {{ampl: 0.7, pos: [1], ops: [Z]}}]}}",
+            init_state,
+        ));
+        let (result, _) = task("[0, 1]").run(None, 1e-14, 1e-8);
+        assert!(result.top_level_populations[1].iter().all(|population| (population - 1.).abs() < 1e-10));
+        let leaked = std::panic::catch_unwind(|| task("[1, 1]").run(None, 1e-14, 1e-8));
+        assert!(leaked.is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_resume()
+    {
+        let dir = std::env::temp_dir();
+        let checkpoint = dir.join(format!("resume_test_{}.pkl", std::process::id()));
+        let checkpoint = checkpoint.to_str().unwrap();
+        let task = |steps: usize, init_state: &str| {
+            let config = format!(
+                "!ChebyshevDynamics {{max_occupation: [2, 2, 2], init_state: {}, total_time_steps_number: {}, \
+                time_step_size: 0.2, density_matrices: [[0], [1, 2]], conserve_particle_number: true, \
+                checkpoint: {{path: {}, every: 3}}, \
+                hamiltonian: [{{ampl: -1, pos: [0, 1], ops: [A+, A-], hc: true}}, {{ampl: -1, pos: [1, 2], ops: [A+, A-], hc: true}}, \
+                {{ampl: 0.5, pos: [1], ops: [N2]}}]}}",
+                init_state, steps, checkpoint,
+            );
+            parse(&config).unwrap()
+        };
+        let (TaskResult::ChebyshevDynamics(full), _) = task(10, "[2, 0, 1]").run(None, 1e-14, 1e-8) else { panic!("Unexpected result") };
+        task(4, "[2, 0, 1]").run(None, 1e-14, 1e-8);
+        let mut resumed = task(10, "[2, 0, 1]");
+        resumed.resume_from(checkpoint).unwrap();
+        let (TaskResult::ChebyshevDynamics(result), _) = resumed.run(None, 1e-14, 1e-8) else { panic!("Unexpected result") };
+        assert_eq!(result.density_matrices.len(), 2);
+        assert_eq!(result.density_matrices[1].len(), 11);
+        for (lhs, rhs) in result.density_matrices.iter().flatten().flatten().zip(full.density_matrices.iter().flatten().flatten()) {
+            assert!((lhs - rhs).norm() < 1e-10);
+        }
+        assert_eq!(result.diagnostics.norms.len(), 11);
+        assert!(result.diagnostics.norms.iter().all(|norm| (norm - 1.).abs() < 1e-10));
+        assert_eq!(result.diagnostics.chebyshev_order, full.diagnostics.chebyshev_order);
+        let metadata = resumed.metadata(&TaskResult::ChebyshevDynamics(result), None);
+        assert_eq!(metadata.density_matrices, [vec![0], vec![1, 2]]);
+        assert_eq!(metadata.times.len(), 11);
+        assert!((metadata.times[10] - 2.).abs() < 1e-12);
+        assert!(metadata.trace_deviations.iter().all(|deviation| *deviation < 1e-10));
+        // the state of the checkpoint is the initial state of another task
+        let (TaskResult::ChebyshevDynamics(continued), _) = task(0, &format!("\"{}\"", checkpoint)).run(None, 1e-14, 1e-8) else {
+            panic!("Unexpected result")
+        };
+        for (lhs, rhs) in continued.density_matrices[1][0].iter().zip(&full.density_matrices[1][10]) {
+            assert!((lhs - rhs).norm() < 1e-10);
+        }
+        let mut other = task(10, "[1, 1, 1]");
+        other.resume_from(checkpoint).unwrap();
+        assert!(std::panic::catch_unwind(|| other.run(None, 1e-14, 1e-8)).is_err());
+        std::fs::remove_file(checkpoint).unwrap();
+        let config = "!ChebyshevDynamics {max_occupation: [1], init_state: [1], total_time_steps_number: 2, time_step_size: 0.1, \
+            density_matrices: [], checkpoint: {path: never.pkl, every: 0}, hamiltonian: [{ampl: 1, pos: [0], ops: [N1]}]}";
+        let error = parse(config).unwrap_err();
+        assert!(error.contains("every positive number of time steps"));
+    }
+}
@@ -1,7 +1,8 @@
 use ndarray::{Array2, ArrayView};
 use ndarray_einsum_beta::{einsum, ArrayLike};
+use num_traits::{Zero, One};
 use crate::subroutines_utils::{
-    Term, Op, Value,
+    Term, Op, Value, TrueComplex,
 };
 
 fn get_rising_op<T: Value>(dim: usize) -> Array2<T>
@@ -40,57 +41,82 @@ fn get_nsq_op<T: Value>(dim: usize) -> Array2<T>
     lowering
 }
 
-pub(super) fn apply_term_test<const N: usize, T: Value>(
+fn get_pauli_op<T: Value + TrueComplex>(op_type: Op) -> Array2<T>
+{
+    let (zero, one) = (T::Real::zero(), T::Real::one());
+    let (upper, lower) = match op_type {
+        Op::SigmaPlus => (T::zero(), T::one()),
+        Op::SigmaMinus => (T::one(), T::zero()),
+        Op::X => (T::one(), T::one()),
+        Op::Y => (T::new(zero, one), T::new(zero, -one)),
+        Op::MinusY => (T::new(zero, -one), T::new(zero, one)),
+        Op::Z => return Array2::from_shape_vec((2, 2), vec![-T::one(), T::zero(), T::zero(), T::one()]).unwrap(),
+        _ => unreachable!(),
+    };
+    Array2::from_shape_vec((2, 2), vec![T::zero(), upper, lower, T::zero()]).unwrap()
+}
+
+fn get_op<T: Value + TrueComplex>(op_type: Op, dim: usize) -> Array2<T>
+{
+    match op_type {
+        Op::Rising => get_rising_op::<T>(dim),
+        Op::Lowering => get_lowering_op::<T>(dim),
+        Op::N => get_n_op::<T>(dim),
+        Op::N2 => get_nsq_op::<T>(dim),
+        Op::Parity => Array2::from_diag(&(0..dim).map(|i| if i % 2 == 0 { T::one() } else { -T::one() }).collect::<ndarray::Array1<T>>()),
+        _ => {
+            assert_eq!(dim, 2);
+            get_pauli_op::<T>(op_type)
+        },
+    }
+}
+
+pub(super) fn apply_term_test<T: Value + TrueComplex>(
     dst: &mut [T],
     src: &[T],
-    term: &Term<N>,
-    all_encodings: &[usize],
+    term: &Term,
+    local_dims: &[usize],
     delta: T,
 )
 {
-    // ------------------ C-layout to Fortran-layout --------------------------------------
-    let all_encodings: Vec<_> = all_encodings.into_iter().rev().map(|x| *x).collect();
-    let particles_number = all_encodings.len();
-    let mut term = term.clone();
-    term.op_types.reverse();
-    term.positions.reverse();
-    for pos in &mut term.positions {
-        *pos = particles_number - *pos - 1;
+    // ------------------ Products of operators acting on the same mode -------------------
+    let mut modes: Vec<usize> = Vec::new();
+    let mut local_operators: Vec<Array2<T>> = Vec::new();
+    for (pos, op_type) in term.positions.iter().zip(&term.op_types)
+    {
+        let op = get_op::<T>(*op_type, local_dims[*pos]);
+        match modes.iter().position(|mode| mode == pos) {
+            Some(i) => local_operators[i] = local_operators[i].dot(&op),
+            None => {
+                modes.push(*pos);
+                local_operators.push(op);
+            },
+        }
     }
+    let n = modes.len();
+    // ------------------ C-layout to Fortran-layout --------------------------------------
+    let local_dims: Vec<_> = local_dims.into_iter().rev().map(|x| *x).collect();
+    let particles_number = local_dims.len();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by_key(|i| std::cmp::Reverse(modes[*i]));
     // ------------------- Slices to Arrays -----------------------------------------------
     let mut shape = Vec::new();
     let mut operators = Vec::new();
     let mut start = 0;
-    for (pos, op_type) in term.positions.iter().zip(term.op_types)
+    for i in order
     {
-        let dim = 2usize.pow((&all_encodings[start..*pos]).iter().map(|x| *x).sum::<usize>() as u32);
+        let pos = particles_number - modes[i] - 1;
+        let dim = local_dims[start..pos].iter().product::<usize>();
         shape.push(dim);
-        let dim = 2usize.pow(all_encodings[*pos] as u32);
+        let dim = local_dims[pos];
         shape.push(dim);
         start = pos + 1;
-        match op_type {
-            Op::Rising => {
-                let op = get_rising_op::<T>(2usize.pow(all_encodings[*pos] as u32));
-                operators.push(op);
-            },
-            Op::Lowering => {
-                let op = get_lowering_op::<T>(2usize.pow(all_encodings[*pos] as u32));
-                operators.push(op);
-            },
-            Op::N => {
-                let op = get_n_op::<T>(2usize.pow(all_encodings[*pos] as u32));
-                operators.push(op);
-            },
-            Op::N2 => {
-                let op = get_nsq_op::<T>(2usize.pow(all_encodings[*pos] as u32));
-                operators.push(op);
-            },
-        }
+        operators.push(local_operators[i].clone());
     }
-    if start == all_encodings.len() {
+    if start == local_dims.len() {
         shape.push(1);
     } else {
-        let dim = 2usize.pow((&all_encodings[start..]).iter().map(|x| *x).sum::<usize>() as u32);
+        let dim = local_dims[start..].iter().product::<usize>();
         shape.push(dim);
     }
     let src = ArrayView::from_shape(shape.clone(), src).unwrap();
@@ -100,24 +126,24 @@ pub(super) fn apply_term_test<const N: usize, T: Value>(
     for op in &operators {
         operands.push(op);
     }
-    let mut free_state_indices_iter = (97u8..(97u8 + (2 * N as u8) + 1u8))
+    let mut free_state_indices_iter = (97u8..(97u8 + (2 * n as u8) + 1u8))
         .filter(|x| x % 2 != 0 )
         .map(|x| x as char);
-    let mut free_ops_indices_iter = (97u8..(97u8 + (2 * N as u8) + 1u8))
+    let mut free_ops_indices_iter = (97u8..(97u8 + (2 * n as u8) + 1u8))
         .filter(|x| x % 2 == 0 )
         .map(|x| x as char);
-    let mut closed_indices_iter = ((122u8 - (N as u8) + 1u8)..=122u8).map(|x| x as char);
-    let final_indices_iter = (97u8..(97u8 + (2 * N as u8) + 1u8))
+    let mut closed_indices_iter = ((122u8 - (n as u8) + 1u8)..=122u8).map(|x| x as char);
+    let final_indices_iter = (97u8..(97u8 + (2 * n as u8) + 1u8))
         .map(|x| x as char);
     let mut einsum_string = String::new();
     let mut closed_indices_iter_clone = closed_indices_iter.clone();
     einsum_string.push(free_state_indices_iter.next().unwrap());
-    for _ in 0..N {
+    for _ in 0..n {
         einsum_string.push(closed_indices_iter_clone.next().unwrap());
         einsum_string.push(free_state_indices_iter.next().unwrap());
     }
     einsum_string.push(',');
-    for _ in 0..N {
+    for _ in 0..n {
         einsum_string.push(free_ops_indices_iter.next().unwrap());
         einsum_string.push(closed_indices_iter.next().unwrap());
         einsum_string.push(',');
@@ -134,16 +160,17 @@ pub(super) fn apply_term_test<const N: usize, T: Value>(
     }
 }
 
-pub(super) fn get_density_test<const N: usize, T: Value>(
+pub(super) fn get_density_test<T: Value>(
     src: &[T],
-    positions: &[usize; N],
-    all_encodings: &[usize],
+    positions: &[usize],
+    local_dims: &[usize],
 ) -> Vec<T>
 {
+    let n = positions.len();
     // ------------------ C-layout to Fortran-layout --------------------------------------
-    let all_encodings: Vec<_> = all_encodings.into_iter().rev().map(|x| *x).collect();
-    let particles_number = all_encodings.len();
-    let mut positions = positions.clone();
+    let local_dims: Vec<_> = local_dims.into_iter().rev().map(|x| *x).collect();
+    let particles_number = local_dims.len();
+    let mut positions = positions.to_owned();
     positions.reverse();
     for pos in &mut positions {
         *pos = particles_number - *pos - 1;
@@ -153,16 +180,16 @@ pub(super) fn get_density_test<const N: usize, T: Value>(
     let mut start = 0;
     for pos in positions.iter()
     {
-        let dim = 2usize.pow((&all_encodings[start..*pos]).iter().map(|x| *x).sum::<usize>() as u32);
+        let dim = local_dims[start..*pos].iter().product::<usize>();
         shape.push(dim);
-        let dim = 2usize.pow(all_encodings[*pos] as u32);
+        let dim = local_dims[*pos];
         shape.push(dim);
         start = pos + 1;
     }
-    if start == all_encodings.len() {
+    if start == local_dims.len() {
         shape.push(1);
     } else {
-        let dim = 2usize.pow((&all_encodings[start..]).iter().map(|x| *x).sum::<usize>() as u32);
+        let dim = local_dims[start..].iter().product::<usize>();
         shape.push(dim);
     }
     let src = ArrayView::from_shape(shape.clone(), src).unwrap();
@@ -172,32 +199,32 @@ pub(super) fn get_density_test<const N: usize, T: Value>(
     let mut operands: Vec<&dyn ArrayLike<T>> = Vec::new();
     operands.push(&src_conj);
     operands.push(&src);
-    let mut first_operand_free_indices_iter = (97u8..(97u8 + N as u8)).map(|x| x as char);
-    let mut second_operand_free_indices_iter = ((97u8 + N as u8)..=(97u8 + 2 * N as u8)).map(|x| x as char);
-    let mut closed_indices_iter = ((122u8 - (N as u8))..=122u8).map(|x| x as char);
+    let mut first_operand_free_indices_iter = (97u8..(97u8 + n as u8)).map(|x| x as char);
+    let mut second_operand_free_indices_iter = ((97u8 + n as u8)..=(97u8 + 2 * n as u8)).map(|x| x as char);
+    let mut closed_indices_iter = ((122u8 - (n as u8))..=122u8).map(|x| x as char);
     let mut einsum_string = String::new();
     let mut closed_indices_iter_clone = closed_indices_iter.clone();
     let mut first_operand_free_indices_iter_clone = first_operand_free_indices_iter.clone();
     let mut second_operand_free_indices_iter_clone = second_operand_free_indices_iter.clone();
-    for _ in 0..N
+    for _ in 0..n
     {
         einsum_string.push(closed_indices_iter_clone.next().unwrap());
         einsum_string.push(first_operand_free_indices_iter_clone.next().unwrap());
     }
     einsum_string.push(closed_indices_iter_clone.next().unwrap());
     einsum_string.push(',');
-    for _ in 0..N
+    for _ in 0..n
     {
         einsum_string.push(closed_indices_iter.next().unwrap());
         einsum_string.push(second_operand_free_indices_iter_clone.next().unwrap());
     }
     einsum_string.push(closed_indices_iter.next().unwrap());
     einsum_string.push_str("->");
-    for _ in 0..N
+    for _ in 0..n
     {
         einsum_string.push(first_operand_free_indices_iter.next().unwrap());
     }
-    for _ in 0..N
+    for _ in 0..n
     {
         einsum_string.push(second_operand_free_indices_iter.next().unwrap());
     }
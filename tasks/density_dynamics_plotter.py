@@ -13,19 +13,29 @@ def main() -> None:
     )
     parser.add_argument(
         "--path", "-p",
-        help="Path to the result  *.pickle file",
+        help="Path to the result *.pickle or *.npz file",
         default=os.getcwd(),
     )
     args = parser.parse_args()
     dirname = os.path.dirname(args.path)
-    with open(args.path, 'rb') as f:
-        result = pickle.load(f)
+    bosons = []
+    if args.path.endswith(".npz"):
+        result = np.load(args.path)
+        i = 0
+        while f"density_matrix_{i}" in result:
+            bosons.append(result[f"density_matrix_{i}"])
+            i += 1
+    else:
+        with open(args.path, 'rb') as f:
+            result = pickle.load(f)["result"]
+        for boson in result["density_matrices"]:
+            boson = np.array(boson).astype(np.complex128)
+            boson = boson[..., 0] + 1j * boson[..., 1]
+            dim = int(sqrt(boson.shape[-1]))
+            bosons.append(boson.reshape((boson.shape[0], dim, dim)))
     densities = []
-    for boson in result:
-        boson = np.array(boson).astype(np.complex128)
-        boson = boson[..., 0] + 1j * boson[..., 1]
-        dim = int(sqrt(boson.shape[-1]))
-        boson = boson.reshape((boson.shape[0], dim, dim))
+    for boson in bosons:
+        dim = boson.shape[-1]
         boson = boson * np.arange(dim).reshape((1, 1, -1))
         boson = np.trace(boson, axis1=1, axis2=2).real
         densities.append(boson)